@@ -0,0 +1,493 @@
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::extractors::ByteRange;
+use crate::file_data::FileData;
+use crate::hashing::chunk_ranges;
+use crate::storage::{Storage, StorageRef};
+use crate::utils::constants::*;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use dataplatform_archive::zip::zip_entries;
+use dataplatform_archive::RangeRead;
+use flate2::write::MultiGzDecoder;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+const TAR_BLOCK_SIZE: u64 = 512;
+const TAR_MAX_EXTENDED_SIZE: u64 = 1024 * 1024; // gnu long names and pax headers
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// from the extension of the key, compressed tars as `.tar.gz` or `.tgz`
+    pub fn from_key(key: &str) -> Option<Self> {
+        let key = key.to_lowercase();
+        if key.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if key.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if key.ends_with(".tar.gz") || key.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// file inside an archive, directories and links are not listed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveMember {
+    pub path: String,
+    pub size: i64,
+}
+
+/// ranged reads of an object of the storage
+struct StorageRange<'a> {
+    storage: &'a dyn Storage,
+    key: &'a str,
+    file_size: u64,
+}
+
+impl RangeRead for StorageRange<'_> {
+    type Error = anyhow::Error;
+
+    async fn read_tail(&self, len: u64) -> Result<(Bytes, u64)> {
+        let tail = self.storage.get_range(self.key, ByteRange::Tail(self.file_size.min(len))).await?;
+        Ok((tail, self.file_size))
+    }
+
+    async fn read_range(&self, start: u64, end: u64) -> Result<Bytes> {
+        self.storage.get_range(self.key, ByteRange::Span(start, end)).await
+    }
+}
+
+/// files of the central directory, read with two or three ranged requests
+async fn zip_members(storage: &dyn Storage, key: &str, file_size: u64) -> Result<Vec<ArchiveMember>> {
+    let reader = StorageRange { storage, key, file_size };
+    let members = zip_entries(&reader)
+        .await?
+        .into_iter()
+        .map(|x| ArchiveMember {
+            path: x.path,
+            size: x.size as i64,
+        })
+        .collect();
+    Ok(members)
+}
+
+/// tar data is padded to whole blocks
+fn padded(size: u64) -> u64 {
+    size.div_ceil(TAR_BLOCK_SIZE) * TAR_BLOCK_SIZE
+}
+
+/// null-terminated field of a tar header
+fn tar_str(field: &[u8]) -> String {
+    let end = field.iter().position(|x| *x == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+/// octal number, or big-endian base-256 if the high bit is set
+fn tar_number(field: &[u8]) -> Result<u64> {
+    if field.first().is_some_and(|x| x & 0x80 != 0) {
+        return Ok(field[1..].iter().fold(0, |n, x| (n << 8) | *x as u64));
+    }
+    let text = tar_str(field);
+    let text = text.trim_matches(|x: char| x == ' ' || x == '\0');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| anyhow!("invalid tar number: {text}"))
+}
+
+/// members of a tar stream fed in order, file data is skipped without being kept,
+/// so callers reading ranges may jump over it with `take_skip`
+#[derive(Debug, Default)]
+pub struct TarScanner {
+    /// partial header block or extended header data
+    buf: Vec<u8>,
+    /// bytes of file data and padding before the next header
+    skip: u64,
+    /// typeflag and size of the gnu long name or pax data being read
+    extended: Option<(u8, u64)>,
+    long_name: Option<String>,
+    pax_size: Option<u64>,
+    members: Vec<ArchiveMember>,
+    done: bool,
+}
+
+impl TarScanner {
+    pub fn feed(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() && !self.done {
+            if self.skip > 0 {
+                let n = self.skip.min(data.len() as u64) as usize;
+                self.skip -= n as u64;
+                data = &data[n..];
+                continue;
+            }
+            let need = match self.extended {
+                Some((_, size)) => padded(size),
+                None => TAR_BLOCK_SIZE,
+            } as usize;
+            let n = (need - self.buf.len()).min(data.len());
+            self.buf.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.buf.len() == need {
+                let buf = std::mem::take(&mut self.buf);
+                match self.extended.take() {
+                    Some((flag, size)) => self.extended_header(flag, &buf[..size as usize]),
+                    None => self.header(&buf)?,
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn header(&mut self, block: &[u8]) -> Result<()> {
+        if block.iter().all(|x| *x == 0) {
+            self.done = true;
+            return Ok(());
+        }
+        let checksum = tar_number(&block[148..156])?;
+        let sum = block
+            .iter()
+            .enumerate()
+            .map(|(i, x)| if (148..156).contains(&i) { b' ' as u64 } else { *x as u64 })
+            .sum::<u64>();
+        if sum != checksum {
+            return Err(anyhow!("invalid tar header checksum"));
+        }
+        let size = tar_number(&block[124..136])?;
+        let mut name = tar_str(&block[..100]);
+        let prefix = tar_str(&block[345..500]);
+        if &block[257..262] == b"ustar" && !prefix.is_empty() {
+            name = format!("{prefix}/{name}");
+        }
+        match block[156] {
+            flag @ (b'L' | b'x') => {
+                if size > TAR_MAX_EXTENDED_SIZE {
+                    return Err(anyhow!("tar extended header of: {size} bytes"));
+                }
+                self.extended = Some((flag, size));
+            }
+            // global pax headers and long link names
+            b'g' | b'K' => self.skip = padded(size),
+            b'0' | b'7' | 0 => {
+                let size = self.pax_size.take().unwrap_or(size);
+                let path = self.long_name.take().unwrap_or(name);
+                self.skip = padded(size);
+                if !path.ends_with('/') {
+                    self.members.push(ArchiveMember {
+                        path,
+                        size: size as i64,
+                    });
+                }
+            }
+            _ => {
+                self.long_name = None;
+                self.pax_size = None;
+                self.skip = padded(size);
+            }
+        }
+        Ok(())
+    }
+
+    /// pax records are `{length} {key}={value}\n`
+    fn extended_header(&mut self, flag: u8, data: &[u8]) {
+        if flag == b'L' {
+            self.long_name = Some(tar_str(data));
+            return;
+        }
+        for record in String::from_utf8_lossy(data).lines() {
+            let Some((_, field)) = record.split_once(' ') else {
+                continue;
+            };
+            match field.split_once('=') {
+                Some(("path", path)) => self.long_name = Some(path.to_string()),
+                Some(("size", size)) => self.pax_size = size.parse().ok(),
+                _ => (),
+            }
+        }
+    }
+
+    /// data left to skip, for callers reading ranges that continue after it
+    pub fn take_skip(&mut self) -> u64 {
+        std::mem::take(&mut self.skip)
+    }
+
+    /// the end of archive block was read
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub fn finish(self) -> Vec<ArchiveMember> {
+        self.members
+    }
+}
+
+/// decompressed gzip data is fed to the scanner
+impl Write for TarScanner {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.feed(buf).map_err(std::io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// reads the headers with ranged requests, jumping over the data of large members
+async fn tar_members(storage: &dyn Storage, key: &str, file_size: u64) -> Result<Vec<ArchiveMember>> {
+    let mut scanner = TarScanner::default();
+    let mut offset = 0;
+    while offset < file_size && !scanner.is_done() {
+        let end = (offset + ARCHIVE_CHUNK_SIZE).min(file_size) - 1;
+        let chunk = storage.get_range(key, ByteRange::Span(offset, end)).await?;
+        if chunk.is_empty() {
+            break;
+        }
+        scanner.feed(&chunk)?;
+        offset += chunk.len() as u64 + scanner.take_skip();
+    }
+    Ok(scanner.finish())
+}
+
+/// gzip has no index, the whole object is read until the end of the archive
+async fn tar_gz_members(storage: &dyn Storage, key: &str, file_size: u64) -> Result<Vec<ArchiveMember>> {
+    if file_size > ARCHIVE_MAX_GZIP_SIZE {
+        return Err(anyhow!("compressed tar: {key} of: {file_size} bytes is over the limit: {ARCHIVE_MAX_GZIP_SIZE}"));
+    }
+    let mut decoder = MultiGzDecoder::new(TarScanner::default());
+    let mut chunks = stream::iter(chunk_ranges(file_size, ARCHIVE_CHUNK_SIZE))
+        .map(|range| storage.get_range(key, range))
+        .buffered(ARCHIVE_CHUNK_WORKERS);
+    while let Some(chunk) = chunks.try_next().await? {
+        decoder.write_all(&chunk)?;
+        if decoder.get_ref().is_done() {
+            break;
+        }
+    }
+    if !decoder.get_ref().is_done() {
+        decoder.try_finish()?;
+    }
+    Ok(std::mem::take(decoder.get_mut()).finish())
+}
+
+pub async fn archive_members(
+    storage: &dyn Storage,
+    key: &str,
+    kind: ArchiveKind,
+    file_size: u64,
+) -> Result<Vec<ArchiveMember>> {
+    match kind {
+        ArchiveKind::Zip => zip_members(storage, key, file_size).await,
+        ArchiveKind::Tar => tar_members(storage, key, file_size).await,
+        ArchiveKind::TarGz => tar_gz_members(storage, key, file_size).await,
+    }
+}
+
+/// adds a row per member of the zip and tar archives among the records,
+/// archives failing to read are logged and skipped, returns their number
+pub async fn add_members(storage: StorageRef, records: &mut Vec<FileData>) -> Result<usize> {
+    let sem = Arc::new(Semaphore::new(ARCHIVE_WORKERS));
+    let mut tasks = JoinSet::new();
+    for (i, record) in records.iter().enumerate() {
+        let (Some(key), Some(file_size)) = (&record.file_path, record.file_size) else {
+            continue;
+        };
+        let Some(kind) = ArchiveKind::from_key(key) else {
+            continue;
+        };
+        if file_size <= 0 || !record.is_current() || record.member_path.is_some() {
+            continue;
+        }
+        let permit = sem.clone().acquire_owned().await?;
+        let storage = storage.clone();
+        let key = key.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            let res = archive_members(storage.as_ref(), &key, kind, file_size as u64).await;
+            (i, key, res)
+        });
+    }
+
+    let mut errors = 0;
+    let mut members = vec![];
+    while let Some(task) = tasks.join_next().await {
+        match task? {
+            (i, _, Ok(found)) => members.extend(found.iter().map(|x| records[i].member(x))),
+            (_, key, Err(e)) => {
+                tracing::warn!("failed to read members of archive: {key}: {e:?}");
+                errors += 1;
+            }
+        }
+    }
+    records.extend(members);
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::storage::ObjectStoreStorage;
+    use crate::utils::aws::ObjectInfo;
+
+    use dataplatform_archive::zip::{CENTRAL_HEADER_SIGNATURE, EOCD_SIGNATURE, LOCAL_HEADER_SIGNATURE};
+    use flate2::write::GzEncoder;
+    use rstest::rstest;
+
+    /// stored zip with the given files, as written by `zip -0`
+    fn zip_file(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut data = vec![];
+        let mut directory = vec![];
+        for (name, body) in files {
+            let offset = data.len() as u32;
+            let header = |signature: u32, central: bool| {
+                let mut out = signature.to_le_bytes().to_vec();
+                if central {
+                    out.extend(20u16.to_le_bytes());
+                }
+                out.extend([20, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+                out.extend((body.len() as u32).to_le_bytes());
+                out.extend((body.len() as u32).to_le_bytes());
+                out.extend((name.len() as u16).to_le_bytes());
+                out.extend(0u16.to_le_bytes());
+                if central {
+                    out.extend([0; 10]);
+                    out.extend(offset.to_le_bytes());
+                }
+                out.extend(name.as_bytes());
+                out
+            };
+            directory.extend(header(CENTRAL_HEADER_SIGNATURE, true));
+            data.extend(header(LOCAL_HEADER_SIGNATURE, false));
+            data.extend(*body);
+        }
+        let offset = data.len() as u32;
+        data.extend(&directory);
+        data.extend(EOCD_SIGNATURE.to_le_bytes());
+        data.extend([0; 4]);
+        data.extend((files.len() as u16).to_le_bytes());
+        data.extend((files.len() as u16).to_le_bytes());
+        data.extend((directory.len() as u32).to_le_bytes());
+        data.extend(offset.to_le_bytes());
+        data.extend(0u16.to_le_bytes());
+        data
+    }
+
+    fn tar_header(name: &str, size: u64, flag: u8) -> Vec<u8> {
+        let mut block = vec![0; TAR_BLOCK_SIZE as usize];
+        block[..name.len()].copy_from_slice(name.as_bytes());
+        block[124..135].copy_from_slice(format!("{size:011o}").as_bytes());
+        block[156] = flag;
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[148..156].copy_from_slice(b"        ");
+        let sum = block.iter().map(|x| *x as u64).sum::<u64>();
+        block[148..155].copy_from_slice(format!("{sum:06o}\0").as_bytes());
+        block
+    }
+
+    fn tar_file(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut data = vec![];
+        for (name, body) in files {
+            if name.len() > 100 {
+                data.extend(tar_header("././@LongLink", name.len() as u64 + 1, b'L'));
+                let mut long = name.as_bytes().to_vec();
+                long.resize(padded(name.len() as u64 + 1) as usize, 0);
+                data.extend(long);
+            }
+            data.extend(tar_header(&name[..name.len().min(100)], body.len() as u64, b'0'));
+            data.extend(*body);
+            data.resize(padded(data.len() as u64) as usize, 0);
+        }
+        data.extend(vec![0; 2 * TAR_BLOCK_SIZE as usize]);
+        data
+    }
+
+    fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+        let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
+
+    #[rstest]
+    #[case("a/b.zip", Some(ArchiveKind::Zip))]
+    #[case("a/b.TAR", Some(ArchiveKind::Tar))]
+    #[case("a/b.tar.gz", Some(ArchiveKind::TarGz))]
+    #[case("a/b.tgz", Some(ArchiveKind::TarGz))]
+    #[case("a/b.gz", None)]
+    fn test_from_key(#[case] key: &str, #[case] expected: Option<ArchiveKind>) {
+        assert_eq!(ArchiveKind::from_key(key), expected);
+    }
+
+    #[test]
+    fn test_tar_scanner() -> Result<()> {
+        let long = format!("{}/scan.dcm", "d".repeat(120));
+        let data = tar_file(&[("a.csv", b"id\n1\n"), (&long, &[7; 1000])]);
+        // fed in small pieces as gzip output arrives
+        let mut scanner = TarScanner::default();
+        for chunk in data.chunks(100) {
+            scanner.feed(chunk)?;
+        }
+        assert!(scanner.is_done());
+        let members = scanner.finish();
+        assert_eq!(members[0], ArchiveMember { path: "a.csv".to_string(), size: 5 });
+        assert_eq!(members[1], ArchiveMember { path: long, size: 1000 });
+
+        let mut scanner = TarScanner::default();
+        assert!(scanner.feed(&[1; 512]).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_add_members() -> Result<()> {
+        let storage: StorageRef = Arc::new(ObjectStoreStorage::memory("memory://raw"));
+        let files: &[(&str, &[u8])] = &[("mri/a.csv", b"id\n1\n"), ("mri/", b""), ("b.json", b"{}")];
+        let archives = [
+            ("a.zip", zip_file(files)),
+            ("a.tar", tar_file(files)),
+            ("a.tar.gz", gzip(&tar_file(files))?),
+            ("broken.zip", b"not a zip".to_vec()),
+        ];
+        let mut records = vec![];
+        for (key, data) in archives {
+            let info = ObjectInfo {
+                size: Some(data.len() as i64),
+                ..Default::default()
+            };
+            storage.put(key, Bytes::from(data)).await?;
+            records.push(FileData::new("memory://raw", key.to_string(), info));
+        }
+
+        assert_eq!(add_members(storage, &mut records).await?, 1);
+        assert_eq!(records.len(), 4 + 3 * 2);
+        for archive in ["a.zip", "a.tar", "a.tar.gz"] {
+            let mut members = records
+                .iter()
+                .filter(|x| x.archive_path.as_deref() == Some(archive))
+                .map(|x| (x.member_path.clone().unwrap_or_default(), x.member_size, x.file_name.clone()))
+                .collect::<Vec<_>>();
+            members.sort();
+            assert_eq!(
+                members,
+                vec![
+                    ("b.json".to_string(), Some(2), Some("b.json".to_string())),
+                    ("mri/a.csv".to_string(), Some(5), Some("a.csv".to_string())),
+                ],
+                "{archive}"
+            );
+        }
+        let member = records.iter().find(|x| x.member_path.is_some()).expect("member row");
+        assert_eq!(member.file_url, records.iter().find(|x| x.file_path == member.file_path).and_then(|x| x.file_url.clone()));
+        Ok(())
+    }
+}
@@ -0,0 +1,26 @@
+use data_indexer::config::Config;
+use data_indexer::events::{index_events, EventSummary};
+use data_indexer::storage::Stores;
+use data_indexer::utils::aws::get_aws_client;
+use data_indexer::utils::tracing::init_tracing;
+
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde_json::Value;
+
+/// lambda indexing single objects from s3 event notifications,
+/// configured from the environment like the batch runs
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    init_tracing();
+    let config = Config::new()?;
+    config.validate()?;
+    let client = get_aws_client(config.args.region()).await;
+    let stores = Stores::open(Some(client), &config).await?;
+
+    let (stores, config) = (&stores, &config);
+    run(service_fn(move |event: LambdaEvent<Value>| async move {
+        let res: anyhow::Result<EventSummary> = index_events(stores, config, &event.payload).await;
+        res.inspect_err(|err| tracing::error!(?err, "failed to index events"))
+    }))
+    .await
+}
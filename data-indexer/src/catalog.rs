@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::partition::HIVE_DEFAULT_PARTITION;
+use crate::storage::Storage;
+use crate::utils::datafusion::{read_parquet, write_batches};
+
+use anyhow::Result;
+use datafusion::arrow::array::RecordBatch;
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::functions::expr_fn::left;
+use datafusion::functions_aggregate::expr_fn::{count, sum};
+use datafusion::prelude::*;
+use datafusion::scalar::ScalarValue;
+
+pub const CATALOG_FILE: &str = "catalog.parquet";
+
+/// schema of the object_store_catalog table read by the api
+pub fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("year", DataType::Utf8, true),
+        Field::new("file_type", DataType::Utf8, true),
+        Field::new("cnt_file_type", DataType::Int64, true),
+        Field::new("sum_file_size", DataType::Int64, true),
+    ]))
+}
+
+/// hive partition values from the key, e.g. `index/year=2021/file_type=csv/x.parquet`
+pub fn partition_values(key: &str) -> HashMap<String, Option<String>> {
+    key.split('/')
+        .filter_map(|x| x.split_once('='))
+        .map(|(name, value)| {
+            let value = (value != HIVE_DEFAULT_PARTITION).then(|| value.to_string());
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// count and size of files per year and file_type of one index file
+pub async fn aggregate(
+    ctx: &SessionContext,
+    batches: Vec<RecordBatch>,
+    partition_values: &HashMap<String, Option<String>>,
+) -> Result<Vec<RecordBatch>> {
+    let mut df = ctx.read_batches(batches)?;
+    if df.schema().field_with_unqualified_name("deleted_at").is_ok() {
+        df = df.filter(col("deleted_at").is_null())?;
+    }
+    // partition columns are stored in the path only
+    if df.schema().field_with_unqualified_name("file_type").is_err() {
+        let file_type = partition_values.get("file_type").cloned().flatten();
+        df = df.with_column("file_type", lit(ScalarValue::Utf8(file_type)))?;
+    }
+    // files written before the typed columns only have dt
+    let year = if df.schema().field_with_unqualified_name("year").is_ok() {
+        cast(col("year"), DataType::Utf8)
+    } else {
+        left(col("dt"), lit(4))
+    };
+    let df = df.aggregate(
+        vec![
+            year.alias("year"),
+            col("file_type"),
+        ],
+        vec![
+            count(lit(1)).alias("cnt_file_type"),
+            sum(col("file_size")).alias("sum_file_size"),
+        ],
+    )?;
+    Ok(df.collect().await?)
+}
+
+/// merges the aggregates of all index files into the catalog
+pub async fn combine(ctx: &SessionContext, partials: Vec<RecordBatch>) -> Result<Vec<RecordBatch>> {
+    let schema = schema();
+    let partials = partials
+        .into_iter()
+        .map(|x| Ok(RecordBatch::try_new(schema.clone(), x.columns().to_vec())?))
+        .collect::<Result<Vec<_>>>()?;
+    let df = ctx.read_batches(partials)?;
+    let df = df
+        .aggregate(
+            vec![col("year"), col("file_type")],
+            vec![
+                sum(col("cnt_file_type")).alias("cnt_file_type"),
+                sum(col("sum_file_size")).alias("sum_file_size"),
+            ],
+        )?
+        .sort(vec![
+            col("year").sort(true, false),
+            col("cnt_file_type").sort(false, false),
+            col("file_type").sort(true, false),
+        ])?;
+    let batches = df
+        .collect()
+        .await?
+        .into_iter()
+        .map(|x| Ok(RecordBatch::try_new(schema.clone(), x.columns().to_vec())?))
+        .collect::<Result<Vec<_>>>()?;
+    if batches.is_empty() {
+        return Ok(vec![RecordBatch::new_empty(schema)]);
+    }
+    Ok(batches)
+}
+
+/// rebuilds the catalog from the index files,
+/// one index file is kept in memory at a time
+pub async fn write_catalog(
+    storage: &dyn Storage,
+    keys: &[String],
+    catalog_prefix: &str,
+) -> Result<String> {
+    let ctx = SessionContext::new();
+    let mut partials = vec![];
+    for key in keys {
+        tracing::info!("reading index file: {}", key);
+        let batches = read_parquet(storage, key).await?;
+        partials.extend(aggregate(&ctx, batches, &partition_values(key)).await?);
+    }
+
+    let batches = combine(&ctx, partials).await?;
+    let key = format!("{catalog_prefix}{CATALOG_FILE}");
+    tracing::info!(
+        "writing catalog with {} rows to: {}",
+        batches.iter().map(|x| x.num_rows()).sum::<usize>(),
+        key
+    );
+    write_batches(storage, &key, batches).await?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use datafusion::arrow::array::{Array, ArrayRef, AsArray, Int64Array, StringArray};
+    use datafusion::arrow::datatypes::Int64Type;
+
+    fn index_batch(file_type: Option<Vec<Option<&str>>>) -> RecordBatch {
+        let mut fields = vec![
+            Field::new("dt", DataType::Utf8, true),
+            Field::new("file_size", DataType::Int64, true),
+        ];
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(vec!["2024-01-01T00:00:00Z", "2024-05-01T00:00:00Z", "2025-01-01T00:00:00Z"])),
+            Arc::new(Int64Array::from(vec![1, 2, 4])),
+        ];
+        if let Some(file_type) = file_type {
+            fields.push(Field::new("file_type", DataType::Utf8, true));
+            columns.push(Arc::new(StringArray::from(file_type)));
+        }
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).unwrap()
+    }
+
+    #[test]
+    fn test_partition_values() {
+        let values = partition_values("index/year=2021/file_type=__HIVE_DEFAULT_PARTITION__/a.parquet");
+        assert_eq!(values.get("year"), Some(&Some("2021".to_string())));
+        assert_eq!(values.get("file_type"), Some(&None));
+        assert_eq!(values.get("month"), None);
+    }
+
+    #[tokio::test]
+    async fn test_catalog() -> Result<()> {
+        let ctx = SessionContext::new();
+        let mut partials = aggregate(
+            &ctx,
+            vec![index_batch(Some(vec![Some("csv"), Some("csv"), None]))],
+            &HashMap::new(),
+        )
+        .await?;
+        partials.extend(
+            aggregate(
+                &ctx,
+                vec![index_batch(None)],
+                &partition_values("index/file_type=csv/a.parquet"),
+            )
+            .await?,
+        );
+
+        let batches = combine(&ctx, partials).await?;
+        let batch = datafusion::arrow::compute::concat_batches(&schema(), &batches)?;
+        let years = batch.column(0).as_string::<i32>();
+        let file_types = batch.column(1).as_string::<i32>();
+        let counts = batch.column(2).as_primitive::<Int64Type>();
+        let sizes = batch.column(3).as_primitive::<Int64Type>();
+        let rows = (0..batch.num_rows())
+            .map(|i| (years.value(i), file_types.is_valid(i).then(|| file_types.value(i)), counts.value(i), sizes.value(i)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rows,
+            vec![
+                ("2024", Some("csv"), 4, 6),
+                ("2025", Some("csv"), 1, 4),
+                ("2025", None, 1, 4),
+            ]
+        );
+        Ok(())
+    }
+}
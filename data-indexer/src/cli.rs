@@ -0,0 +1,66 @@
+use std::path::PathBuf;
+
+use crate::config::{Config, Mode};
+
+use anyhow::Result;
+use clap::Parser;
+
+/// indexes s3 objects into parquet files queried by the api
+#[derive(Parser, Debug)]
+#[command(name = "data-indexer", version)]
+pub struct Cli {
+    /// toml config file, without it the config is read from the environment
+    #[arg(short, long, global = true, env = "DATA_INDEXER_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// list and summarize without writing to the target bucket
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+
+    /// defaults to args.mode of the config
+    #[command(subcommand)]
+    pub command: Option<Mode>,
+}
+
+impl Cli {
+    /// config with the command line overrides, validated
+    pub fn config(&self) -> Result<Config> {
+        let mut config = match &self.config {
+            Some(path) => Config::from_file(path)?,
+            None => Config::new()?,
+        };
+        if let Some(mode) = self.command {
+            config.args.mode = Some(mode);
+        }
+        if self.dry_run {
+            config.args.dry_run = Some(true);
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(&["data-indexer"], None, false)]
+    #[case(&["data-indexer", "compact"], Some(Mode::Compact), false)]
+    #[case(&["data-indexer", "index", "--dry-run"], Some(Mode::Index), true)]
+    #[case(&["data-indexer", "--dry-run", "stats"], Some(Mode::Stats), true)]
+    #[case(&["data-indexer", "migrate"], Some(Mode::Migrate), false)]
+    #[case(&["data-indexer", "vacuum", "--dry-run"], Some(Mode::Vacuum), true)]
+    fn test_parse(#[case] args: &[&str], #[case] command: Option<Mode>, #[case] dry_run: bool) {
+        let cli = Cli::try_parse_from(args).unwrap();
+        assert_eq!(cli.command, command);
+        assert_eq!(cli.dry_run, dry_run);
+    }
+
+    #[test]
+    fn test_unknown_command() {
+        assert!(Cli::try_parse_from(["data-indexer", "reindex"]).is_err());
+    }
+}
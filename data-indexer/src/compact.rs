@@ -0,0 +1,464 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::catalog::partition_values;
+use crate::config::Config;
+use crate::deletions::DeletionPolicy;
+use crate::file_data::{derive_temporal, FileData};
+use crate::index_writer::IndexWriter;
+use crate::key_fields::KeyFields;
+use crate::schema_version::{check, read_versioned};
+use crate::sorter::ExternalSorter;
+use crate::storage::{list_keys, Storage, StorageRef};
+use crate::utils::constants::GENERATION_FILE;
+use crate::utils::datafusion::read_parquet;
+
+use anyhow::{anyhow, Result};
+use datafusion::arrow::array::{new_null_array, Array, ArrayRef, BooleanArray, RecordBatch, StringArray};
+use datafusion::arrow::compute::{cast, filter_record_batch, is_null};
+use serde::{Deserialize, Serialize};
+
+/// current set of combined index files, the api reads only the files
+/// of the generation this pointer refers to, so replacing it switches
+/// the whole set at once
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Generation {
+    pub generation: String,
+    pub created: String,
+    pub rows: usize,
+    pub keys: Vec<String>,
+    /// files of the generation before, deleted by the next compaction
+    pub previous: Vec<String>,
+}
+
+impl Generation {
+    pub fn key(combined_prefix: &str) -> String {
+        format!("{combined_prefix}{GENERATION_FILE}")
+    }
+
+    pub fn prefix(combined_prefix: &str, generation: &str) -> String {
+        format!("{combined_prefix}{generation}/")
+    }
+
+    pub async fn load(storage: &dyn Storage, combined_prefix: &str) -> Result<Option<Self>> {
+        let key = Self::key(combined_prefix);
+        let Some(data) = storage.get(&key).await? else {
+            return Ok(None);
+        };
+        let generation = serde_json::from_slice(&data)?;
+        Ok(Some(generation))
+    }
+
+    pub async fn save(&self, storage: &dyn Storage, combined_prefix: &str) -> Result<()> {
+        let key = Self::key(combined_prefix);
+        let data = serde_json::to_vec_pretty(self)?;
+        storage.put(&key, data.into()).await?;
+        Ok(())
+    }
+}
+
+/// per-run index files under the prefix that are not compacted yet
+pub async fn run_keys(storage: StorageRef, prefix: &str, excluded: &[&str]) -> Result<Vec<String>> {
+    let mut keys = list_keys(storage, prefix)
+        .await?
+        .into_iter()
+        .filter(|x| x.ends_with(".parquet"))
+        .filter(|x| !excluded.iter().any(|prefix| x.starts_with(prefix)))
+        .collect::<Vec<_>>();
+    keys.sort();
+    Ok(keys)
+}
+
+/// dt and whether the row is a tombstone
+type Version = (Option<String>, bool);
+
+/// optional columns the row key is built from
+struct KeyColumns {
+    file_urls: StringArray,
+    version_ids: Option<StringArray>,
+    member_paths: Option<StringArray>,
+}
+
+impl KeyColumns {
+    fn try_new(batch: &RecordBatch) -> Result<Self> {
+        Ok(Self {
+            file_urls: string_column(batch, "file_url")?,
+            version_ids: string_column(batch, "version_id").ok(),
+            member_paths: string_column(batch, "member_path").ok(),
+        })
+    }
+
+    /// file_url of the row, with its version id when versions are indexed
+    fn object_key(&self, i: usize) -> String {
+        match self.version_ids.as_ref().filter(|x| x.is_valid(i)) {
+            Some(version_ids) => format!("{}?versionId={}", self.file_urls.value(i), version_ids.value(i)),
+            None => self.file_urls.value(i).to_string(),
+        }
+    }
+
+    /// object key of the row and the member path for rows of archive members
+    fn row_key(&self, i: usize) -> (String, Option<String>) {
+        let member = self.member_paths.as_ref().filter(|x| x.is_valid(i)).map(|x| x.value(i).to_string());
+        (self.object_key(i), member)
+    }
+}
+
+/// position of the newest row per object over all input files,
+/// keyed by file_url so equal file_path values of different sources are kept,
+/// and by member path for rows of archive members
+#[derive(Debug, Default)]
+pub struct Latest {
+    entries: HashMap<(String, Option<String>), (Version, usize, usize)>,
+}
+
+impl Latest {
+    /// a tombstone wins over the row with the same dt,
+    /// otherwise rows of later files win if dt is equal
+    pub fn add(&mut self, file: usize, offset: usize, batch: &RecordBatch) -> Result<()> {
+        let keys = KeyColumns::try_new(batch)?;
+        let dts = string_column(batch, "dt")?;
+        let deleted_ats = string_column(batch, "deleted_at").ok();
+        for i in 0..batch.num_rows() {
+            if keys.file_urls.is_null(i) {
+                continue;
+            }
+            let dt = dts.is_valid(i).then(|| dts.value(i).to_string());
+            let deleted = deleted_ats.as_ref().is_some_and(|x| x.is_valid(i));
+            let version = (dt, deleted);
+            let key = keys.row_key(i);
+            match self.entries.get(&key) {
+                Some((current, _, _)) if *current > version => (),
+                _ => {
+                    self.entries.insert(key, (version, file, offset + i));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// rows of the batch to keep, rows without file_url are always kept,
+    /// members are dropped once their archive is replaced or deleted
+    pub fn mask(&self, file: usize, offset: usize, batch: &RecordBatch) -> Result<BooleanArray> {
+        let keys = KeyColumns::try_new(batch)?;
+        let mask = (0..batch.num_rows())
+            .map(|i| {
+                if keys.file_urls.is_null(i) {
+                    return true;
+                }
+                let (object, member) = keys.row_key(i);
+                let archive = member.is_some().then(|| self.entries.get(&(object.clone(), None))).flatten();
+                let Some((version, f, row)) = self.entries.get(&(object, member)) else {
+                    return false;
+                };
+                let replaced = archive.is_some_and(|(current, _, _)| current > version);
+                *f == file && *row == offset + i && !replaced
+            })
+            .collect::<Vec<_>>();
+        Ok(BooleanArray::from(mask))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn drop_deleted(batch: &RecordBatch) -> Result<RecordBatch> {
+    let Some(deleted_at) = batch.column_by_name("deleted_at") else {
+        return Ok(batch.clone());
+    };
+    Ok(filter_record_batch(batch, &is_null(deleted_at)?)?)
+}
+
+pub fn string_column(batch: &RecordBatch, name: &str) -> Result<StringArray> {
+    let col = batch
+        .column_by_name(name)
+        .ok_or_else(|| anyhow!("index file has no {name} column"))?;
+    let col = cast(col, &datafusion::arrow::datatypes::DataType::Utf8)?;
+    Ok(col.as_any().downcast_ref::<StringArray>().cloned().expect("casted to utf8"))
+}
+
+/// brings a batch read from an index file to the `FileData` schema,
+/// partition columns come from the path, temporal columns from dt
+/// and other columns added since are null
+pub fn conform(batch: &RecordBatch, partition_values: &HashMap<String, Option<String>>) -> Result<RecordBatch> {
+    let schema = Arc::new(FileData::schema());
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| {
+            if let Some(col) = batch.column_by_name(field.name()) {
+                return Ok(cast(col, field.data_type())?);
+            }
+            let col: ArrayRef = match partition_values.get(field.name()) {
+                Some(value) => Arc::new(StringArray::from(vec![value.clone(); batch.num_rows()])),
+                None => derive_temporal(field.name(), batch)
+                    .unwrap_or_else(|| new_null_array(field.data_type(), batch.num_rows())),
+            };
+            Ok(cast(&col, field.data_type())?)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+/// merges generation and per-run files into a new deduplicated generation
+pub struct Compaction {
+    storage: StorageRef,
+    combined_prefix: String,
+    config: Config,
+}
+
+impl Compaction {
+    pub fn try_new(storage: StorageRef, config: &Config) -> Result<Self> {
+        let combined_prefix = config.args.combined_prefix().to_string();
+        if config.prefix_target.starts_with(&combined_prefix) {
+            return Err(anyhow!(
+                "prefix_target: {} must not be inside combined_prefix: {}",
+                config.prefix_target,
+                combined_prefix
+            ));
+        }
+        Ok(Self {
+            storage,
+            combined_prefix,
+            config: config.clone(),
+        })
+    }
+
+    fn writer(&self, prefix: &str) -> Result<IndexWriter> {
+        let writer = IndexWriter::new(
+            self.storage.clone(),
+            prefix,
+            "table=data_index.parquet",
+            self.config.args.part_size(),
+            self.config.args.partition_by()?,
+            KeyFields::try_new(self.config.args.fields())?,
+        )?;
+        Ok(writer
+            .with_max_file_size(self.config.args.target_file_size())
+            .with_options(self.config.args.writer_options()?))
+    }
+
+    /// returns the new generation, or none if there is nothing to compact
+    pub async fn run(
+        &self,
+        current: Option<&Generation>,
+        run_keys: &[String],
+    ) -> Result<Option<Generation>> {
+        if run_keys.is_empty() {
+            return Ok(None);
+        }
+        let inputs = current
+            .map(|x| x.keys.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .chain(run_keys.iter().cloned())
+            .collect::<Vec<_>>();
+
+        let mut latest = Latest::default();
+        for (file, key) in inputs.iter().enumerate() {
+            tracing::info!("reading index file: {}", key);
+            let mut offset = 0;
+            let (version, batches) = read_versioned(self.storage.as_ref(), key).await?;
+            check(key, version)?;
+            for batch in batches {
+                latest.add(file, offset, &batch)?;
+                offset += batch.num_rows();
+            }
+        }
+        tracing::info!("unique files in {} index files: {}", inputs.len(), latest.len());
+
+        let generation = format!("gen={}", chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ"));
+        let mut writer = self.writer(&Generation::prefix(&self.combined_prefix, &generation))?;
+        // sorted over all inputs, so row groups can be pruned by file_path
+        let mut sorter = self
+            .config
+            .args
+            .writer_options()?
+            .sort_by_file_path
+            .then(|| ExternalSorter::new(Arc::new(FileData::schema())));
+        for (file, key) in inputs.iter().enumerate() {
+            let values = partition_values(key);
+            let mut offset = 0;
+            for batch in read_parquet(self.storage.as_ref(), key).await? {
+                let mask = latest.mask(file, offset, &batch)?;
+                offset += batch.num_rows();
+                let mut batch = filter_record_batch(&batch, &mask)?;
+                if self.config.args.deletion_policy() == DeletionPolicy::Drop {
+                    batch = drop_deleted(&batch)?;
+                }
+                let batch = conform(&batch, &values)?;
+                match sorter.as_mut() {
+                    Some(sorter) => sorter.push(batch)?,
+                    None => writer.write_batch(&batch).await?,
+                }
+            }
+        }
+        if let Some(sorter) = sorter {
+            sorter.write_to(&mut writer).await?;
+        }
+        let rows = writer.rows();
+        let keys = writer.finish().await?;
+
+        Ok(Some(Generation {
+            generation,
+            created: chrono::Utc::now().to_rfc3339(),
+            rows,
+            keys,
+            previous: current.map(|x| x.keys.clone()).unwrap_or_default(),
+        }))
+    }
+
+    /// switches the api to the new generation, then removes compacted files
+    pub async fn replace(
+        &self,
+        current: Option<Generation>,
+        next: &Generation,
+        run_keys: Vec<String>,
+    ) -> Result<()> {
+        next.save(self.storage.as_ref(), &self.combined_prefix).await?;
+        tracing::info!("switched combined index to: {}", next.generation);
+
+        // readers may still use the previous generation, only the one before is removed
+        let mut stale = current.map(|x| x.previous).unwrap_or_default();
+        stale.extend(run_keys);
+        self.storage.delete(stale).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::archives::ArchiveMember;
+    use crate::utils::aws::ObjectInfo;
+
+    fn record(key: &str, dt: &str, size: i64) -> FileData {
+        let info = ObjectInfo {
+            size: Some(size),
+            last_modified: Some(dt.to_string()),
+            ..Default::default()
+        };
+        FileData::new("bucket", key.to_string(), info)
+    }
+
+    #[test]
+    fn test_latest() -> Result<()> {
+        let first = FileData::to_record_batch(&[
+            record("a.csv", "2024-01-01T00:00:00Z", 1),
+            record("b.csv", "2024-03-01T00:00:00Z", 1),
+        ])?;
+        let second = FileData::to_record_batch(&[
+            record("a.csv", "2024-02-01T00:00:00Z", 2),
+            record("b.csv", "2024-01-01T00:00:00Z", 2),
+            record("c.csv", "2024-01-01T00:00:00Z", 2),
+        ])?;
+        let mut latest = Latest::default();
+        latest.add(0, 0, &first)?;
+        latest.add(1, 0, &second)?;
+        assert_eq!(latest.len(), 3);
+
+        let first = filter_record_batch(&first, &latest.mask(0, 0, &first)?)?;
+        let second = filter_record_batch(&second, &latest.mask(1, 0, &second)?)?;
+        assert_eq!(string_column(&first, "file_path")?, StringArray::from(vec!["b.csv"]));
+        assert_eq!(string_column(&second, "file_path")?, StringArray::from(vec!["a.csv", "c.csv"]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_latest_offset() -> Result<()> {
+        let batch = FileData::to_record_batch(&[record("a.csv", "2024-01-01T00:00:00Z", 1)])?;
+        let mut latest = Latest::default();
+        latest.add(0, 0, &batch)?;
+        latest.add(0, 1, &batch)?;
+        assert_eq!(latest.mask(0, 0, &batch)?, BooleanArray::from(vec![false]));
+        assert_eq!(latest.mask(0, 1, &batch)?, BooleanArray::from(vec![true]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_latest_tombstone() -> Result<()> {
+        let live = FileData::to_record_batch(&[record("a.csv", "2024-01-01T00:00:00Z", 1)])?;
+        let mut tombstone = record("a.csv", "2024-01-01T00:00:00Z", 1);
+        tombstone.deleted_at = Some("2024-05-01T00:00:00Z".to_string());
+        let tombstone = FileData::to_record_batch(&[tombstone])?;
+
+        let mut latest = Latest::default();
+        latest.add(0, 0, &tombstone)?;
+        latest.add(1, 0, &live)?;
+        assert_eq!(latest.mask(0, 0, &tombstone)?, BooleanArray::from(vec![true]));
+        assert_eq!(latest.mask(1, 0, &live)?, BooleanArray::from(vec![false]));
+        assert_eq!(drop_deleted(&tombstone)?.num_rows(), 0);
+        assert_eq!(drop_deleted(&live)?.num_rows(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_latest_versions() -> Result<()> {
+        // versions of an object share the file_url and are all kept
+        let mut records = vec![
+            record("a.csv", "2024-01-01T00:00:00Z", 1),
+            record("a.csv", "2024-02-01T00:00:00Z", 2),
+        ];
+        records[0].version_id = Some("v1".to_string());
+        records[1].version_id = Some("v2".to_string());
+        let batch = FileData::to_record_batch(&records)?;
+        let mut latest = Latest::default();
+        latest.add(0, 0, &batch)?;
+        assert_eq!(latest.len(), 2);
+        assert_eq!(latest.mask(0, 0, &batch)?, BooleanArray::from(vec![true, true]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_latest_members() -> Result<()> {
+        let archive = record("a.zip", "2024-01-01T00:00:00Z", 10);
+        let member = |path: &str, archive: &FileData| {
+            archive.member(&ArchiveMember {
+                path: path.to_string(),
+                size: 1,
+            })
+        };
+        let first = FileData::to_record_batch(&[
+            member("x.csv", &archive),
+            member("y.csv", &archive),
+            archive.clone(),
+        ])?;
+        // the archive is replaced without y.csv
+        let changed = record("a.zip", "2024-02-01T00:00:00Z", 20);
+        let second = FileData::to_record_batch(&[member("x.csv", &changed), changed])?;
+
+        let mut latest = Latest::default();
+        latest.add(0, 0, &first)?;
+        assert_eq!(latest.len(), 3);
+        assert_eq!(latest.mask(0, 0, &first)?, BooleanArray::from(vec![true, true, true]));
+        latest.add(1, 0, &second)?;
+        assert_eq!(latest.mask(0, 0, &first)?, BooleanArray::from(vec![false, false, false]));
+        assert_eq!(latest.mask(1, 0, &second)?, BooleanArray::from(vec![true, true]));
+
+        // members of a deleted archive are dropped with it
+        let mut tombstone = record("a.zip", "2024-02-01T00:00:00Z", 20);
+        tombstone.deleted_at = Some("2024-03-01T00:00:00Z".to_string());
+        let third = FileData::to_record_batch(&[tombstone])?;
+        latest.add(2, 0, &third)?;
+        assert_eq!(latest.mask(1, 0, &second)?, BooleanArray::from(vec![false, false]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_conform() -> Result<()> {
+        let batch = FileData::to_record_batch(&[record("a.csv", "2024-01-01T00:00:00Z", 1)])?;
+        // as written with partition_by file_type and before the metadata column existed
+        let batch = batch.project(&[0, 2, 3, 4, 5])?;
+        let batch = conform(&batch, &partition_values("index/file_type=csv/x.parquet"))?;
+        assert_eq!(batch.schema().as_ref(), &FileData::schema());
+        assert_eq!(string_column(&batch, "file_type")?, StringArray::from(vec!["csv"]));
+        assert!(batch.column_by_name("metadata").unwrap().is_null(0));
+        assert!(batch.column_by_name("year").unwrap().is_valid(0));
+        Ok(())
+    }
+}
@@ -1,7 +1,18 @@
 use std::env;
+use std::path::Path;
 
-use anyhow::Result;
-use serde::Deserialize;
+use crate::deletions::DeletionPolicy;
+use crate::file_data::FileData;
+use crate::key_fields::{FieldSpec, KeyFields};
+use crate::partition::PartitionColumn;
+use crate::source::Source;
+use crate::utils::constants::*;
+use crate::writer_options::WriterOptions;
+
+use anyhow::{anyhow, Context, Result};
+use clap::Subcommand;
+use dataplatform_multipart::constants::{MIN_PART_SIZE, PART_SIZE};
+use serde::{Deserialize, Serialize};
 
 struct Input {
     bucket_source: String,
@@ -14,11 +25,12 @@ struct Input {
 
 impl Input {
     fn new() -> Result<Self> {
-        let bucket_source = env::var("bucket_source")?;
+        // single source, empty when the sources are listed in args
+        let bucket_source = env::var("bucket_source").unwrap_or_default();
         let bucket_target = env::var("bucket_target")?;
-        let prefix_source = env::var("prefix_source")?;
+        let prefix_source = env::var("prefix_source").unwrap_or_default();
         let prefix_target = env::var("prefix_target")?;
-        let item_name = env::var("item_name")?;
+        let item_name = env::var("item_name").unwrap_or_default();
         let args = env::var("args")?;
 
         Ok(Self {
@@ -32,19 +44,102 @@ impl Input {
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
+/// read from the environment or from a toml file, e.g.
+/// ```toml
+/// bucket_target = "index"
+/// prefix_target = "index/"
+///
+/// [args]
+/// incremental = true
+///
+/// [[args.sources]]
+/// bucket = "raw-data"
+/// prefix = "mri/"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    #[serde(default)]
     pub bucket_source: String,
     pub bucket_target: String,
+    #[serde(default)]
     pub prefix_source: String,
     pub prefix_target: String,
+    #[serde(default)]
     pub item_name: String,
+    #[serde(default)]
     pub args: Args,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Args {
     pub region: Option<String>,
+    pub incremental: Option<bool>,
+    pub append_only: Option<bool>,
+    pub head_object: Option<bool>,
+    pub part_size: Option<usize>,
+    pub partition_by: Option<Vec<String>>,
+    pub fields: Option<Vec<FieldSpec>>,
+    pub extract_metadata: Option<bool>,
+    pub mode: Option<Mode>,
+    pub catalog: Option<bool>,
+    pub catalog_prefix: Option<String>,
+    pub combined_prefix: Option<String>,
+    pub target_file_size: Option<usize>,
+    pub track_deletions: Option<bool>,
+    pub deletion_policy: Option<DeletionPolicy>,
+    pub sources: Option<Vec<Source>>,
+    pub sharded_listing: Option<bool>,
+    /// s3 inventory manifest read instead of listing the single source
+    pub inventory: Option<String>,
+    pub content_hash: Option<bool>,
+    pub duplicates_prefix: Option<String>,
+    /// list and summarize without writing to the target bucket
+    pub dry_run: Option<bool>,
+    pub output: Option<OutputFormat>,
+    pub enrich: Option<bool>,
+    pub versions: Option<bool>,
+    pub archives: Option<bool>,
+    pub vacuum_retention_hours: Option<u64>,
+    /// parquet codec of the index files, e.g. `snappy` or `zstd(3)`
+    pub compression: Option<String>,
+    /// `none`, `chunk` or `page` min/max statistics
+    pub statistics: Option<String>,
+    pub row_group_size: Option<usize>,
+    pub bloom_filters: Option<Vec<String>>,
+    /// rows of run files are sorted per row group, compaction sorts the whole generation
+    pub sort_by_file_path: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Subcommand, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// list the source and write new index files
+    #[default]
+    Index,
+    /// only rebuild the catalog from the existing index files
+    Catalog,
+    /// merge the per-run index files into the deduplicated combined files
+    Compact,
+    /// check that the index files referenced by the watermark and generation are readable
+    Verify,
+    /// print the number of index files, rows and bytes
+    Stats,
+    /// rewrite index files of an older schema version with the current columns
+    Migrate,
+    /// delete files no longer referenced by the delta table
+    Vacuum,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFormat {
+    /// per-run parquet files, merged by compact into the combined files
+    #[default]
+    Parquet,
+    /// delta lake table at the combined prefix, every run is merged into it on file_url
+    Delta,
 }
 
 impl Config {
@@ -62,6 +157,12 @@ impl Config {
         })
     }
 
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file: {}", path.display()))?;
+        toml::from_str(&data).with_context(|| format!("invalid config file: {}", path.display()))
+    }
+
     pub fn create(
         bucket_source: &str,
         bucket_target: &str,
@@ -83,6 +184,259 @@ impl Config {
     }
 }
 
+impl Config {
+    /// checks the whole config up front, all problems are reported at once
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = vec![];
+        if self.bucket_target.is_empty() {
+            errors.push("bucket_target is empty".to_string());
+        }
+        let prefixes = [
+            ("prefix_target", self.prefix_target.as_str()),
+            ("args.catalog_prefix", self.args.catalog_prefix()),
+            ("args.combined_prefix", self.args.combined_prefix()),
+            ("args.duplicates_prefix", self.args.duplicates_prefix()),
+        ];
+        for (name, prefix) in prefixes {
+            if !prefix.is_empty() && !prefix.ends_with('/') {
+                errors.push(format!("{name}: {prefix} must end with /"));
+            }
+        }
+        if self.prefix_target.starts_with(self.args.combined_prefix()) {
+            errors.push(format!(
+                "prefix_target: {} must not be inside args.combined_prefix: {}",
+                self.prefix_target,
+                self.args.combined_prefix()
+            ));
+        }
+        if self.args.part_size() < MIN_PART_SIZE {
+            errors.push(format!("args.part_size: {} is below the s3 minimum of {MIN_PART_SIZE}", self.args.part_size()));
+        }
+        if self.args.target_file_size() == 0 {
+            errors.push("args.target_file_size must be greater than 0".to_string());
+        }
+        if self.args.is_append_only() && !self.args.is_incremental() {
+            errors.push("args.append_only requires args.incremental".to_string());
+        }
+        if let Err(e) = self.args.partition_by() {
+            errors.push(format!("args.partition_by: {e}"));
+        }
+        match (KeyFields::try_new(self.args.fields()), self.args.writer_options()) {
+            (Ok(key_fields), Ok(options)) => {
+                let schema = key_fields.schema(FileData::schema());
+                for name in &options.bloom_filters {
+                    if schema.index_of(name).is_err() {
+                        errors.push(format!("args.bloom_filters: unknown column: {name}"));
+                    }
+                }
+            }
+            (key_fields, options) => {
+                if let Err(e) = key_fields {
+                    errors.push(format!("args.fields: {e}"));
+                }
+                if let Err(e) = options {
+                    errors.push(format!("args.{e}"));
+                }
+            }
+        }
+        match (self.args.output(), self.args.mode()) {
+            (OutputFormat::Delta, Mode::Compact | Mode::Migrate) => {
+                errors.push("args.mode compact and migrate rewrite files outside of the delta log".to_string());
+            }
+            (OutputFormat::Parquet, Mode::Vacuum) => errors.push("args.mode vacuum requires args.output delta".to_string()),
+            _ => (),
+        }
+        if self.args.output() == OutputFormat::Delta && self.args.partition_by.as_ref().is_some_and(|x| !x.is_empty()) {
+            errors.push("args.partition_by is not supported with args.output delta".to_string());
+        }
+        if self.args.with_versions() {
+            // the snapshot, deletion tracking and delta merge keep one row per file_url
+            let conflicts = [
+                ("args.incremental", self.args.is_incremental()),
+                ("args.track_deletions", self.args.with_deletions()),
+                ("args.sharded_listing", self.args.with_sharded_listing()),
+                ("args.output delta", self.args.output() == OutputFormat::Delta),
+            ];
+            for (name, enabled) in conflicts {
+                if enabled {
+                    errors.push(format!("{name} is not supported with args.versions"));
+                }
+            }
+        }
+        if matches!(self.args.mode(), Mode::Index | Mode::Verify | Mode::Stats) {
+            if let Err(e) = self.sources() {
+                errors.push(e.to_string());
+            }
+        }
+
+        if errors.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!("invalid config:\n  - {}", errors.join("\n  - ")))
+    }
+
+    /// sources from args, or the single source from bucket_source, prefix_source and item_name
+    pub fn sources(&self) -> Result<Vec<Source>> {
+        let sources = match &self.args.sources {
+            Some(sources) => sources.clone(),
+            None if self.bucket_source.is_empty() => {
+                return Err(anyhow!("no sources configured, set bucket_source or args.sources"));
+            }
+            None => vec![Source {
+                bucket: self.bucket_source.clone(),
+                prefix: format!("{}{}", self.prefix_source, self.item_name),
+                dataset: Some(self.item_name.clone()).filter(|x| !x.is_empty()),
+                region: None,
+                profile: None,
+                inventory: self.args.inventory.clone(),
+            }],
+        };
+        if sources.is_empty() {
+            return Err(anyhow!("args.sources is empty"));
+        }
+        for (i, source) in sources.iter().enumerate() {
+            if sources[..i].iter().any(|x| x.url() == source.url()) {
+                return Err(anyhow!("duplicated source: {}", source.url()));
+            }
+        }
+        Ok(sources)
+    }
+}
+
+impl Args {
+    pub fn region(&self) -> &str {
+        self.region.as_deref().unwrap_or(REGION)
+    }
+
+    pub fn is_incremental(&self) -> bool {
+        self.incremental.unwrap_or(false)
+    }
+
+    /// keys under the prefix are only ever added in ascending order,
+    /// so listing can continue after the last key of the previous run
+    pub fn is_append_only(&self) -> bool {
+        self.append_only.unwrap_or(false)
+    }
+
+    /// fetch content type, encoding and checksum value with HeadObject per file
+    pub fn with_head_object(&self) -> bool {
+        self.head_object.unwrap_or(false)
+    }
+
+    /// size in bytes of each part of the multipart upload
+    pub fn part_size(&self) -> usize {
+        self.part_size.unwrap_or(PART_SIZE)
+    }
+
+    /// hive partition columns of the index output, e.g. ["year", "month", "file_type"]
+    pub fn partition_by(&self) -> Result<Vec<PartitionColumn>> {
+        self.partition_by
+            .iter()
+            .flatten()
+            .map(|x| x.parse())
+            .collect()
+    }
+
+    /// extra columns extracted from the object key
+    pub fn fields(&self) -> &[FieldSpec] {
+        self.fields.as_deref().unwrap_or_default()
+    }
+
+    /// read parquet footers, csv headers, image headers and json keys with ranged GETs
+    pub fn with_metadata(&self) -> bool {
+        self.extract_metadata.unwrap_or(false)
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode.unwrap_or_default()
+    }
+
+    /// rebuild the catalog after every index run
+    pub fn with_catalog(&self) -> bool {
+        self.catalog.unwrap_or(false)
+    }
+
+    /// prefix in the target bucket the catalog is written to
+    pub fn catalog_prefix(&self) -> &str {
+        self.catalog_prefix.as_deref().unwrap_or(CATALOG_PREFIX)
+    }
+
+    /// prefix in the target bucket the compacted index is written to
+    pub fn combined_prefix(&self) -> &str {
+        self.combined_prefix.as_deref().unwrap_or(COMBINED_PREFIX)
+    }
+
+    /// size in bytes after which compaction starts a new file
+    pub fn target_file_size(&self) -> usize {
+        self.target_file_size.unwrap_or(TARGET_FILE_SIZE)
+    }
+
+    /// diff the listing against the previous snapshot and write tombstones
+    pub fn with_deletions(&self) -> bool {
+        self.track_deletions.unwrap_or(false)
+    }
+
+    /// list common prefixes concurrently, the whole listing of a source is kept in memory
+    pub fn with_sharded_listing(&self) -> bool {
+        self.sharded_listing.unwrap_or(false)
+    }
+
+    /// sha-256 of the object bodies, reads every new or changed file
+    pub fn with_content_hash(&self) -> bool {
+        self.content_hash.unwrap_or(false)
+    }
+
+    pub fn duplicates_prefix(&self) -> &str {
+        self.duplicates_prefix.as_deref().unwrap_or(DUPLICATES_PREFIX)
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run.unwrap_or(false)
+    }
+
+    pub fn deletion_policy(&self) -> DeletionPolicy {
+        self.deletion_policy.unwrap_or_default()
+    }
+
+    /// tags with GetObjectTagging and `x-amz-meta-*` headers with HeadObject per new or changed file,
+    /// tags changed without a new version of the object are only seen by full runs
+    pub fn with_enrichment(&self) -> bool {
+        self.enrich.unwrap_or(false)
+    }
+
+    /// every version and delete marker with ListObjectVersions, or from inventory
+    /// reports with all versions, instead of the current objects
+    pub fn with_versions(&self) -> bool {
+        self.versions.unwrap_or(false)
+    }
+
+    /// rows for the members of `.zip`, `.tar`, `.tar.gz` and `.tgz` files, zip central directories
+    /// and tar headers are read with ranged requests, compressed tars are read whole
+    pub fn with_archives(&self) -> bool {
+        self.archives.unwrap_or(false)
+    }
+
+    pub fn output(&self) -> OutputFormat {
+        self.output.unwrap_or_default()
+    }
+
+    /// hours removed delta files are kept for readers of older versions
+    pub fn vacuum_retention_hours(&self) -> u64 {
+        self.vacuum_retention_hours.unwrap_or(VACUUM_RETENTION_HOURS)
+    }
+
+    /// parquet settings of the run, compacted and delta files
+    pub fn writer_options(&self) -> Result<WriterOptions> {
+        WriterOptions::try_new(
+            self.compression.as_deref(),
+            self.statistics.as_deref(),
+            self.row_group_size,
+            self.bloom_filters.clone().unwrap_or_default(),
+            self.sort_by_file_path.unwrap_or(false),
+        )
+    }
+}
+
 impl std::fmt::Display for Config {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "config: item_name: {} bucket_source: {} bucket_target: {} prefix_source: {} prefix_target: {} args: {:?}",
@@ -95,3 +449,99 @@ impl std::fmt::Display for Config {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_single_source() -> Result<()> {
+        let config = Config::create("raw", "index", "data/", "index/", "mri/", "{}")?;
+        let sources = config.sources()?;
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].url(), "s3://raw/data/mri/");
+        assert_eq!(sources[0].dataset.as_deref(), Some("mri/"));
+        assert_eq!(sources[0].inventory, None);
+
+        let args = r#"{"inventory": "s3://inventory/raw/daily/2024-05-02T01-00Z/manifest.json"}"#;
+        let sources = Config::create("raw", "index", "data/", "index/", "", args)?.sources()?;
+        assert!(sources[0].inventory.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sources() -> Result<()> {
+        let args = r#"{"sources": [
+            {"bucket": "raw", "prefix": "mri/", "dataset": "mri"},
+            {"bucket": "archive", "prefix": "", "region": "us-east-1", "profile": "archive"}
+        ]}"#;
+        let config = Config::create("", "index", "", "index/", "", args)?;
+        let sources = config.sources()?;
+        assert_eq!(sources[1].url(), "s3://archive/");
+        assert_eq!(sources[1].profile.as_deref(), Some("archive"));
+
+        let args = r#"{"sources": [{"bucket": "raw", "prefix": "a/"}, {"bucket": "raw", "prefix": "a/"}]}"#;
+        assert!(Config::create("", "index", "", "index/", "", args)?.sources().is_err());
+        assert!(Config::create("", "index", "", "index/", "", "{}")?.sources().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_toml() -> Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            bucket_target = "index"
+            prefix_target = "index/"
+
+            [args]
+            incremental = true
+            partition_by = ["year", "file_type"]
+
+            [[args.sources]]
+            bucket = "raw"
+            prefix = "mri/"
+            "#,
+        )?;
+        config.validate()?;
+        assert!(config.args.is_incremental());
+        assert_eq!(config.args.region(), REGION);
+        assert_eq!(config.sources()?[0].url(), "s3://raw/mri/");
+
+        let typo = toml::from_str::<Config>("bucket_target = \"a\"\nprefix_target = \"\"\n[args]\nincremantal = true");
+        assert!(typo.unwrap_err().to_string().contains("incremantal"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() -> Result<()> {
+        let args = r#"{"partition_by": ["day"], "part_size": 1024, "append_only": true, "catalog_prefix": "catalog"}"#;
+        let err = Config::create("raw", "", "data/", "index", "", args)?.validate().unwrap_err().to_string();
+        for expected in ["bucket_target", "prefix_target: index", "args.catalog_prefix", "args.part_size", "args.append_only", "day"] {
+            assert!(err.contains(expected), "{expected} not in: {err}");
+        }
+        let err = Config::create("", "index", "", "index/", "", "{}")?.validate().unwrap_err().to_string();
+        assert!(err.contains("no sources configured"));
+        assert!(Config::create("", "index", "", "index/", "", r#"{"mode": "compact"}"#)?.validate().is_ok());
+
+        let args = r#"{"output": "delta", "mode": "compact", "partition_by": ["year"]}"#;
+        let err = Config::create("raw", "index", "", "index/", "", args)?.validate().unwrap_err().to_string();
+        assert!(err.contains("args.mode compact") && err.contains("args.partition_by"), "{err}");
+        assert!(Config::create("", "index", "", "index/", "", r#"{"mode": "vacuum"}"#)?.validate().is_err());
+        assert!(Config::create("", "index", "", "index/", "", r#"{"mode": "vacuum", "output": "delta"}"#)?.validate().is_ok());
+
+        let args = r#"{"versions": true, "incremental": true, "track_deletions": true}"#;
+        let err = Config::create("raw", "index", "", "index/", "", args)?.validate().unwrap_err().to_string();
+        assert!(err.contains("args.incremental is not") && err.contains("args.track_deletions is not"), "{err}");
+        assert!(Config::create("raw", "index", "", "index/", "", r#"{"versions": true}"#)?.validate().is_ok());
+
+        let args = r#"{"compression": "zstd(3)", "bloom_filters": ["file_name", "project"], "fields": [{"name": "project", "source": "segment", "index": 0}]}"#;
+        assert!(Config::create("raw", "index", "", "index/", "", args)?.validate().is_ok());
+        let args = r#"{"compression": "zip", "row_group_size": 0}"#;
+        let err = Config::create("raw", "index", "", "index/", "", args)?.validate().unwrap_err().to_string();
+        assert!(err.contains("args.compression"), "{err}");
+        let args = r#"{"bloom_filters": ["file_nam"]}"#;
+        let err = Config::create("raw", "index", "", "index/", "", args)?.validate().unwrap_err().to_string();
+        assert!(err.contains("unknown column: file_nam"), "{err}");
+        Ok(())
+    }
+}
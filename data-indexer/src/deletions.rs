@@ -0,0 +1,61 @@
+use crate::snapshot::SnapshotEntry;
+use crate::storage::Storage;
+use crate::utils::constants::DELETIONS_DIR;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// what to do with rows of objects removed from the source bucket
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeletionPolicy {
+    /// keep the rows with deleted_at set
+    #[default]
+    Tombstone,
+    /// remove the rows when compacting
+    Drop,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DeletedFile {
+    pub file_url: String,
+    pub file_size: Option<i64>,
+    pub dt: Option<String>,
+}
+
+/// objects that disappeared since the previous run, written next to the index files
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DeletionReport {
+    pub run: String,
+    pub deleted_at: String,
+    pub files: Vec<DeletedFile>,
+}
+
+impl DeletionReport {
+    pub fn new(run: &str, deleted_at: &str, deleted: &[(String, SnapshotEntry)]) -> Self {
+        let files = deleted
+            .iter()
+            .map(|(url, entry)| DeletedFile {
+                file_url: url.clone(),
+                file_size: entry.file_size,
+                dt: entry.dt.clone(),
+            })
+            .collect();
+        Self {
+            run: run.to_string(),
+            deleted_at: deleted_at.to_string(),
+            files,
+        }
+    }
+
+    pub fn key(prefix_target: &str, run: &str) -> String {
+        format!("{prefix_target}{DELETIONS_DIR}id={run}-deletions.json")
+    }
+
+    pub async fn save(&self, storage: &dyn Storage, prefix_target: &str) -> Result<String> {
+        let key = Self::key(prefix_target, &self.run);
+        let data = serde_json::to_vec_pretty(self)?;
+        storage.put(&key, data.into()).await?;
+        Ok(key)
+    }
+}
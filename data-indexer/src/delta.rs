@@ -0,0 +1,590 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::compact::string_column;
+use crate::storage::{list_keys, read_file, ObjectPages, StorageRef};
+use crate::utils::aws::ObjectInfo;
+use crate::utils::constants::DELTA_LOG_DIR;
+use crate::utils::datafusion::{read_parquet, write_batches_with};
+use crate::writer_options::WriterOptions;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use datafusion::arrow::array::{Array, BooleanArray, RecordBatch};
+use datafusion::arrow::compute::filter_record_batch;
+use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+/// protocol of the tables written here, no table features
+const MIN_READER_VERSION: u32 = 1;
+const MIN_WRITER_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Protocol {
+    pub min_reader_version: u32,
+    pub min_writer_version: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Format {
+    pub provider: String,
+    #[serde(default)]
+    pub options: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Metadata {
+    pub id: String,
+    pub format: Format,
+    pub schema_string: String,
+    pub partition_columns: Vec<String>,
+    #[serde(default)]
+    pub configuration: HashMap<String, String>,
+    pub created_time: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Add {
+    /// relative to the table root
+    pub path: String,
+    #[serde(default)]
+    pub partition_values: HashMap<String, Option<String>>,
+    pub size: i64,
+    pub modification_time: i64,
+    pub data_change: bool,
+    /// json with numRecords and min and max of file_url
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Remove {
+    pub path: String,
+    pub deletion_timestamp: Option<i64>,
+    pub data_change: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<i64>,
+}
+
+/// one line of a commit, other actions of the protocol are ignored
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Action {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<Protocol>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta_data: Option<Metadata>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub add: Option<Add>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remove: Option<Remove>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit_info: Option<Value>,
+}
+
+/// table at its latest version, replayed from the log
+#[derive(Debug, Default)]
+pub struct TableState {
+    pub version: Option<u64>,
+    pub metadata: Option<Metadata>,
+    /// active files by path
+    pub files: BTreeMap<String, Add>,
+    /// deletion timestamp of removed files, they stay until vacuumed
+    pub removed: HashMap<String, i64>,
+}
+
+impl TableState {
+    pub fn apply(&mut self, action: Action) -> Result<()> {
+        if let Some(protocol) = action.protocol {
+            if protocol.min_reader_version > MIN_READER_VERSION || protocol.min_writer_version > MIN_WRITER_VERSION {
+                return Err(anyhow!(
+                    "delta table needs reader version: {} writer version: {}, supported are: {} and: {}",
+                    protocol.min_reader_version,
+                    protocol.min_writer_version,
+                    MIN_READER_VERSION,
+                    MIN_WRITER_VERSION
+                ));
+            }
+        }
+        if let Some(metadata) = action.meta_data {
+            self.metadata = Some(metadata);
+        }
+        if let Some(remove) = action.remove {
+            self.files.remove(&remove.path);
+            self.removed.insert(remove.path, remove.deletion_timestamp.unwrap_or_default());
+        }
+        if let Some(add) = action.add {
+            self.removed.remove(&add.path);
+            self.files.insert(add.path.clone(), add);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct Stats {
+    #[serde(default)]
+    min_values: HashMap<String, Value>,
+    #[serde(default)]
+    max_values: HashMap<String, Value>,
+}
+
+/// stats of a file with the rows of the urls, min and max let merges skip the file
+fn stats<'a>(urls: impl Iterator<Item = &'a str>, rows: Option<usize>) -> String {
+    let (mut min, mut max) = (None::<&str>, None::<&str>);
+    for url in urls {
+        min = Some(min.map_or(url, |x| x.min(url)));
+        max = Some(max.map_or(url, |x| x.max(url)));
+    }
+    let mut stats = json!({
+        "minValues": { "file_url": min },
+        "maxValues": { "file_url": max },
+    });
+    if let Some(rows) = rows {
+        stats["numRecords"] = json!(rows);
+    }
+    stats.to_string()
+}
+
+/// false only if the stats of the file exclude all urls
+fn may_contain(add: &Add, urls: &BTreeSet<String>) -> bool {
+    let Some(stats) = add.stats.as_deref().and_then(|x| serde_json::from_str::<Stats>(x).ok()) else {
+        return true;
+    };
+    let min = stats.min_values.get("file_url").and_then(|x| x.as_str());
+    let max = stats.max_values.get("file_url").and_then(|x| x.as_str());
+    match (min, max) {
+        (Some(min), Some(max)) => urls.range(min.to_string()..=max.to_string()).next().is_some(),
+        _ => true,
+    }
+}
+
+fn delta_type(data_type: &DataType) -> Result<Value> {
+    let value = match data_type {
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View => json!("string"),
+        DataType::Int64 => json!("long"),
+        DataType::Int32 => json!("integer"),
+        DataType::Int16 => json!("short"),
+        DataType::Int8 => json!("byte"),
+        DataType::Float64 => json!("double"),
+        DataType::Float32 => json!("float"),
+        DataType::Boolean => json!("boolean"),
+        DataType::Binary | DataType::LargeBinary => json!("binary"),
+        DataType::Date32 => json!("date"),
+        DataType::Timestamp(_, _) => json!("timestamp"),
+        DataType::List(field) | DataType::LargeList(field) => json!({
+            "type": "array",
+            "elementType": delta_type(field.data_type())?,
+            "containsNull": field.is_nullable(),
+        }),
+        DataType::Struct(fields) => json!({
+            "type": "struct",
+            "fields": fields.iter().map(|x| delta_field(x)).collect::<Result<Vec<_>>>()?,
+        }),
+        DataType::Map(entries, _) => {
+            let DataType::Struct(fields) = entries.data_type() else {
+                return Err(anyhow!("map without key and value: {data_type}"));
+            };
+            json!({
+                "type": "map",
+                "keyType": delta_type(fields[0].data_type())?,
+                "valueType": delta_type(fields[1].data_type())?,
+                "valueContainsNull": fields[1].is_nullable(),
+            })
+        }
+        other => return Err(anyhow!("no delta type for column type: {other}")),
+    };
+    Ok(value)
+}
+
+fn delta_field(field: &Field) -> Result<Value> {
+    Ok(json!({
+        "name": field.name(),
+        "type": delta_type(field.data_type())?,
+        "nullable": field.is_nullable(),
+        "metadata": {},
+    }))
+}
+
+/// `schemaString` of the table metadata
+pub fn delta_schema(schema: &Schema) -> Result<String> {
+    let fields = schema
+        .fields()
+        .iter()
+        .map(|x| delta_field(x))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(json!({ "type": "struct", "fields": fields }).to_string())
+}
+
+/// index as a delta lake table, parquet files next to `_delta_log/`,
+/// a commit is only written if its version does not exist yet, so readers
+/// see either all files of a run or none. No checkpoints are written,
+/// the log is replayed from the first version
+pub struct DeltaTable {
+    storage: StorageRef,
+    prefix: String,
+    options: WriterOptions,
+}
+
+impl DeltaTable {
+    pub fn new(storage: StorageRef, prefix: &str) -> Self {
+        Self {
+            storage,
+            prefix: prefix.to_string(),
+            options: WriterOptions::default(),
+        }
+    }
+
+    /// parquet settings of the files rewritten by merges
+    pub fn with_options(mut self, options: WriterOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    fn log_key(&self, version: u64) -> String {
+        format!("{}{DELTA_LOG_DIR}{version:020}.json", self.prefix)
+    }
+
+    /// key of a file of the table in the bucket
+    pub fn key(&self, path: &str) -> String {
+        format!("{}{path}", self.prefix)
+    }
+
+    pub async fn load(&self) -> Result<TableState> {
+        let log_prefix = format!("{}{DELTA_LOG_DIR}", self.prefix);
+        let mut versions = vec![];
+        for key in list_keys(self.storage.clone(), &log_prefix).await? {
+            let name = &key[log_prefix.len()..];
+            if name.contains(".checkpoint.") {
+                return Err(anyhow!("delta table: {} has checkpoints, they are not supported", self.prefix));
+            }
+            if let Some(version) = name.strip_suffix(".json").and_then(|x| x.parse::<u64>().ok()) {
+                versions.push((version, key));
+            }
+        }
+        versions.sort();
+
+        let mut state = TableState::default();
+        for (i, (version, key)) in versions.into_iter().enumerate() {
+            if version != i as u64 {
+                return Err(anyhow!("delta table: {} misses commit version: {}", self.prefix, i));
+            }
+            let data = read_file(self.storage.as_ref(), &key).await?;
+            for line in data.split(|x| *x == b'\n').filter(|x| !x.is_empty()) {
+                state.apply(serde_json::from_slice(line)?)?;
+            }
+            state.version = Some(version);
+        }
+        Ok(state)
+    }
+
+    /// writes the actions as the next version of the state and applies them,
+    /// fails if another writer committed that version first
+    pub async fn commit(&self, state: &mut TableState, operation: &str, actions: Vec<Action>) -> Result<u64> {
+        let version = state.version.map(|x| x + 1).unwrap_or(0);
+        let commit_info = Action {
+            commit_info: Some(json!({
+                "timestamp": Utc::now().timestamp_millis(),
+                "operation": operation,
+                "engineInfo": format!("data-indexer/{}", env!("CARGO_PKG_VERSION")),
+            })),
+            ..Default::default()
+        };
+        let lines = std::iter::once(&commit_info)
+            .chain(actions.iter())
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        let data = Bytes::from(lines.join("\n"));
+        if !self.storage.put_if_absent(&self.log_key(version), data).await? {
+            return Err(anyhow!(
+                "version: {version} of delta table: {} was committed by another writer",
+                self.prefix
+            ));
+        }
+        for action in actions {
+            state.apply(action)?;
+        }
+        state.version = Some(version);
+        Ok(version)
+    }
+
+    /// parquet files under the table prefix, also the ones not in the log
+    async fn data_files(&self) -> Result<HashMap<String, ObjectInfo>> {
+        let log_prefix = format!("{}{DELTA_LOG_DIR}", self.prefix);
+        let mut pages = ObjectPages::new(self.storage.clone(), &self.prefix, None);
+        let mut files = HashMap::new();
+        while let Some(page) = pages.next_page().await? {
+            files.extend(
+                page.into_iter()
+                    .filter(|(key, _)| key.ends_with(".parquet") && !key.starts_with(&log_prefix)),
+            );
+        }
+        Ok(files)
+    }
+
+    /// commits the written files as upserts: rows of the table with a file_url of the
+    /// written rows are replaced, the files holding them are rewritten without those rows.
+    /// Returns the table after the commit
+    pub async fn merge(&self, keys: &[String], urls: &BTreeSet<String>, schema: &Schema) -> Result<TableState> {
+        let mut state = self.load().await?;
+        let now = Utc::now().timestamp_millis();
+        let mut actions = vec![];
+        if state.version.is_none() {
+            actions.push(Action {
+                protocol: Some(Protocol {
+                    min_reader_version: MIN_READER_VERSION,
+                    min_writer_version: MIN_WRITER_VERSION,
+                }),
+                ..Default::default()
+            });
+        }
+        let schema_string = delta_schema(schema)?;
+        if state.metadata.as_ref().map(|x| &x.schema_string) != Some(&schema_string) {
+            let metadata = Metadata {
+                id: state
+                    .metadata
+                    .as_ref()
+                    .map(|x| x.id.clone())
+                    .unwrap_or_else(|| Uuid::new_v4().to_string()),
+                format: Format {
+                    provider: "parquet".to_string(),
+                    options: HashMap::new(),
+                },
+                schema_string,
+                partition_columns: vec![],
+                configuration: HashMap::new(),
+                created_time: Some(now),
+            };
+            actions.push(Action {
+                meta_data: Some(metadata),
+                ..Default::default()
+            });
+        }
+
+        let mut rewritten = vec![];
+        for (path, add) in state.files.iter().filter(|(_, add)| may_contain(add, urls)) {
+            let (batches, replaced) = self.without_urls(&self.key(path), urls).await?;
+            if replaced == 0 {
+                continue;
+            }
+            tracing::info!("rewriting delta file: {} without {} replaced rows", path, replaced);
+            actions.push(Action {
+                remove: Some(Remove {
+                    path: path.clone(),
+                    deletion_timestamp: Some(now),
+                    data_change: true,
+                    size: Some(add.size),
+                }),
+                ..Default::default()
+            });
+            let rows = batches.iter().map(|x| x.num_rows()).sum::<usize>();
+            if rows == 0 {
+                continue;
+            }
+            let mut kept = vec![];
+            for batch in &batches {
+                let col = string_column(batch, "file_url")?;
+                kept.extend((0..col.len()).filter(|i| col.is_valid(*i)).map(|i| col.value(i).to_string()));
+            }
+            let new_path = format!("id={}-table=data_index.parquet", Uuid::new_v4());
+            write_batches_with(self.storage.as_ref(), &self.key(&new_path), batches, &self.options).await?;
+            rewritten.push((new_path, stats(kept.iter().map(|x| x.as_str()), Some(rows))));
+        }
+
+        let files = self.data_files().await?;
+        let written = keys
+            .iter()
+            .map(|key| {
+                let path = key
+                    .strip_prefix(&self.prefix)
+                    .ok_or_else(|| anyhow!("file: {key} is not in delta table: {}", self.prefix))?;
+                Ok((path.to_string(), stats(urls.iter().map(|x| x.as_str()), None)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (path, stats) in written.into_iter().chain(rewritten) {
+            let size = files
+                .get(&self.key(&path))
+                .and_then(|x| x.size)
+                .ok_or_else(|| anyhow!("written file: {} not found", self.key(&path)))?;
+            actions.push(Action {
+                add: Some(Add {
+                    path,
+                    partition_values: HashMap::new(),
+                    size,
+                    modification_time: now,
+                    data_change: true,
+                    stats: Some(stats),
+                }),
+                ..Default::default()
+            });
+        }
+
+        let version = self.commit(&mut state, "MERGE", actions).await?;
+        tracing::info!("committed version: {} of delta table: {}", version, self.prefix);
+        Ok(state)
+    }
+
+    /// batches of the file without the rows of the urls and the number of rows dropped
+    async fn without_urls(&self, key: &str, urls: &BTreeSet<String>) -> Result<(Vec<RecordBatch>, usize)> {
+        let mut batches = vec![];
+        let mut replaced = 0;
+        for batch in read_parquet(self.storage.as_ref(), key).await? {
+            let col = string_column(&batch, "file_url")?;
+            let mask = (0..col.len())
+                .map(|i| col.is_null(i) || !urls.contains(col.value(i)))
+                .collect::<Vec<_>>();
+            let mask = BooleanArray::from(mask);
+            replaced += batch.num_rows() - mask.true_count();
+            batches.push(filter_record_batch(&batch, &mask)?);
+        }
+        Ok((batches, replaced))
+    }
+
+    /// files not in the table any more, removed ones once they are removed for longer
+    /// than the retention, files never committed once they are older than it
+    pub async fn stale_files(&self, retention_hours: u64) -> Result<Vec<String>> {
+        let state = self.load().await?;
+        let cutoff = Utc::now() - chrono::Duration::hours(retention_hours as i64);
+        let mut stale = vec![];
+        for (key, info) in self.data_files().await? {
+            let path = &key[self.prefix.len()..];
+            if state.files.contains_key(path) {
+                continue;
+            }
+            let since = match state.removed.get(path) {
+                Some(timestamp) => DateTime::from_timestamp_millis(*timestamp),
+                None => info
+                    .last_modified
+                    .as_deref()
+                    .and_then(|x| DateTime::parse_from_rfc3339(x).ok())
+                    .map(|x| x.with_timezone(&Utc)),
+            };
+            if since.is_some_and(|x| x <= cutoff) {
+                stale.push(key);
+            }
+        }
+        stale.sort();
+        Ok(stale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::file_data::FileData;
+    use crate::storage::ObjectStoreStorage;
+    use crate::utils::datafusion::write_batches;
+
+    fn record(key: &str, size: i64) -> FileData {
+        let info = ObjectInfo {
+            size: Some(size),
+            ..Default::default()
+        };
+        FileData::new("raw", key.to_string(), info)
+    }
+
+    async fn write(table: &DeltaTable, records: &[FileData]) -> Result<TableState> {
+        let key = table.key(&format!("id={}-table=data_index.parquet", Uuid::new_v4()));
+        let batch = FileData::to_record_batch(records)?;
+        write_batches(table.storage.as_ref(), &key, vec![batch]).await?;
+        let urls = records.iter().filter_map(|x| x.file_url.clone()).collect();
+        table.merge(&[key], &urls, &FileData::schema()).await
+    }
+
+    async fn sizes(table: &DeltaTable, state: &TableState) -> Result<BTreeMap<String, i64>> {
+        let mut sizes = BTreeMap::new();
+        for path in state.files.keys() {
+            for batch in read_parquet(table.storage.as_ref(), &table.key(path)).await? {
+                let urls = string_column(&batch, "file_url")?;
+                let size = batch.column_by_name("file_size").unwrap();
+                let size = size.as_any().downcast_ref::<datafusion::arrow::array::Int64Array>().unwrap();
+                for i in 0..batch.num_rows() {
+                    sizes.insert(urls.value(i).to_string(), size.value(i));
+                }
+            }
+        }
+        Ok(sizes)
+    }
+
+    #[tokio::test]
+    async fn test_merge() -> Result<()> {
+        let storage: StorageRef = Arc::new(ObjectStoreStorage::memory("memory://index"));
+        let table = DeltaTable::new(storage.clone(), "index/combined/");
+
+        let state = write(&table, &[record("a.csv", 1), record("b.csv", 1)]).await?;
+        assert_eq!(state.version, Some(0));
+        assert!(state.metadata.is_some());
+
+        // b.csv is replaced, the first file is rewritten with a.csv only
+        let state = write(&table, &[record("b.csv", 2), record("c.csv", 2)]).await?;
+        assert_eq!(state.version, Some(1));
+        assert_eq!(state.files.len(), 2);
+        assert_eq!(state.removed.len(), 1);
+        let expected = BTreeMap::from([
+            ("s3://raw/a.csv".to_string(), 1),
+            ("s3://raw/b.csv".to_string(), 2),
+            ("s3://raw/c.csv".to_string(), 2),
+        ]);
+        assert_eq!(sizes(&table, &state).await?, expected);
+
+        // the replayed log matches the state of the commits
+        let loaded = table.load().await?;
+        assert_eq!(loaded.files, state.files);
+        assert_eq!(loaded.version, Some(1));
+
+        // the removed file is kept for the retention
+        assert!(table.stale_files(1).await?.is_empty());
+        assert_eq!(table.stale_files(0).await?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_commit_conflict() -> Result<()> {
+        let storage: StorageRef = Arc::new(ObjectStoreStorage::memory("memory://index"));
+        let table = DeltaTable::new(storage, "index/combined/");
+        let mut first = TableState::default();
+        let mut second = TableState::default();
+        table.commit(&mut first, "WRITE", vec![]).await?;
+        assert!(table.commit(&mut second, "WRITE", vec![]).await.is_err());
+        assert_eq!(second.version, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_may_contain() {
+        let urls = BTreeSet::from(["s3://raw/b.csv".to_string()]);
+        let add = |stats: Option<String>| Add {
+            path: "a.parquet".to_string(),
+            partition_values: HashMap::new(),
+            size: 1,
+            modification_time: 0,
+            data_change: true,
+            stats,
+        };
+        assert!(may_contain(&add(None), &urls));
+        assert!(may_contain(&add(Some(stats(["s3://raw/a.csv", "s3://raw/c.csv"].into_iter(), None))), &urls));
+        assert!(!may_contain(&add(Some(stats(["s3://raw/c.csv"].into_iter(), Some(1)))), &urls));
+    }
+
+    #[test]
+    fn test_delta_schema() -> Result<()> {
+        let schema: Value = serde_json::from_str(&delta_schema(&FileData::schema())?)?;
+        assert_eq!(schema["fields"][0], json!({"name": "file_name", "type": "string", "nullable": true, "metadata": {}}));
+        let metadata = schema["fields"]
+            .as_array()
+            .and_then(|x| x.iter().find(|f| f["name"] == "metadata"))
+            .expect("metadata column");
+        assert_eq!(metadata["type"]["type"], "struct");
+        Ok(())
+    }
+}
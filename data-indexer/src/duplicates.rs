@@ -0,0 +1,204 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::compact::string_column;
+use crate::storage::Storage;
+use crate::utils::datafusion::{read_parquet, write_batches};
+
+use anyhow::Result;
+use datafusion::arrow::array::{Array, AsArray, Int64Array, ListBuilder, RecordBatch, StringArray, StringBuilder};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, Field, Int64Type, Schema, SchemaRef};
+
+pub const DUPLICATES_FILE: &str = "duplicates.parquet";
+
+/// schema of the duplicates table, one row per content hash found under several urls
+pub fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("content_sha256", DataType::Utf8, false),
+        Field::new("file_size", DataType::Int64, true),
+        Field::new("copies", DataType::Int64, false),
+        Field::new("wasted_bytes", DataType::Int64, true),
+        Field::new(
+            "file_urls",
+            DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+            false,
+        ),
+    ]))
+}
+
+#[derive(Debug)]
+struct HashEntry {
+    /// dt and whether the row is a tombstone, as in compaction
+    version: (Option<String>, bool),
+    hash: Option<String>,
+    file_size: Option<i64>,
+}
+
+/// content hash of the newest row per file_url over all index files
+#[derive(Debug, Default)]
+pub struct Hashes {
+    entries: HashMap<String, HashEntry>,
+}
+
+impl Hashes {
+    pub fn add(&mut self, batch: &RecordBatch) -> Result<()> {
+        let file_urls = string_column(batch, "file_url")?;
+        let dts = string_column(batch, "dt")?;
+        let deleted_ats = string_column(batch, "deleted_at").ok();
+        let hashes = string_column(batch, "content_sha256").ok();
+        let sizes = match batch.column_by_name("file_size") {
+            Some(col) => Some(cast(col, &DataType::Int64)?),
+            None => None,
+        };
+        let sizes = sizes.as_ref().map(|x| x.as_primitive::<Int64Type>());
+        for i in 0..batch.num_rows() {
+            if file_urls.is_null(i) {
+                continue;
+            }
+            let dt = dts.is_valid(i).then(|| dts.value(i).to_string());
+            let deleted = deleted_ats.as_ref().is_some_and(|x| x.is_valid(i));
+            let version = (dt, deleted);
+            if matches!(self.entries.get(file_urls.value(i)), Some(current) if current.version > version) {
+                continue;
+            }
+            let entry = HashEntry {
+                version,
+                hash: hashes.as_ref().filter(|x| x.is_valid(i)).map(|x| x.value(i).to_string()),
+                file_size: sizes.filter(|x| x.is_valid(i)).map(|x| x.value(i)),
+            };
+            self.entries.insert(file_urls.value(i).to_string(), entry);
+        }
+        Ok(())
+    }
+
+    /// hashes shared by existing files, most wasted bytes first
+    pub fn duplicates(&self) -> Result<RecordBatch> {
+        let mut groups: BTreeMap<&str, (Option<i64>, Vec<&str>)> = BTreeMap::new();
+        for (url, entry) in &self.entries {
+            let Some(hash) = entry.hash.as_deref().filter(|_| !entry.version.1) else {
+                continue;
+            };
+            let group = groups.entry(hash).or_default();
+            group.0 = group.0.or(entry.file_size);
+            group.1.push(url);
+        }
+        let mut rows = groups
+            .into_iter()
+            .filter(|(_, (_, urls))| urls.len() > 1)
+            .map(|(hash, (size, mut urls))| {
+                urls.sort();
+                let wasted = size.map(|x| x * (urls.len() as i64 - 1));
+                (hash, size, urls, wasted)
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| b.3.cmp(&a.3).then(a.0.cmp(b.0)));
+
+        let mut file_urls = ListBuilder::new(StringBuilder::new());
+        for (_, _, urls, _) in &rows {
+            file_urls.append_value(urls.iter().map(Some));
+        }
+        let file_urls = file_urls.finish();
+        Ok(RecordBatch::try_new(
+            schema(),
+            vec![
+                Arc::new(StringArray::from(rows.iter().map(|x| x.0).collect::<Vec<_>>())),
+                Arc::new(Int64Array::from(rows.iter().map(|x| x.1).collect::<Vec<_>>())),
+                Arc::new(Int64Array::from(rows.iter().map(|x| x.2.len() as i64).collect::<Vec<_>>())),
+                Arc::new(Int64Array::from(rows.iter().map(|x| x.3).collect::<Vec<_>>())),
+                Arc::new(file_urls),
+            ],
+        )?)
+    }
+}
+
+/// rebuilds the duplicates table from the index files,
+/// only the hash of each file is kept in memory
+pub async fn write_duplicates(
+    storage: &dyn Storage,
+    keys: &[String],
+    duplicates_prefix: &str,
+) -> Result<String> {
+    let mut hashes = Hashes::default();
+    for key in keys {
+        tracing::info!("reading index file: {}", key);
+        for batch in read_parquet(storage, key).await? {
+            hashes.add(&batch)?;
+        }
+    }
+
+    let batch = hashes.duplicates()?;
+    let key = format!("{duplicates_prefix}{DUPLICATES_FILE}");
+    tracing::info!("writing {} duplicated hashes to: {}", batch.num_rows(), key);
+    write_batches(storage, &key, vec![batch]).await?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use datafusion::arrow::array::ArrayRef;
+
+    fn index_batch(rows: &[(&str, &str, Option<&str>, i64, bool)]) -> RecordBatch {
+        let schema = Schema::new(vec![
+            Field::new("file_url", DataType::Utf8, true),
+            Field::new("dt", DataType::Utf8, true),
+            Field::new("content_sha256", DataType::Utf8, true),
+            Field::new("file_size", DataType::Int64, true),
+            Field::new("deleted_at", DataType::Utf8, true),
+        ]);
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(rows.iter().map(|x| x.0).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(rows.iter().map(|x| x.1).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(rows.iter().map(|x| x.2).collect::<Vec<_>>())),
+            Arc::new(Int64Array::from(rows.iter().map(|x| x.3).collect::<Vec<_>>())),
+            Arc::new(StringArray::from(rows.iter().map(|x| x.4.then_some("2024-03-01")).collect::<Vec<_>>())),
+        ];
+        RecordBatch::try_new(Arc::new(schema), columns).unwrap()
+    }
+
+    #[test]
+    fn test_duplicates() -> Result<()> {
+        let mut hashes = Hashes::default();
+        hashes.add(&index_batch(&[
+            ("s3://a/1.csv", "2024-01-01", Some("aa"), 10, false),
+            ("s3://a/2.csv", "2024-01-01", Some("aa"), 10, false),
+            ("s3://b/1.csv", "2024-01-01", Some("aa"), 10, false),
+            ("s3://a/3.bin", "2024-01-01", Some("bb"), 100, false),
+            ("s3://a/4.bin", "2024-01-01", Some("bb"), 100, false),
+            ("s3://a/5.bin", "2024-01-01", Some("cc"), 5, false),
+            ("s3://a/6.bin", "2024-01-01", None, 5, false),
+        ]))?;
+        // 4.bin changed, 2.csv was deleted
+        hashes.add(&index_batch(&[
+            ("s3://a/4.bin", "2024-02-01", Some("dd"), 100, false),
+            ("s3://a/2.csv", "2024-01-01", Some("aa"), 10, true),
+        ]))?;
+
+        let batch = hashes.duplicates()?;
+        assert_eq!(batch.num_rows(), 1);
+        assert_eq!(batch.column(0).as_string::<i32>().value(0), "aa");
+        assert_eq!(batch.column(2).as_primitive::<Int64Type>().value(0), 2);
+        assert_eq!(batch.column(3).as_primitive::<Int64Type>().value(0), 10);
+        let urls = batch.column(4).as_list::<i32>().value(0);
+        let urls = urls.as_string::<i32>().iter().flatten().collect::<Vec<_>>();
+        assert_eq!(urls, vec!["s3://a/1.csv", "s3://b/1.csv"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_wasted_bytes_order() -> Result<()> {
+        let mut hashes = Hashes::default();
+        hashes.add(&index_batch(&[
+            ("s3://a/1", "2024-01-01", Some("aa"), 10, false),
+            ("s3://a/2", "2024-01-01", Some("aa"), 10, false),
+            ("s3://a/3", "2024-01-01", Some("bb"), 100, false),
+            ("s3://a/4", "2024-01-01", Some("bb"), 100, false),
+        ]))?;
+        let batch = hashes.duplicates()?;
+        let wasted = batch.column(3).as_primitive::<Int64Type>().values().to_vec();
+        assert_eq!(wasted, vec![100, 10]);
+        Ok(())
+    }
+}
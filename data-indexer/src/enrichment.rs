@@ -0,0 +1,134 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::file_data::FileData;
+use crate::storage::StorageRef;
+use crate::utils::constants::*;
+
+use anyhow::Result;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// runs the request until it succeeds or `ENRICH_MAX_ATTEMPTS` are used, doubling the delay between tries
+async fn retry<T, F, Fut>(key: &str, mut request: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut delay = Duration::from_millis(ENRICH_RETRY_DELAY_MS);
+    let mut attempt = 1;
+    loop {
+        match request().await {
+            Err(e) if attempt < ENRICH_MAX_ATTEMPTS => {
+                tracing::debug!("retrying request of: {key} after attempt {attempt}: {e:?}");
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+/// adds tags, user metadata and the head of the objects to the records,
+/// files still failing after the retries are logged and skipped, returns their number
+pub async fn enrich_records(storage: StorageRef, records: &mut [FileData]) -> Result<usize> {
+    let sem = Arc::new(Semaphore::new(ENRICH_WORKERS));
+    let mut tasks = JoinSet::new();
+    for (i, record) in records.iter().enumerate() {
+        let Some(key) = &record.file_path else {
+            continue;
+        };
+        if !record.is_current() {
+            continue;
+        }
+        let permit = sem.clone().acquire_owned().await?;
+        let storage = storage.clone();
+        let key = key.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            let res = async {
+                let head = retry(&key, || storage.head(&key)).await?;
+                let tags = retry(&key, || storage.tags(&key)).await?;
+                anyhow::Ok((head, tags))
+            }
+            .await;
+            (i, key, res)
+        });
+    }
+
+    let mut errors = 0;
+    while let Some(task) = tasks.join_next().await {
+        match task? {
+            (i, _, Ok((head, tags))) => {
+                records[i].with_head(head);
+                records[i].tags = Some(tags);
+            }
+            (_, key, Err(e)) => {
+                tracing::warn!("failed to enrich file: {key}: {e:?}");
+                errors += 1;
+            }
+        }
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use crate::storage::ObjectStoreStorage;
+    use crate::utils::aws::ObjectInfo;
+
+    use anyhow::anyhow;
+    use datafusion::arrow::array::{Array, AsArray};
+
+    #[tokio::test]
+    async fn test_retry() -> Result<()> {
+        let calls = AtomicU32::new(0);
+        let res = retry("a.csv", || async {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 => Err(anyhow!("slow down")),
+                n => Ok(n),
+            }
+        })
+        .await?;
+        assert_eq!(res, 1);
+
+        calls.store(0, Ordering::SeqCst);
+        let res: Result<()> = retry("a.csv", || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Err(anyhow!("access denied"))
+        })
+        .await;
+        assert!(res.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), ENRICH_MAX_ATTEMPTS);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_enrich_records() -> Result<()> {
+        let storage: StorageRef = Arc::new(ObjectStoreStorage::memory("memory://raw"));
+        storage.put("a.csv", bytes::Bytes::from("1")).await?;
+        let mut records = vec![
+            FileData::new("memory://raw", "a.csv".to_string(), ObjectInfo::default()),
+            FileData::new("memory://raw", "missing.csv".to_string(), ObjectInfo::default()),
+            FileData::tombstone("memory://raw", "b.csv".to_string(), ObjectInfo::default(), "2024-05-02"),
+        ];
+        assert_eq!(enrich_records(storage, &mut records).await?, 1);
+        assert_eq!(records[0].tags, Some(BTreeMap::new()));
+        assert!(records[1].tags.is_none() && records[2].tags.is_none());
+
+        records[0].tags = Some(BTreeMap::from([("team".to_string(), "mri".to_string())]));
+        let batch = FileData::to_record_batch(&records)?;
+        let tags = batch.column_by_name("tags").expect("tags column").as_map();
+        assert_eq!(tags.value(0).column(0).as_string::<i32>().value(0), "team");
+        assert_eq!(tags.value(0).column(1).as_string::<i32>().value(0), "mri");
+        assert!(tags.is_null(1));
+        Ok(())
+    }
+}
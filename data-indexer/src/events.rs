@@ -0,0 +1,378 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::config::{Config, OutputFormat};
+use crate::delta::DeltaTable;
+use crate::file_data::FileData;
+use crate::index_writer::IndexWriter;
+use crate::inventory::{decode_key, format_dt};
+use crate::source::{process_page, Source, SourceOptions};
+use crate::storage::{object_url, Stores};
+use crate::utils::aws::ObjectInfo;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    Created,
+    Removed,
+}
+
+/// change of one object from an s3 event notification
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectEvent {
+    pub kind: EventKind,
+    pub bucket: String,
+    pub key: String,
+    /// same format as the listed last modified
+    pub time: String,
+    pub size: Option<i64>,
+    pub etag: Option<String>,
+    pub version_id: Option<String>,
+    /// the removal left a delete marker in a versioned bucket
+    pub delete_marker: bool,
+    /// orders the events of one key, the event time has no more than millisecond resolution
+    pub sequencer: Option<String>,
+}
+
+impl ObjectEvent {
+    /// order of two events of the same object, by the sequencers if both have one
+    pub fn order(&self, other: &Self) -> Ordering {
+        match (&self.sequencer, &other.sequencer) {
+            (Some(a), Some(b)) => compare_sequencers(a, b),
+            _ => self.time.cmp(&other.time),
+        }
+    }
+}
+
+/// sequencers are hex strings of varying length, the shorter one is padded with zeros on the right
+pub fn compare_sequencers(a: &str, b: &str) -> Ordering {
+    let len = a.len().max(b.len());
+    format!("{a:0<len$}").cmp(&format!("{b:0<len$}"))
+}
+
+#[derive(Deserialize, Debug)]
+struct BucketName {
+    name: String,
+}
+
+/// record of an s3 event notification, keys are url encoded
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct S3Record {
+    event_name: String,
+    event_time: String,
+    s3: S3Entity,
+}
+
+#[derive(Deserialize, Debug)]
+struct S3Entity {
+    bucket: BucketName,
+    object: S3Object,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct S3Object {
+    key: String,
+    size: Option<i64>,
+    e_tag: Option<String>,
+    version_id: Option<String>,
+    sequencer: Option<String>,
+}
+
+impl S3Record {
+    fn event(self) -> Option<ObjectEvent> {
+        let kind = match self.event_name.split(':').next() {
+            Some("ObjectCreated") => EventKind::Created,
+            Some("ObjectRemoved" | "LifecycleExpiration") => EventKind::Removed,
+            _ => {
+                tracing::debug!("skipping event: {} of: {}", self.event_name, self.s3.object.key);
+                return None;
+            }
+        };
+        let object = self.s3.object;
+        Some(ObjectEvent {
+            kind,
+            bucket: self.s3.bucket.name,
+            key: decode_key(&object.key),
+            time: format_dt(&self.event_time).unwrap_or(self.event_time),
+            size: object.size,
+            etag: object.e_tag.map(|x| x.trim_matches('"').to_string()),
+            version_id: object.version_id,
+            delete_marker: self.event_name.ends_with("DeleteMarkerCreated"),
+            sequencer: object.sequencer,
+        })
+    }
+}
+
+/// s3 event sent to eventbridge, keys are not encoded
+#[derive(Deserialize, Debug)]
+struct BridgeEvent {
+    #[serde(rename = "detail-type")]
+    detail_type: String,
+    time: String,
+    detail: BridgeDetail,
+}
+
+#[derive(Deserialize, Debug)]
+struct BridgeDetail {
+    bucket: BucketName,
+    object: BridgeObject,
+    #[serde(rename = "deletion-type")]
+    deletion_type: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BridgeObject {
+    key: String,
+    size: Option<i64>,
+    etag: Option<String>,
+    #[serde(rename = "version-id")]
+    version_id: Option<String>,
+    sequencer: Option<String>,
+}
+
+impl BridgeEvent {
+    fn event(self) -> Option<ObjectEvent> {
+        let kind = match self.detail_type.as_str() {
+            "Object Created" => EventKind::Created,
+            "Object Deleted" => EventKind::Removed,
+            _ => {
+                tracing::debug!("skipping event: {} of: {}", self.detail_type, self.detail.object.key);
+                return None;
+            }
+        };
+        let detail = self.detail;
+        Some(ObjectEvent {
+            kind,
+            bucket: detail.bucket.name,
+            key: detail.object.key,
+            time: format_dt(&self.time).unwrap_or(self.time),
+            size: detail.object.size,
+            etag: detail.object.etag,
+            version_id: detail.object.version_id,
+            delete_marker: detail.deletion_type.as_deref() == Some("Delete Marker Created"),
+            sequencer: detail.object.sequencer,
+        })
+    }
+}
+
+/// object events of a lambda payload: s3 notifications sent directly, through sqs or sns,
+/// and eventbridge events, test events and other event types are skipped
+pub fn parse_events(payload: &Value) -> Result<Vec<ObjectEvent>> {
+    let nested = |text: &str| parse_events(&serde_json::from_str(text)?);
+    let mut events = vec![];
+    if let Some(records) = payload.get("Records").and_then(Value::as_array) {
+        for record in records {
+            if let Some(body) = record.get("body").and_then(Value::as_str) {
+                events.extend(nested(body)?);
+            } else if let Some(message) = record.pointer("/Sns/Message").and_then(Value::as_str) {
+                events.extend(nested(message)?);
+            } else if record.get("s3").is_some() {
+                let record: S3Record = serde_json::from_value(record.clone())?;
+                events.extend(record.event());
+            } else {
+                tracing::warn!("skipping record without s3 event: {record}");
+            }
+        }
+    } else if payload.get("source").and_then(Value::as_str) == Some("aws.s3") {
+        let event: BridgeEvent = serde_json::from_value(payload.clone())?;
+        events.extend(event.event());
+    } else if let Some(message) = payload.get("Message").and_then(Value::as_str) {
+        // sns envelope of an sqs message without raw delivery
+        events.extend(nested(message)?);
+    } else if payload.get("Event").and_then(Value::as_str) == Some("s3:TestEvent") {
+        tracing::info!("skipping s3 test event");
+    } else {
+        return Err(anyhow!("unsupported event: {payload}"));
+    }
+    Ok(events)
+}
+
+/// newest event per object, or per version with versions, events without an order keep the later one
+pub fn latest_events(events: Vec<ObjectEvent>, versions: bool) -> Vec<ObjectEvent> {
+    let mut latest = BTreeMap::<_, ObjectEvent>::new();
+    for event in events {
+        let version = event.version_id.clone().filter(|_| versions);
+        let key = (event.bucket.clone(), event.key.clone(), version);
+        let older = latest.get(&key).is_some_and(|x| event.order(x) == Ordering::Less);
+        if !older {
+            latest.insert(key, event);
+        }
+    }
+    latest.into_values().collect()
+}
+
+/// upsert or tombstone of the event, `None` if no source contains the object
+fn record(event: ObjectEvent, sources: &[Source], versions: bool) -> Option<FileData> {
+    let url = object_url(&event.bucket, &event.key);
+    let source = sources
+        .iter()
+        .find(|x| x.key(&url).is_some_and(|key| key.starts_with(&x.prefix)))?;
+    // a delete marker is a version of its own, other removals end the row
+    let removed = event.kind == EventKind::Removed && !(versions && event.delete_marker);
+    let info = ObjectInfo {
+        size: event.size,
+        last_modified: Some(event.time.clone()),
+        etag: event.etag,
+        version_id: event.version_id.filter(|_| versions),
+        is_latest: (versions && !removed).then_some(true),
+        is_delete_marker: versions.then_some(event.delete_marker),
+        ..Default::default()
+    };
+    let record = if removed {
+        FileData::tombstone(&source.bucket, event.key, info, &event.time)
+    } else {
+        FileData::new(&source.bucket, event.key, info)
+    };
+    Some(record.with_dataset(source.dataset.as_deref()))
+}
+
+/// counts of one event batch
+#[derive(Serialize, Debug, Default, PartialEq)]
+pub struct EventSummary {
+    pub events: usize,
+    pub upserts: usize,
+    pub tombstones: usize,
+    /// objects outside of the configured sources
+    pub skipped: usize,
+    /// files whose head, metadata or hash could not be read
+    pub errors: usize,
+    pub outputs: Vec<String>,
+}
+
+/// writes the changes of the events as a small index file next to the run files for the next
+/// compaction, or merges them into the delta table; only the newest event per object is kept.
+/// The watermark is left to the batch runs, which index these objects again if incremental.
+/// With versions the rows of older versions keep is_latest until the next full run
+pub async fn index_events(stores: &Stores, config: &Config, payload: &Value) -> Result<EventSummary> {
+    let events = parse_events(payload)?;
+    let sources = config.sources()?;
+    let versions = config.args.with_versions();
+    let mut summary = EventSummary {
+        events: events.len(),
+        ..Default::default()
+    };
+
+    let mut pages = BTreeMap::<String, Vec<FileData>>::new();
+    for event in latest_events(events, versions) {
+        match record(event, &sources, versions) {
+            Some(record) => pages.entry(record.source_bucket.clone().unwrap_or_default()).or_default().push(record),
+            None => summary.skipped += 1,
+        }
+    }
+
+    let options = SourceOptions::from_config(config);
+    let mut records = vec![];
+    for (bucket, mut page) in pages {
+        summary.errors += process_page(stores.get(&bucket)?, &mut page, &options).await?;
+        records.extend(page);
+    }
+    summary.tombstones = records.iter().filter(|x| x.deleted_at.is_some()).count();
+    summary.upserts = records.len() - summary.tombstones;
+    tracing::info!("upserts: {} tombstones: {} from events: {}", summary.upserts, summary.tombstones, summary.events);
+    if records.is_empty() || config.args.is_dry_run() {
+        return Ok(summary);
+    }
+
+    let target = stores.get(&config.bucket_target)?;
+    let id = Uuid::new_v4().to_string();
+    let mut writer = IndexWriter::for_run(target.clone(), config, &format!("id={id}-table=data_events.parquet"))?;
+    writer.write(&records).await?;
+    let schema = writer.schema();
+    summary.outputs = writer.finish().await?;
+    tracing::info!("written event files: {:?}", summary.outputs);
+    if config.args.output() == OutputFormat::Delta {
+        let urls = records.iter().filter_map(|x| x.file_url.clone()).collect();
+        let table = DeltaTable::new(target, config.args.combined_prefix()).with_options(config.args.writer_options()?);
+        table.merge(&summary.outputs, &urls, &schema).await?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    fn fixture(name: &str) -> Result<Value> {
+        let path = format!("{}/tests/fixtures/events/{name}", env!("CARGO_MANIFEST_DIR"));
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    #[rstest]
+    #[case("s3.json", vec![("mri/scan 1+2.csv", EventKind::Created), ("mri/old.csv", EventKind::Removed)])]
+    #[case("sqs.json", vec![("mri/big.dcm", EventKind::Created), ("mri/big.dcm", EventKind::Removed)])]
+    #[case("eventbridge.json", vec![("mri/scan 2.csv", EventKind::Created)])]
+    fn test_parse_events(#[case] name: &str, #[case] expected: Vec<(&str, EventKind)>) -> Result<()> {
+        let events = parse_events(&fixture(name)?)?;
+        let kinds = events.iter().map(|x| (x.key.as_str(), x.kind)).collect::<Vec<_>>();
+        assert_eq!(kinds, expected);
+        assert!(events.iter().all(|x| x.bucket == "raw"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_s3_event() -> Result<()> {
+        let events = parse_events(&fixture("s3.json")?)?;
+        assert_eq!(events[0].time, "2024-05-02T10:00:00Z");
+        assert_eq!(events[0].size, Some(1024));
+        assert_eq!(events[0].version_id.as_deref(), Some("3HL4kqtJlcpXroDTDmJ+rmSpXd3dIbrHY"));
+        assert!(events[1].delete_marker);
+        assert!(parse_events(&serde_json::json!({"foo": 1})).is_err());
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("0066336E1A3B2C4D5F", "0066336E1A3B2C4D60", Ordering::Less)]
+    #[case("0066336E1A3B2C4D6", "0066336E1A3B2C4D5F", Ordering::Greater)]
+    #[case("0066336E1A3B2C4D6", "0066336E1A3B2C4D60", Ordering::Equal)]
+    fn test_compare_sequencers(#[case] a: &str, #[case] b: &str, #[case] expected: Ordering) {
+        assert_eq!(compare_sequencers(a, b), expected);
+    }
+
+    #[test]
+    fn test_latest_events() -> Result<()> {
+        // events of the same time, the delete of a.csv is delivered after its later put
+        let events = parse_events(&fixture("same_time.json")?)?;
+        assert!(events.iter().all(|x| x.time == events[0].time));
+        let latest = latest_events(events, false);
+        let kinds = latest.iter().map(|x| (x.key.as_str(), x.kind)).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![("mri/a.csv", EventKind::Created), ("mri/b.csv", EventKind::Removed)]);
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(false, None, Some("2024-05-02T10:05:00Z"))]
+    #[case(true, Some(true), None)]
+    fn test_record_delete_marker(
+        #[case] versions: bool,
+        #[case] is_delete_marker: Option<bool>,
+        #[case] deleted_at: Option<&str>,
+    ) -> Result<()> {
+        let sources = vec![Source {
+            bucket: "raw".to_string(),
+            prefix: "mri/".to_string(),
+            dataset: Some("mri".to_string()),
+            region: None,
+            profile: None,
+            inventory: None,
+        }];
+        let event = parse_events(&fixture("s3.json")?)?.remove(1);
+        let row = record(event, &sources, versions).expect("source of the event");
+        assert_eq!(row.file_url.as_deref(), Some("s3://raw/mri/old.csv"));
+        assert_eq!(row.dataset.as_deref(), Some("mri"));
+        assert_eq!(row.is_delete_marker, is_delete_marker);
+        assert_eq!(row.deleted_at.as_deref(), deleted_at);
+
+        let mut outside = parse_events(&fixture("eventbridge.json")?)?.remove(0);
+        outside.key = "other/a.csv".to_string();
+        assert!(record(outside, &sources, versions).is_none());
+        Ok(())
+    }
+}
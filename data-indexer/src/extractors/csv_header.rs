@@ -0,0 +1,80 @@
+use super::{ByteRange, Extraction, FileMetadata, MetadataExtractor};
+
+use anyhow::{anyhow, Result};
+
+const HEADER_HINT: u64 = 16 * 1024;
+const HEADER_MAX: u64 = 1024 * 1024;
+const DELIMITERS: [char; 4] = [',', ';', '\t', '|'];
+
+/// header columns and delimiter from the first line of a csv file
+pub struct CsvHeaderExtractor;
+
+impl MetadataExtractor for CsvHeaderExtractor {
+    fn range(&self, file_size: u64) -> ByteRange {
+        ByteRange::Head(file_size.min(HEADER_HINT))
+    }
+
+    fn extract(&self, data: &[u8], file_size: u64) -> Result<Extraction> {
+        let line = match data.iter().position(|x| *x == b'\n') {
+            Some(i) => &data[..i],
+            None if (data.len() as u64) < file_size.min(HEADER_MAX) => {
+                let next = (data.len() as u64 * 4).clamp(HEADER_HINT, HEADER_MAX);
+                return Ok(Extraction::NeedMore(ByteRange::Head(next)));
+            }
+            None if (data.len() as u64) < file_size => {
+                return Err(anyhow!("csv header is longer than {HEADER_MAX} bytes"));
+            }
+            None => data,
+        };
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_start_matches('\u{feff}').trim_end_matches('\r');
+
+        let delimiter = DELIMITERS
+            .into_iter()
+            .map(|x| (x, line.matches(x).count()))
+            .filter(|(_, count)| *count > 0)
+            .max_by_key(|(_, count)| *count)
+            .map(|(x, _)| x)
+            .unwrap_or(',');
+        let columns = line
+            .split(delimiter)
+            .map(|x| x.trim().trim_matches('"').to_string())
+            .collect();
+
+        Ok(Extraction::Done(FileMetadata {
+            format: Some("csv".to_string()),
+            columns: Some(columns),
+            delimiter: Some(delimiter.to_string()),
+            ..Default::default()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("id,name,age\n1,a,2\n", ",", vec!["id", "name", "age"])]
+    #[case("\u{feff}\"id\";\"name\"\r\n1;a\r\n", ";", vec!["id", "name"])]
+    #[case("id\tname\n", "\t", vec!["id", "name"])]
+    #[case("id|name", "|", vec!["id", "name"])]
+    #[case("id\n1\n", ",", vec!["id"])]
+    fn test_extract(#[case] data: &str, #[case] delimiter: &str, #[case] columns: Vec<&str>) -> Result<()> {
+        let Extraction::Done(metadata) = CsvHeaderExtractor.extract(data.as_bytes(), data.len() as u64)? else {
+            panic!("expected metadata");
+        };
+        assert_eq!(metadata.delimiter.as_deref(), Some(delimiter));
+        assert_eq!(metadata.columns, Some(columns.into_iter().map(|x| x.to_string()).collect()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_need_more() -> Result<()> {
+        let extraction = CsvHeaderExtractor.extract(b"id,name", 100_000)?;
+        assert!(matches!(extraction, Extraction::NeedMore(ByteRange::Head(_))));
+        Ok(())
+    }
+}
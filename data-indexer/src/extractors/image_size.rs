@@ -0,0 +1,163 @@
+use super::{ByteRange, Extraction, FileMetadata, MetadataExtractor};
+
+use anyhow::{anyhow, Result};
+
+const IMAGE_HINT: u64 = 64 * 1024;
+const IMAGE_MAX: u64 = 1024 * 1024; // jpeg exif data can push the frame header far
+
+/// image dimensions from the png, gif, bmp or jpeg header
+pub struct ImageSizeExtractor;
+
+impl MetadataExtractor for ImageSizeExtractor {
+    fn range(&self, file_size: u64) -> ByteRange {
+        ByteRange::Head(file_size.min(IMAGE_HINT))
+    }
+
+    fn extract(&self, data: &[u8], file_size: u64) -> Result<Extraction> {
+        let found = if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+            png_size(data).map(|x| ("png", x))
+        } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+            gif_size(data).map(|x| ("gif", x))
+        } else if data.starts_with(b"BM") {
+            bmp_size(data).map(|x| ("bmp", x))
+        } else if data.starts_with(&[0xff, 0xd8]) {
+            jpeg_size(data).map(|x| ("jpeg", x))
+        } else {
+            return Err(anyhow!("unknown image format"));
+        };
+
+        match found {
+            Some((format, (width, height))) => Ok(Extraction::Done(FileMetadata {
+                format: Some(format.to_string()),
+                width: Some(width),
+                height: Some(height),
+                ..Default::default()
+            })),
+            None => {
+                let len = data.len() as u64;
+                if len >= file_size || len >= IMAGE_MAX {
+                    return Err(anyhow!("image size not found in {len} bytes"));
+                }
+                let next = (len * 4).clamp(IMAGE_HINT, IMAGE_MAX.min(file_size));
+                Ok(Extraction::NeedMore(ByteRange::Head(next)))
+            }
+        }
+    }
+}
+
+fn be_u16(data: &[u8], i: usize) -> Option<i64> {
+    data.get(i..i + 2).map(|x| u16::from_be_bytes([x[0], x[1]]) as i64)
+}
+
+fn png_size(data: &[u8]) -> Option<(i64, i64)> {
+    // IHDR is always the first chunk
+    let ihdr = data.get(16..24)?;
+    let width = u32::from_be_bytes(ihdr[0..4].try_into().ok()?);
+    let height = u32::from_be_bytes(ihdr[4..8].try_into().ok()?);
+    Some((width as i64, height as i64))
+}
+
+fn gif_size(data: &[u8]) -> Option<(i64, i64)> {
+    let screen = data.get(6..10)?;
+    let width = u16::from_le_bytes([screen[0], screen[1]]);
+    let height = u16::from_le_bytes([screen[2], screen[3]]);
+    Some((width as i64, height as i64))
+}
+
+fn bmp_size(data: &[u8]) -> Option<(i64, i64)> {
+    let header = data.get(18..26)?;
+    let width = i32::from_le_bytes(header[0..4].try_into().ok()?);
+    // negative height means the rows are stored top-down
+    let height = i32::from_le_bytes(header[4..8].try_into().ok()?);
+    Some((width.unsigned_abs() as i64, height.unsigned_abs() as i64))
+}
+
+fn jpeg_size(data: &[u8]) -> Option<(i64, i64)> {
+    let mut i = 2;
+    while i + 4 <= data.len() {
+        if data[i] != 0xff {
+            return None;
+        }
+        let marker = data[i + 1];
+        match marker {
+            // padding
+            0xff => {
+                i += 1;
+                continue;
+            }
+            // markers without a payload
+            0x01 | 0xd0..=0xd8 => {
+                i += 2;
+                continue;
+            }
+            // start of frame, except huffman, arithmetic coding and jpeg extensions
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                let height = be_u16(data, i + 5)?;
+                let width = be_u16(data, i + 7)?;
+                return Some((width, height));
+            }
+            _ => {}
+        }
+        i += 2 + be_u16(data, i + 2)? as usize;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        data.extend(width.to_be_bytes());
+        data.extend(height.to_be_bytes());
+        data.extend([8, 6, 0, 0, 0]);
+        data
+    }
+
+    fn gif(width: u16, height: u16) -> Vec<u8> {
+        let mut data = b"GIF89a".to_vec();
+        data.extend(width.to_le_bytes());
+        data.extend(height.to_le_bytes());
+        data
+    }
+
+    fn jpeg(width: u16, height: u16) -> Vec<u8> {
+        // SOI, APP0 with a 4 byte payload, SOF0
+        let mut data = vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x06, 0, 0, 0, 0, 0xff, 0xc0, 0x00, 0x11, 8];
+        data.extend(height.to_be_bytes());
+        data.extend(width.to_be_bytes());
+        data.extend([3, 0, 0, 0]);
+        data
+    }
+
+    #[rstest]
+    #[case(png(640, 480), "png", 640, 480)]
+    #[case(gif(32, 16), "gif", 32, 16)]
+    #[case(jpeg(1920, 1080), "jpeg", 1920, 1080)]
+    fn test_extract(
+        #[case] data: Vec<u8>,
+        #[case] format: &str,
+        #[case] width: i64,
+        #[case] height: i64,
+    ) -> Result<()> {
+        let Extraction::Done(metadata) = ImageSizeExtractor.extract(&data, data.len() as u64)? else {
+            panic!("expected metadata");
+        };
+        assert_eq!(metadata.format.as_deref(), Some(format));
+        assert_eq!(metadata.width, Some(width));
+        assert_eq!(metadata.height, Some(height));
+        Ok(())
+    }
+
+    #[test]
+    fn test_need_more() -> Result<()> {
+        let data = jpeg(1, 1);
+        let extraction = ImageSizeExtractor.extract(&data[..8], 100_000)?;
+        assert!(matches!(extraction, Extraction::NeedMore(ByteRange::Head(_))));
+        assert!(ImageSizeExtractor.extract(b"not an image", 12).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,84 @@
+use super::{ByteRange, Extraction, FileMetadata, MetadataExtractor};
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+const JSON_HINT: u64 = 64 * 1024;
+const JSON_MAX: u64 = 4 * 1024 * 1024;
+
+/// top level keys of the first json value, for json lines the first line
+pub struct JsonKeysExtractor;
+
+impl MetadataExtractor for JsonKeysExtractor {
+    fn range(&self, file_size: u64) -> ByteRange {
+        ByteRange::Head(file_size.min(JSON_HINT))
+    }
+
+    fn extract(&self, data: &[u8], file_size: u64) -> Result<Extraction> {
+        let data = data.strip_prefix("\u{feff}".as_bytes()).unwrap_or(data);
+        let value = match serde_json::Deserializer::from_slice(data)
+            .into_iter::<Value>()
+            .next()
+        {
+            Some(Ok(value)) => value,
+            Some(Err(e)) if e.is_eof() => {
+                let len = data.len() as u64;
+                if len >= file_size || len >= JSON_MAX {
+                    return Err(anyhow!("first json value is not complete in {len} bytes"));
+                }
+                let next = (len * 4).clamp(JSON_HINT, JSON_MAX.min(file_size));
+                return Ok(Extraction::NeedMore(ByteRange::Head(next)));
+            }
+            Some(Err(e)) => return Err(e.into()),
+            None => return Err(anyhow!("empty json file")),
+        };
+
+        let (object, row_count) = match &value {
+            Value::Array(values) => (values.first(), Some(values.len() as i64)),
+            value => (Some(value), None),
+        };
+        let columns = object
+            .and_then(|x| x.as_object())
+            .map(|x| x.keys().cloned().collect());
+
+        Ok(Extraction::Done(FileMetadata {
+            format: Some("json".to_string()),
+            row_count,
+            columns,
+            ..Default::default()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(r#"{"b": 1, "a": {"c": 2}}"#, Some(vec!["a", "b"]), None)]
+    #[case("{\"id\": 1}\n{\"id\": 2, \"x\": 3}\n", Some(vec!["id"]), None)]
+    #[case(r#"[{"id": 1}, {"id": 2}]"#, Some(vec!["id"]), Some(2))]
+    #[case("[1, 2, 3]", None, Some(3))]
+    fn test_extract(
+        #[case] data: &str,
+        #[case] columns: Option<Vec<&str>>,
+        #[case] row_count: Option<i64>,
+    ) -> Result<()> {
+        let Extraction::Done(metadata) = JsonKeysExtractor.extract(data.as_bytes(), data.len() as u64)? else {
+            panic!("expected metadata");
+        };
+        assert_eq!(metadata.columns, columns.map(|x| x.into_iter().map(|x| x.to_string()).collect()));
+        assert_eq!(metadata.row_count, row_count);
+        Ok(())
+    }
+
+    #[test]
+    fn test_need_more() -> Result<()> {
+        let extraction = JsonKeysExtractor.extract(br#"{"id": 1, "name": "#, 100_000)?;
+        assert!(matches!(extraction, Extraction::NeedMore(ByteRange::Head(_))));
+        assert!(JsonKeysExtractor.extract(br#"{"id": 1, "name": "#, 18).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,251 @@
+mod csv_header;
+mod image_size;
+mod json_keys;
+mod parquet_footer;
+
+pub use csv_header::CsvHeaderExtractor;
+pub use image_size::ImageSizeExtractor;
+pub use json_keys::JsonKeysExtractor;
+pub use parquet_footer::ParquetFooterExtractor;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::file_data::FileData;
+use crate::storage::{Storage, StorageRef};
+use crate::utils::constants::*;
+
+use anyhow::{anyhow, Result};
+use datafusion::arrow::array::{
+    ArrayRef, Int64Builder, ListBuilder, StringBuilder, StructArray,
+};
+use datafusion::arrow::buffer::NullBuffer;
+use datafusion::arrow::datatypes::{DataType, Field, Fields};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// content level metadata stored in the nested metadata column
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileMetadata {
+    pub format: Option<String>,
+    pub row_count: Option<i64>,
+    pub columns: Option<Vec<String>>,
+    pub schema: Option<String>,
+    pub delimiter: Option<String>,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+}
+
+impl FileMetadata {
+    pub fn fields() -> Fields {
+        Fields::from(vec![
+            Field::new("format", DataType::Utf8, true),
+            Field::new("row_count", DataType::Int64, true),
+            Field::new(
+                "columns",
+                DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))),
+                true,
+            ),
+            Field::new("schema", DataType::Utf8, true),
+            Field::new("delimiter", DataType::Utf8, true),
+            Field::new("width", DataType::Int64, true),
+            Field::new("height", DataType::Int64, true),
+        ])
+    }
+
+    pub fn to_array(records: &[Option<&Self>]) -> Result<StructArray> {
+        let mut formats = StringBuilder::new();
+        let mut row_counts = Int64Builder::new();
+        let mut columns = ListBuilder::new(StringBuilder::new());
+        let mut schemas = StringBuilder::new();
+        let mut delimiters = StringBuilder::new();
+        let mut widths = Int64Builder::new();
+        let mut heights = Int64Builder::new();
+
+        for record in records {
+            let record = record.cloned().unwrap_or_default();
+            formats.append_option(record.format);
+            row_counts.append_option(record.row_count);
+            match record.columns {
+                Some(cols) => columns.append_value(cols.into_iter().map(Some)),
+                None => columns.append_null(),
+            }
+            schemas.append_option(record.schema);
+            delimiters.append_option(record.delimiter);
+            widths.append_option(record.width);
+            heights.append_option(record.height);
+        }
+
+        let arrays: Vec<ArrayRef> = vec![
+            Arc::new(formats.finish()),
+            Arc::new(row_counts.finish()),
+            Arc::new(columns.finish()),
+            Arc::new(schemas.finish()),
+            Arc::new(delimiters.finish()),
+            Arc::new(widths.finish()),
+            Arc::new(heights.finish()),
+        ];
+        let nulls = NullBuffer::from(records.iter().map(|x| x.is_some()).collect::<Vec<_>>());
+        Ok(StructArray::try_new(Self::fields(), arrays, Some(nulls))?)
+    }
+}
+
+/// part of the object an extractor needs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteRange {
+    /// first n bytes
+    Head(u64),
+    /// last n bytes
+    Tail(u64),
+    /// bytes from start to end, inclusive
+    Span(u64, u64),
+}
+
+impl ByteRange {
+    pub fn header(&self) -> String {
+        match self {
+            ByteRange::Head(n) => format!("bytes=0-{}", n.saturating_sub(1)),
+            ByteRange::Tail(n) => format!("bytes=-{n}"),
+            ByteRange::Span(start, end) => format!("bytes={start}-{end}"),
+        }
+    }
+}
+
+pub enum Extraction {
+    Done(FileMetadata),
+    /// the range was too small, read again with the bigger one
+    NeedMore(ByteRange),
+}
+
+/// reads content level metadata of one kind of files from a byte range
+pub trait MetadataExtractor: Send + Sync {
+    fn range(&self, file_size: u64) -> ByteRange;
+
+    fn extract(&self, data: &[u8], file_size: u64) -> Result<Extraction>;
+}
+
+/// extractors keyed by file_type
+#[derive(Clone)]
+pub struct Extractors {
+    extractors: HashMap<String, Arc<dyn MetadataExtractor>>,
+}
+
+impl Default for Extractors {
+    fn default() -> Self {
+        let mut extractors = Self::empty();
+        extractors.register(&["parquet"], Arc::new(ParquetFooterExtractor));
+        extractors.register(&["csv", "tsv"], Arc::new(CsvHeaderExtractor));
+        extractors.register(&["json", "jsonl", "ndjson"], Arc::new(JsonKeysExtractor));
+        extractors.register(&["png", "gif", "jpg", "jpeg", "bmp"], Arc::new(ImageSizeExtractor));
+        extractors
+    }
+}
+
+impl Extractors {
+    pub fn empty() -> Self {
+        Self {
+            extractors: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, file_types: &[&str], extractor: Arc<dyn MetadataExtractor>) {
+        for file_type in file_types {
+            self.extractors.insert(file_type.to_string(), extractor.clone());
+        }
+    }
+
+    pub fn get(&self, file_type: &str) -> Option<Arc<dyn MetadataExtractor>> {
+        self.extractors.get(&file_type.to_lowercase()).cloned()
+    }
+}
+
+async fn extract(
+    storage: &dyn Storage,
+    key: &str,
+    file_size: u64,
+    extractor: Arc<dyn MetadataExtractor>,
+) -> Result<FileMetadata> {
+    let mut range = extractor.range(file_size);
+    for _ in 0..METADATA_MAX_READS {
+        let data = storage.get_range(key, range).await?;
+        match extractor.extract(&data, file_size)? {
+            Extraction::Done(metadata) => return Ok(metadata),
+            Extraction::NeedMore(next) => range = next,
+        }
+    }
+    Err(anyhow!("metadata of file: {key} not found in {METADATA_MAX_READS} reads"))
+}
+
+/// fills metadata of records with a known file_type, reading only byte ranges,
+/// returns the number of files that failed
+pub async fn extract_metadata(
+    storage: StorageRef,
+    records: &mut [FileData],
+    extractors: &Extractors,
+) -> Result<usize> {
+    let sem = Arc::new(Semaphore::new(METADATA_WORKERS));
+    let mut tasks = JoinSet::new();
+    for (i, record) in records.iter().enumerate() {
+        let (Some(key), Some(file_type), Some(file_size)) =
+            (&record.file_path, &record.file_type, record.file_size)
+        else {
+            continue;
+        };
+        let Some(extractor) = extractors.get(file_type) else {
+            continue;
+        };
+        if file_size <= 0 || !record.is_current() {
+            continue;
+        }
+        let permit = sem.clone().acquire_owned().await?;
+        let storage = storage.clone();
+        let key = key.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            let res = extract(storage.as_ref(), &key, file_size as u64, extractor).await;
+            (i, key, res)
+        });
+    }
+
+    let mut errors = 0;
+    while let Some(task) = tasks.join_next().await {
+        match task? {
+            (i, _, Ok(metadata)) => records[i].metadata = Some(metadata),
+            (_, key, Err(e)) => {
+                tracing::warn!("failed to extract metadata of file: {key}: {e:?}");
+                errors += 1;
+            }
+        }
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use datafusion::arrow::array::Array;
+
+    #[test]
+    fn test_to_array() -> Result<()> {
+        let metadata = FileMetadata {
+            format: Some("csv".to_string()),
+            columns: Some(vec!["a".to_string(), "b".to_string()]),
+            delimiter: Some(",".to_string()),
+            ..Default::default()
+        };
+        let array = FileMetadata::to_array(&[Some(&metadata), None])?;
+        assert_eq!(array.len(), 2);
+        assert!(array.is_valid(0));
+        assert!(array.is_null(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_registry() {
+        let extractors = Extractors::default();
+        assert!(extractors.get("PNG").is_some());
+        assert!(extractors.get("parquet").is_some());
+        assert!(extractors.get("foo").is_none());
+    }
+}
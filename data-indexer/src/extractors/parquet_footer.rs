@@ -0,0 +1,112 @@
+use super::{ByteRange, Extraction, FileMetadata, MetadataExtractor};
+
+use anyhow::{anyhow, Result};
+use parquet::arrow::parquet_to_arrow_schema;
+use parquet::file::metadata::ParquetMetaDataReader;
+
+const FOOTER_SIZE: usize = 8;
+const FOOTER_HINT: u64 = 64 * 1024; // usually enough for the whole metadata
+
+/// schema and row count from the parquet footer
+pub struct ParquetFooterExtractor;
+
+impl MetadataExtractor for ParquetFooterExtractor {
+    fn range(&self, file_size: u64) -> ByteRange {
+        ByteRange::Tail(file_size.min(FOOTER_HINT))
+    }
+
+    fn extract(&self, data: &[u8], file_size: u64) -> Result<Extraction> {
+        if data.len() < FOOTER_SIZE {
+            return Err(anyhow!("file is too small to be parquet"));
+        }
+        let (rest, footer) = data.split_at(data.len() - FOOTER_SIZE);
+        let footer: &[u8; FOOTER_SIZE] = footer.try_into()?;
+        let metadata_len = ParquetMetaDataReader::decode_footer(footer)?;
+        if rest.len() < metadata_len {
+            let needed = (metadata_len + FOOTER_SIZE) as u64;
+            if needed > file_size {
+                return Err(anyhow!("parquet metadata is bigger than the file"));
+            }
+            return Ok(Extraction::NeedMore(ByteRange::Tail(needed)));
+        }
+
+        let metadata = ParquetMetaDataReader::decode_metadata(&rest[rest.len() - metadata_len..])?;
+        let file_metadata = metadata.file_metadata();
+        let schema = parquet_to_arrow_schema(
+            file_metadata.schema_descr(),
+            file_metadata.key_value_metadata(),
+        )?;
+        let columns = schema.fields().iter().map(|x| x.name().to_string()).collect();
+        let schema = schema
+            .fields()
+            .iter()
+            .map(|x| format!("{}: {}", x.name(), x.data_type()))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        Ok(Extraction::Done(FileMetadata {
+            format: Some("parquet".to_string()),
+            row_count: Some(file_metadata.num_rows()),
+            columns: Some(columns),
+            schema: Some(schema),
+            ..Default::default()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use datafusion::arrow::array::{Int64Array, RecordBatch, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Schema};
+    use parquet::arrow::ArrowWriter;
+
+    fn parquet_file() -> Vec<u8> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("id", DataType::Int64, true),
+            Field::new("name", DataType::Utf8, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(Int64Array::from(vec![1, 2, 3])),
+                Arc::new(StringArray::from(vec!["a", "b", "c"])),
+            ],
+        )
+        .unwrap();
+        let mut buf = vec![];
+        let mut writer = ArrowWriter::try_new(&mut buf, schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_extract() -> Result<()> {
+        let data = parquet_file();
+        let size = data.len() as u64;
+
+        // only the footer is read, the metadata needs a second read
+        let Extraction::NeedMore(range) = ParquetFooterExtractor.extract(&data[data.len() - 8..], size)? else {
+            panic!("expected a bigger range");
+        };
+        let ByteRange::Tail(n) = range else {
+            panic!("expected a tail range");
+        };
+        let Extraction::Done(metadata) = ParquetFooterExtractor.extract(&data[data.len() - n as usize..], size)? else {
+            panic!("expected metadata");
+        };
+        assert_eq!(metadata.row_count, Some(3));
+        assert_eq!(metadata.columns, Some(vec!["id".to_string(), "name".to_string()]));
+        assert_eq!(metadata.schema.as_deref(), Some("id: Int64, name: Utf8"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_not_parquet() {
+        assert!(ParquetFooterExtractor.extract(b"a,b,c\n1,2,3\n", 12).is_err());
+    }
+}
@@ -1,10 +1,23 @@
+use std::collections::BTreeMap;
+use std::path::Path;
 use std::sync::Arc;
 
+use crate::archives::ArchiveMember;
+use crate::extractors::FileMetadata;
+use crate::inventory::InventoryInfo;
+use crate::storage::object_url;
+use crate::utils::aws::{HeadInfo, ObjectInfo};
+
 use anyhow::Result;
-use datafusion::arrow::array::{Int64Array, RecordBatch, StringArray};
-use datafusion::arrow::datatypes::{DataType, Field, Schema};
+use chrono::{DateTime, Datelike, Utc};
+use datafusion::arrow::array::{
+    Array, ArrayRef, BooleanArray, Int32Array, Int64Array, MapBuilder, RecordBatch, StringArray, StringBuilder,
+    TimestampMicrosecondArray,
+};
+use datafusion::arrow::datatypes::{DataType, Field, Fields, Schema, TimeUnit};
 use datafusion::prelude::*;
 
+#[derive(Clone)]
 pub struct FileData {
     pub file_name: Option<String>,
     pub file_type: Option<String>,
@@ -12,10 +25,172 @@ pub struct FileData {
     pub file_size: Option<i64>,
     pub file_url: Option<String>,
     pub dt: Option<String>,
+    pub etag: Option<String>,
+    pub storage_class: Option<String>,
+    pub owner: Option<String>,
+    pub checksum_algorithm: Option<String>,
+    pub checksum_value: Option<String>,
+    pub content_sha256: Option<String>,
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub source_bucket: Option<String>,
+    pub dataset: Option<String>,
+    pub deleted_at: Option<String>,
+    pub metadata: Option<FileMetadata>,
+    pub inventory: Option<InventoryInfo>,
+    /// parsed from dt, which is kept as string for existing queries
+    pub last_modified: Option<DateTime<Utc>>,
+    pub tags: Option<BTreeMap<String, String>>,
+    pub user_metadata: Option<BTreeMap<String, String>>,
+    /// only set when versions are listed
+    pub version_id: Option<String>,
+    pub is_latest: Option<bool>,
+    pub is_delete_marker: Option<bool>,
+    /// only set on rows of archive members, file_path stays the key of the archive
+    pub archive_path: Option<String>,
+    pub member_path: Option<String>,
+    pub member_size: Option<i64>,
+}
+
+pub fn parse_dt(dt: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(dt).ok().map(|x| x.with_timezone(&Utc))
+}
+
+fn temporal_array(name: &str, values: &[Option<DateTime<Utc>>]) -> Option<ArrayRef> {
+    let part = |f: fn(&DateTime<Utc>) -> i32| -> ArrayRef {
+        Arc::new(values.iter().map(|x| x.as_ref().map(f)).collect::<Int32Array>())
+    };
+    match name {
+        "last_modified" => {
+            let micros = values.iter().map(|x| x.map(|x| x.timestamp_micros()));
+            Some(Arc::new(TimestampMicrosecondArray::from_iter(micros).with_timezone("UTC")))
+        }
+        "year" => Some(part(|x| x.year())),
+        "month" => Some(part(|x| x.month() as i32)),
+        "day" => Some(part(|x| x.day() as i32)),
+        _ => None,
+    }
+}
+
+/// string to string map, as built by `MapBuilder` with its default field names
+fn map_type() -> DataType {
+    let entries = Fields::from(vec![
+        Field::new("keys", DataType::Utf8, false),
+        Field::new("values", DataType::Utf8, true),
+    ]);
+    DataType::Map(Arc::new(Field::new("entries", DataType::Struct(entries), false)), false)
+}
+
+fn map_array(values: &[Option<&BTreeMap<String, String>>]) -> Result<ArrayRef> {
+    let mut builder = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+    for value in values {
+        if let Some(map) = value {
+            for (k, v) in map.iter() {
+                builder.keys().append_value(k);
+                builder.values().append_value(v);
+            }
+        }
+        builder.append(value.is_some())?;
+    }
+    Ok(Arc::new(builder.finish()))
+}
+
+/// last_modified, year, month or day of the batch computed from its dt column,
+/// for files written before these columns existed
+pub fn derive_temporal(name: &str, batch: &RecordBatch) -> Option<ArrayRef> {
+    let dts = batch.column_by_name("dt")?.as_any().downcast_ref::<StringArray>()?;
+    let values = (0..dts.len())
+        .map(|i| dts.is_valid(i).then(|| parse_dt(dts.value(i))).flatten())
+        .collect::<Vec<_>>();
+    temporal_array(name, &values)
 }
 
 impl FileData {
-    fn schema() -> Schema {
+    pub fn new(bucket: &str, key: String, info: ObjectInfo) -> Self {
+        let path = Path::new(&key);
+        let file_name = path.file_name().map(|x| x.to_string_lossy().to_string());
+        let file_type = path.extension().map(|x| x.to_string_lossy().to_string());
+        let file_url = Some(object_url(bucket, &key));
+        let last_modified = info.last_modified.as_deref().and_then(parse_dt);
+        Self {
+            file_name,
+            file_type,
+            file_path: Some(key),
+            file_size: info.size,
+            file_url,
+            dt: info.last_modified,
+            etag: info.etag,
+            storage_class: info.storage_class,
+            owner: info.owner,
+            checksum_algorithm: info.checksum_algorithm,
+            checksum_value: None,
+            content_sha256: None,
+            content_type: None,
+            content_encoding: None,
+            source_bucket: Some(bucket.to_string()),
+            dataset: None,
+            deleted_at: None,
+            metadata: None,
+            inventory: info.inventory,
+            last_modified,
+            tags: None,
+            user_metadata: None,
+            version_id: info.version_id,
+            is_latest: info.is_latest,
+            is_delete_marker: info.is_delete_marker,
+            archive_path: None,
+            member_path: None,
+            member_size: None,
+        }
+    }
+
+    /// row of a file inside the archive, url, size and dt are those of the archive,
+    /// so the snapshot and deletions see the archive only
+    pub fn member(&self, member: &ArchiveMember) -> Self {
+        let path = Path::new(&member.path);
+        Self {
+            file_name: path.file_name().map(|x| x.to_string_lossy().to_string()),
+            file_type: path.extension().map(|x| x.to_string_lossy().to_string()),
+            content_sha256: None,
+            content_type: None,
+            content_encoding: None,
+            metadata: None,
+            archive_path: self.file_path.clone(),
+            member_path: Some(member.path.clone()),
+            member_size: Some(member.size),
+            ..self.clone()
+        }
+    }
+
+    pub fn with_dataset(mut self, dataset: Option<&str>) -> Self {
+        self.dataset = dataset.map(|x| x.to_string());
+        self
+    }
+
+    /// row for an object that is gone since the snapshot it was last seen in
+    pub fn tombstone(bucket: &str, key: String, info: ObjectInfo, deleted_at: &str) -> Self {
+        let mut record = Self::new(bucket, key, info);
+        record.deleted_at = Some(deleted_at.to_string());
+        record
+    }
+
+    /// whether the row is the current object, reading its body or head
+    /// would return another version for noncurrent versions and delete markers
+    pub fn is_current(&self) -> bool {
+        self.deleted_at.is_none() && self.is_latest != Some(false) && self.is_delete_marker != Some(true)
+    }
+
+    pub fn with_head(&mut self, head: HeadInfo) {
+        self.content_type = head.content_type;
+        self.content_encoding = head.content_encoding;
+        self.checksum_value = head.checksum_value;
+        self.user_metadata = head.user_metadata;
+        if head.checksum_algorithm.is_some() {
+            self.checksum_algorithm = head.checksum_algorithm;
+        }
+    }
+
+    pub fn schema() -> Schema {
         Schema::new(vec![
             Field::new("file_name", DataType::Utf8, true),
             Field::new("file_type", DataType::Utf8, true),
@@ -23,10 +198,39 @@ impl FileData {
             Field::new("file_path", DataType::Utf8, true),
             Field::new("file_url", DataType::Utf8, true),
             Field::new("dt", DataType::Utf8, true),
+            Field::new("etag", DataType::Utf8, true),
+            Field::new("storage_class", DataType::Utf8, true),
+            Field::new("owner", DataType::Utf8, true),
+            Field::new("checksum_algorithm", DataType::Utf8, true),
+            Field::new("checksum_value", DataType::Utf8, true),
+            Field::new("content_sha256", DataType::Utf8, true),
+            Field::new("content_type", DataType::Utf8, true),
+            Field::new("content_encoding", DataType::Utf8, true),
+            Field::new("source_bucket", DataType::Utf8, true),
+            Field::new("dataset", DataType::Utf8, true),
+            Field::new("deleted_at", DataType::Utf8, true),
+            Field::new("metadata", DataType::Struct(FileMetadata::fields()), true),
+            Field::new("inventory", DataType::Struct(InventoryInfo::fields()), true),
+            Field::new(
+                "last_modified",
+                DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+                true,
+            ),
+            Field::new("year", DataType::Int32, true),
+            Field::new("month", DataType::Int32, true),
+            Field::new("day", DataType::Int32, true),
+            Field::new("tags", map_type(), true),
+            Field::new("user_metadata", map_type(), true),
+            Field::new("version_id", DataType::Utf8, true),
+            Field::new("is_latest", DataType::Boolean, true),
+            Field::new("is_delete_marker", DataType::Boolean, true),
+            Field::new("archive_path", DataType::Utf8, true),
+            Field::new("member_path", DataType::Utf8, true),
+            Field::new("member_size", DataType::Int64, true),
         ])
     }
 
-    fn to_record_batch(records: &[Self]) -> Result<RecordBatch> {
+    pub fn to_record_batch(records: &[Self]) -> Result<RecordBatch> {
         let schema = FileData::schema();
 
         let file_names = records.iter().map(|r| r.file_name.as_deref()).collect::<Vec<_>>();
@@ -35,6 +239,29 @@ impl FileData {
         let file_paths = records.iter().map(|r| r.file_path.as_deref()).collect::<Vec<_>>();
         let file_urls = records.iter().map(|r| r.file_url.as_deref()).collect::<Vec<_>>();
         let dts = records.iter().map(|r| r.dt.as_deref()).collect::<Vec<_>>();
+        let etags = records.iter().map(|r| r.etag.as_deref()).collect::<Vec<_>>();
+        let storage_classes = records.iter().map(|r| r.storage_class.as_deref()).collect::<Vec<_>>();
+        let owners = records.iter().map(|r| r.owner.as_deref()).collect::<Vec<_>>();
+        let checksum_algorithms = records.iter().map(|r| r.checksum_algorithm.as_deref()).collect::<Vec<_>>();
+        let checksum_values = records.iter().map(|r| r.checksum_value.as_deref()).collect::<Vec<_>>();
+        let content_sha256s = records.iter().map(|r| r.content_sha256.as_deref()).collect::<Vec<_>>();
+        let content_types = records.iter().map(|r| r.content_type.as_deref()).collect::<Vec<_>>();
+        let content_encodings = records.iter().map(|r| r.content_encoding.as_deref()).collect::<Vec<_>>();
+        let source_buckets = records.iter().map(|r| r.source_bucket.as_deref()).collect::<Vec<_>>();
+        let datasets = records.iter().map(|r| r.dataset.as_deref()).collect::<Vec<_>>();
+        let deleted_ats = records.iter().map(|r| r.deleted_at.as_deref()).collect::<Vec<_>>();
+        let metadata = records.iter().map(|r| r.metadata.as_ref()).collect::<Vec<_>>();
+        let inventory = records.iter().map(|r| r.inventory.as_ref()).collect::<Vec<_>>();
+        let last_modified = records.iter().map(|r| r.last_modified).collect::<Vec<_>>();
+        let tags = records.iter().map(|r| r.tags.as_ref()).collect::<Vec<_>>();
+        let user_metadata = records.iter().map(|r| r.user_metadata.as_ref()).collect::<Vec<_>>();
+        let version_ids = records.iter().map(|r| r.version_id.as_deref()).collect::<Vec<_>>();
+        let is_latests = records.iter().map(|r| r.is_latest).collect::<Vec<_>>();
+        let is_delete_markers = records.iter().map(|r| r.is_delete_marker).collect::<Vec<_>>();
+        let archive_paths = records.iter().map(|r| r.archive_path.as_deref()).collect::<Vec<_>>();
+        let member_paths = records.iter().map(|r| r.member_path.as_deref()).collect::<Vec<_>>();
+        let member_sizes = records.iter().map(|r| r.member_size).collect::<Vec<_>>();
+        let temporal = |name| temporal_array(name, &last_modified).expect("temporal column");
 
         Ok(RecordBatch::try_new(
             Arc::new(schema),
@@ -45,6 +272,31 @@ impl FileData {
                 Arc::new(StringArray::from(file_paths)),
                 Arc::new(StringArray::from(file_urls)),
                 Arc::new(StringArray::from(dts)),
+                Arc::new(StringArray::from(etags)),
+                Arc::new(StringArray::from(storage_classes)),
+                Arc::new(StringArray::from(owners)),
+                Arc::new(StringArray::from(checksum_algorithms)),
+                Arc::new(StringArray::from(checksum_values)),
+                Arc::new(StringArray::from(content_sha256s)),
+                Arc::new(StringArray::from(content_types)),
+                Arc::new(StringArray::from(content_encodings)),
+                Arc::new(StringArray::from(source_buckets)),
+                Arc::new(StringArray::from(datasets)),
+                Arc::new(StringArray::from(deleted_ats)),
+                Arc::new(FileMetadata::to_array(&metadata)?),
+                Arc::new(InventoryInfo::to_array(&inventory)?),
+                temporal("last_modified"),
+                temporal("year"),
+                temporal("month"),
+                temporal("day"),
+                map_array(&tags)?,
+                map_array(&user_metadata)?,
+                Arc::new(StringArray::from(version_ids)),
+                Arc::new(BooleanArray::from(is_latests)),
+                Arc::new(BooleanArray::from(is_delete_markers)),
+                Arc::new(StringArray::from(archive_paths)),
+                Arc::new(StringArray::from(member_paths)),
+                Arc::new(Int64Array::from(member_sizes)),
             ],
         )?)
     }
@@ -52,7 +304,7 @@ impl FileData {
 
 impl FileData {
     pub async fn to_df(
-        ctx: SessionContext, 
+        ctx: SessionContext,
         records: &[Self],
     ) -> Result<DataFrame> {
         let batch = Self::to_record_batch(records)?;
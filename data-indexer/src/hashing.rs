@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use crate::extractors::ByteRange;
+use crate::file_data::FileData;
+use crate::storage::{Storage, StorageRef};
+use crate::utils::constants::*;
+
+use anyhow::Result;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use sha2::{Digest, Sha256};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// ranges of `chunk_size` covering the object, the last one may be shorter
+pub fn chunk_ranges(size: u64, chunk_size: u64) -> Vec<ByteRange> {
+    (0..size)
+        .step_by(chunk_size as usize)
+        .map(|start| ByteRange::Span(start, (start + chunk_size - 1).min(size - 1)))
+        .collect()
+}
+
+/// hex sha-256 of the object body, ranges are read ahead concurrently
+/// and hashed in order, so at most `HASH_CHUNK_WORKERS` chunks are in memory
+pub async fn hash_object(storage: &dyn Storage, key: &str, size: u64) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut chunks = stream::iter(chunk_ranges(size, HASH_CHUNK_SIZE))
+        .map(|range| storage.get_range(key, range))
+        .buffered(HASH_CHUNK_WORKERS);
+    while let Some(chunk) = chunks.try_next().await? {
+        hasher.update(&chunk);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// adds content_sha256 to the records, files failing to read are logged and skipped,
+/// returns their number
+pub async fn hash_records(storage: StorageRef, records: &mut [FileData]) -> Result<usize> {
+    let sem = Arc::new(Semaphore::new(HASH_WORKERS));
+    let mut tasks = JoinSet::new();
+    for (i, record) in records.iter().enumerate() {
+        let (Some(key), Some(file_size)) = (&record.file_path, record.file_size) else {
+            continue;
+        };
+        if !record.is_current() || file_size < 0 {
+            continue;
+        }
+        let permit = sem.clone().acquire_owned().await?;
+        let storage = storage.clone();
+        let key = key.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            let res = hash_object(storage.as_ref(), &key, file_size as u64).await;
+            (i, key, res)
+        });
+    }
+
+    let mut errors = 0;
+    while let Some(task) = tasks.join_next().await {
+        match task? {
+            (i, _, Ok(hash)) => records[i].content_sha256 = Some(hash),
+            (_, key, Err(e)) => {
+                tracing::warn!("failed to hash file: {key}: {e:?}");
+                errors += 1;
+            }
+        }
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(0, vec![])]
+    #[case(5, vec![ByteRange::Span(0, 4)])]
+    #[case(10, vec![ByteRange::Span(0, 9)])]
+    #[case(25, vec![ByteRange::Span(0, 9), ByteRange::Span(10, 19), ByteRange::Span(20, 24)])]
+    fn test_chunk_ranges(#[case] size: u64, #[case] expected: Vec<ByteRange>) {
+        assert_eq!(chunk_ranges(size, 10), expected);
+    }
+
+    #[test]
+    fn test_chunked_hash() {
+        // hashing the chunks in order equals hashing the whole body
+        let body = (0..25u8).collect::<Vec<_>>();
+        let mut hasher = Sha256::new();
+        for range in chunk_ranges(body.len() as u64, 10) {
+            let ByteRange::Span(start, end) = range else {
+                unreachable!()
+            };
+            hasher.update(&body[start as usize..=end as usize]);
+        }
+        assert_eq!(hex::encode(hasher.finalize()), hex::encode(Sha256::digest(&body)));
+        assert_eq!(
+            hex::encode(Sha256::new().finalize()),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::config::{Config, OutputFormat};
+use crate::file_data::FileData;
+use crate::key_fields::KeyFields;
+use crate::partition::{partition_paths, PartitionColumn};
+use crate::storage::StorageRef;
+use crate::utils::constants::MAX_OPEN_FILES;
+use crate::writer_options::WriterOptions;
+
+use anyhow::Result;
+use datafusion::arrow::array::{RecordBatch, UInt32Array};
+use datafusion::arrow::compute::{concat_batches, take_record_batch};
+use datafusion::arrow::datatypes::SchemaRef;
+use parquet::arrow::async_writer::AsyncFileWriter;
+use parquet::arrow::AsyncArrowWriter;
+
+/// writes index records page by page into parquet files streamed to the storage,
+/// one file per hive partition, so only the current row group
+/// and upload part of each partition are kept in memory.
+/// At most `max_open_files` files are written at once, the least recently written one
+/// is closed for a new partition and the partition continues in a new file.
+/// Sorted rows are buffered per partition and written a row group at a time
+pub struct IndexWriter {
+    storage: StorageRef,
+    prefix: String,
+    file_name: String,
+    part_size: usize,
+    max_file_size: Option<usize>,
+    options: WriterOptions,
+    partition_by: Vec<PartitionColumn>,
+    key_fields: KeyFields,
+    projection: Vec<usize>,
+    schema: SchemaRef,
+    max_open_files: usize,
+    writers: HashMap<String, PartitionFile>,
+    writes: usize,
+    pending: HashMap<String, Vec<RecordBatch>>,
+    files: HashMap<String, usize>,
+    closed: Vec<String>,
+    rows: usize,
+}
+
+impl IndexWriter {
+    pub fn new(
+        storage: StorageRef,
+        prefix: &str,
+        file_name: &str,
+        part_size: usize,
+        partition_by: Vec<PartitionColumn>,
+        key_fields: KeyFields,
+    ) -> Result<Self> {
+        // partition columns are stored in the path only
+        let schema = key_fields.schema(FileData::schema());
+        let projection = schema
+            .fields()
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| !partition_by.iter().any(|col| col.name() == field.name()))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        let schema = Arc::new(schema.project(&projection)?);
+
+        Ok(Self {
+            storage,
+            prefix: prefix.to_string(),
+            file_name: file_name.to_string(),
+            part_size,
+            max_file_size: None,
+            options: WriterOptions::default(),
+            partition_by,
+            key_fields,
+            projection,
+            schema,
+            max_open_files: MAX_OPEN_FILES,
+            writers: HashMap::new(),
+            writes: 0,
+            pending: HashMap::new(),
+            files: HashMap::new(),
+            closed: vec![],
+            rows: 0,
+        })
+    }
+
+    /// writer of an index run, the files of a delta run are written into the table
+    /// and committed by the caller
+    pub fn for_run(storage: StorageRef, config: &Config, file_name: &str) -> Result<Self> {
+        let prefix = match config.args.output() {
+            OutputFormat::Parquet => &config.prefix_target,
+            OutputFormat::Delta => config.args.combined_prefix(),
+        };
+        let writer = Self::new(
+            storage,
+            prefix,
+            file_name,
+            config.args.part_size(),
+            config.args.partition_by()?,
+            KeyFields::try_new(config.args.fields())?,
+        )?;
+        Ok(writer.with_options(config.args.writer_options()?))
+    }
+
+    /// starts a new file in the partition once the current one reaches the size
+    pub fn with_max_file_size(mut self, max_file_size: usize) -> Self {
+        self.max_file_size = Some(max_file_size);
+        self
+    }
+
+    /// bounds the memory of many partitions, must be at least one
+    pub fn with_max_open_files(mut self, max_open_files: usize) -> Self {
+        self.max_open_files = max_open_files.max(1);
+        self
+    }
+
+    pub fn with_options(mut self, options: WriterOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub async fn write(&mut self, records: &[FileData]) -> Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let batch = FileData::to_record_batch(records)?;
+        self.write_batch(&batch).await
+    }
+
+    /// writes a batch with the `FileData` schema, key fields are added here
+    pub async fn write_batch(&mut self, batch: &RecordBatch) -> Result<()> {
+        if batch.num_rows() == 0 {
+            return Ok(());
+        }
+        let mut partitions: HashMap<String, Vec<u32>> = HashMap::new();
+        for (i, path) in partition_paths(&self.partition_by, batch)?.into_iter().enumerate() {
+            partitions.entry(path).or_default().push(i as u32);
+        }
+
+        let batch = self.key_fields.append(batch.clone())?.project(&self.projection)?;
+        for (path, indices) in partitions {
+            let batch = take_record_batch(&batch, &UInt32Array::from(indices))?;
+            if !self.options.sort_by_file_path {
+                self.writer(&path).await?.write(&batch).await?;
+                self.roll(&path).await?;
+                continue;
+            }
+            let pending = self.pending.entry(path.clone()).or_default();
+            pending.push(batch);
+            if pending.iter().map(|x| x.num_rows()).sum::<usize>() >= self.options.row_group_size {
+                self.flush(&path, false).await?;
+            }
+        }
+        self.rows += batch.num_rows();
+        Ok(())
+    }
+
+    /// writes the buffered rows of the partition sorted as full row groups,
+    /// the remaining rows wait for the next row group unless `all` is set
+    async fn flush(&mut self, path: &str, all: bool) -> Result<()> {
+        let Some(batches) = self.pending.remove(path) else {
+            return Ok(());
+        };
+        let mut batch = self.options.sort(concat_batches(&self.schema, &batches)?)?;
+        let full = batch.num_rows() - batch.num_rows() % self.options.row_group_size;
+        if !all && full < batch.num_rows() {
+            self.pending.insert(path.to_string(), vec![batch.slice(full, batch.num_rows() - full)]);
+            batch = batch.slice(0, full);
+        }
+        let writer = self.writer(path).await?;
+        writer.write(&batch).await?;
+        writer.flush().await?;
+        self.roll(path).await
+    }
+
+    async fn roll(&mut self, path: &str) -> Result<()> {
+        let Some(max_file_size) = self.max_file_size else {
+            return Ok(());
+        };
+        let full = self
+            .writers
+            .get(path)
+            .map(|file| file.writer.bytes_written() + file.writer.in_progress_size() >= max_file_size)
+            .unwrap_or(false);
+        if full {
+            self.close(path).await?;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self, path: &str) -> Result<()> {
+        if let Some(file) = self.writers.remove(path) {
+            file.writer.close().await?;
+            self.closed.push(file.key);
+        }
+        Ok(())
+    }
+
+    async fn writer(&mut self, path: &str) -> Result<&mut AsyncArrowWriter<Box<dyn AsyncFileWriter>>> {
+        if !self.writers.contains_key(path) {
+            if self.writers.len() >= self.max_open_files {
+                let oldest = self.writers.iter().min_by_key(|(_, file)| file.last_write).map(|(x, _)| x.clone());
+                if let Some(oldest) = oldest {
+                    tracing::debug!("closing file of partition: {oldest}, over {} open files", self.max_open_files);
+                    self.close(&oldest).await?;
+                }
+            }
+            let key = self.file_key(path);
+            let sink = self.storage.writer(&key, self.part_size);
+            let props = self.options.properties(&self.schema);
+            let writer = AsyncArrowWriter::try_new(sink, self.schema.clone(), Some(props))?;
+            self.writers.insert(path.to_string(), PartitionFile { key, writer, last_write: 0 });
+        }
+        self.writes += 1;
+        let file = self.writers.get_mut(path).expect("writer for partition exists");
+        file.last_write = self.writes;
+        Ok(&mut file.writer)
+    }
+
+    /// `{prefix}{partition}{file_name}`, following files of the partition get a number
+    fn file_key(&mut self, path: &str) -> String {
+        let n = self.files.entry(path.to_string()).or_default();
+        *n += 1;
+        match (*n, self.file_name.rsplit_once('.')) {
+            (1, _) => format!("{}{}{}", self.prefix, path, self.file_name),
+            (n, Some((stem, ext))) => format!("{}{}{}-{:05}.{}", self.prefix, path, stem, n, ext),
+            (n, None) => format!("{}{}{}-{:05}", self.prefix, path, self.file_name, n),
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// schema of the written files, without partition columns
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// closes all files, returns keys of the written files
+    pub async fn finish(mut self) -> Result<Vec<String>> {
+        let paths = self.pending.keys().cloned().collect::<Vec<_>>();
+        for path in paths {
+            self.flush(&path, true).await?;
+        }
+        if self.writers.is_empty() && self.closed.is_empty() {
+            // keep an empty file so the run is visible in the output
+            self.writer("").await?;
+        }
+        let mut keys = self.closed;
+        for (_, file) in self.writers {
+            file.writer.close().await?;
+            keys.push(file.key);
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// drops the writers, already started uploads are aborted
+    pub fn discard(self) {}
+}
+
+/// open file of a partition, `last_write` orders the files to close
+struct PartitionFile {
+    key: String,
+    writer: AsyncArrowWriter<Box<dyn AsyncFileWriter>>,
+    last_write: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::storage::{read_file, ObjectStoreStorage};
+    use crate::utils::aws::ObjectInfo;
+    use crate::utils::datafusion::read_parquet;
+
+    use datafusion::arrow::array::AsArray;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    #[tokio::test]
+    async fn test_sorted_row_groups() -> Result<()> {
+        let storage: StorageRef = Arc::new(ObjectStoreStorage::memory("memory://index"));
+        let options = WriterOptions {
+            row_group_size: 3,
+            bloom_filters: vec!["file_name".to_string()],
+            sort_by_file_path: true,
+            ..Default::default()
+        };
+        let mut writer = IndexWriter::new(storage.clone(), "index/", "a.parquet", 1024, vec![], KeyFields::default())?
+            .with_options(options);
+        for keys in [["e.csv", "b.csv"], ["d.csv", "a.csv"], ["c.csv", "f.csv"]] {
+            let records = keys
+                .iter()
+                .map(|x| FileData::new("raw", x.to_string(), ObjectInfo::default()))
+                .collect::<Vec<_>>();
+            writer.write(&records).await?;
+        }
+        assert_eq!(writer.finish().await?, vec!["index/a.parquet"]);
+
+        let reader = SerializedFileReader::new(read_file(storage.as_ref(), "index/a.parquet").await?)?;
+        let metadata = reader.metadata();
+        // the first four rows are sorted into a row group of three, the fourth is sorted with the last two
+        assert_eq!(metadata.num_row_groups(), 2);
+        for group in metadata.row_groups() {
+            assert!(group.sorting_columns().is_some());
+            let bloom = group.columns().iter().find(|x| x.column_path().string() == "file_name");
+            assert!(bloom.is_some_and(|x| x.bloom_filter_offset().is_some()));
+        }
+
+        let mut paths = vec![];
+        for batch in read_parquet(storage.as_ref(), "index/a.parquet").await? {
+            let col = batch.column_by_name("file_path").expect("file_path").as_string::<i32>();
+            paths.extend(col.iter().flatten().map(|x| x.to_string()));
+        }
+        assert_eq!(paths, ["a.csv", "b.csv", "d.csv", "c.csv", "e.csv", "f.csv"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_open_files() -> Result<()> {
+        let storage: StorageRef = Arc::new(ObjectStoreStorage::memory("memory://index"));
+        let partition_by = vec![PartitionColumn::FileType];
+        let mut writer = IndexWriter::new(storage.clone(), "index/", "a.parquet", 1024, partition_by, KeyFields::default())?
+            .with_max_open_files(1);
+        for key in ["a.csv", "b.json", "c.csv"] {
+            writer.write(&[FileData::new("raw", key.to_string(), ObjectInfo::default())]).await?;
+        }
+        // the csv file is closed for the json partition, later csv rows go to a new file
+        let keys = writer.finish().await?;
+        assert_eq!(
+            keys,
+            vec!["index/file_type=csv/a-00002.parquet", "index/file_type=csv/a.parquet", "index/file_type=json/a.parquet"]
+        );
+        let mut rows = 0;
+        for key in keys {
+            rows += read_parquet(storage.as_ref(), &key).await?.iter().map(|x| x.num_rows()).sum::<usize>();
+        }
+        assert_eq!(rows, 3);
+        Ok(())
+    }
+}
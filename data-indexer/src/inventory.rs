@@ -0,0 +1,547 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::storage::{read_file, Stores};
+use crate::utils::aws::ObjectInfo;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDateTime};
+use datafusion::arrow::array::{
+    Array, ArrayRef, AsArray, BooleanBuilder, RecordBatch, StringBuilder, StructArray,
+};
+use datafusion::arrow::buffer::NullBuffer;
+use datafusion::arrow::datatypes::{DataType, Field, Fields, Int64Type, Schema};
+use datafusion::datasource::file_format::file_compression_type::FileCompressionType;
+use datafusion::execution::SendableRecordBatchStream;
+use datafusion::prelude::*;
+use object_store::aws::AmazonS3Builder;
+use serde::Deserialize;
+use tokio_stream::StreamExt;
+use url::Url;
+
+/// columns read from the report, named as in the parquet and orc reports
+const COLUMNS: [&str; 16] = [
+    "bucket",
+    "key",
+    "version_id",
+    "is_latest",
+    "is_delete_marker",
+    "size",
+    "last_modified_date",
+    "e_tag",
+    "storage_class",
+    "is_multipart_uploaded",
+    "replication_status",
+    "encryption_status",
+    "object_lock_retain_until_date",
+    "object_lock_mode",
+    "object_lock_legal_hold_status",
+    "intelligent_tiering_access_tier",
+];
+
+/// `manifest.json` written by s3 inventory next to the report files
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub source_bucket: String,
+    /// arn of the bucket with the report files
+    pub destination_bucket: String,
+    pub file_format: InventoryFormat,
+    pub file_schema: String,
+    pub files: Vec<ManifestFile>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum InventoryFormat {
+    #[serde(rename = "CSV")]
+    Csv,
+    #[serde(rename = "ORC")]
+    Orc,
+    #[serde(rename = "Parquet", alias = "PARQUET")]
+    Parquet,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct ManifestFile {
+    pub key: String,
+    pub size: Option<i64>,
+}
+
+impl Manifest {
+    pub fn destination_bucket(&self) -> &str {
+        self.destination_bucket
+            .strip_prefix("arn:aws:s3:::")
+            .unwrap_or(&self.destination_bucket)
+    }
+
+    /// csv reports have no header, the columns are listed in the manifest,
+    /// e.g. `Bucket, Key, Size, LastModifiedDate`
+    pub fn csv_columns(&self) -> Vec<String> {
+        self.file_schema.split(',').map(snake_case).collect()
+    }
+}
+
+/// inventory fields not returned by ListObjectsV2, stored in the nested inventory column
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InventoryInfo {
+    pub is_multipart_uploaded: Option<bool>,
+    pub replication_status: Option<String>,
+    pub encryption_status: Option<String>,
+    pub object_lock_retain_until_date: Option<String>,
+    pub object_lock_mode: Option<String>,
+    pub object_lock_legal_hold_status: Option<String>,
+    pub intelligent_tiering_access_tier: Option<String>,
+}
+
+impl InventoryInfo {
+    pub fn fields() -> Fields {
+        Fields::from(vec![
+            Field::new("is_multipart_uploaded", DataType::Boolean, true),
+            Field::new("replication_status", DataType::Utf8, true),
+            Field::new("encryption_status", DataType::Utf8, true),
+            Field::new("object_lock_retain_until_date", DataType::Utf8, true),
+            Field::new("object_lock_mode", DataType::Utf8, true),
+            Field::new("object_lock_legal_hold_status", DataType::Utf8, true),
+            Field::new("intelligent_tiering_access_tier", DataType::Utf8, true),
+        ])
+    }
+
+    pub fn to_array(records: &[Option<&Self>]) -> Result<StructArray> {
+        let mut multipart = BooleanBuilder::new();
+        let mut replication = StringBuilder::new();
+        let mut encryption = StringBuilder::new();
+        let mut retain_until = StringBuilder::new();
+        let mut lock_mode = StringBuilder::new();
+        let mut legal_hold = StringBuilder::new();
+        let mut access_tier = StringBuilder::new();
+
+        for record in records {
+            let record = record.cloned().unwrap_or_default();
+            multipart.append_option(record.is_multipart_uploaded);
+            replication.append_option(record.replication_status);
+            encryption.append_option(record.encryption_status);
+            retain_until.append_option(record.object_lock_retain_until_date);
+            lock_mode.append_option(record.object_lock_mode);
+            legal_hold.append_option(record.object_lock_legal_hold_status);
+            access_tier.append_option(record.intelligent_tiering_access_tier);
+        }
+
+        let arrays: Vec<ArrayRef> = vec![
+            Arc::new(multipart.finish()),
+            Arc::new(replication.finish()),
+            Arc::new(encryption.finish()),
+            Arc::new(retain_until.finish()),
+            Arc::new(lock_mode.finish()),
+            Arc::new(legal_hold.finish()),
+            Arc::new(access_tier.finish()),
+        ];
+        let nulls = NullBuffer::from(records.iter().map(|x| x.is_some()).collect::<Vec<_>>());
+        Ok(StructArray::try_new(Self::fields(), arrays, Some(nulls))?)
+    }
+}
+
+/// s3 inventory report read with datafusion instead of listing the source bucket
+pub struct Inventory {
+    pub manifest: Manifest,
+    ctx: SessionContext,
+    paths: Vec<String>,
+}
+
+impl Inventory {
+    /// manifest location is `s3://bucket/key/manifest.json` or a local path
+    pub async fn load(stores: &Stores, location: &str) -> Result<Self> {
+        let Some(location) = location.strip_prefix("s3://") else {
+            return Self::from_local(Path::new(location));
+        };
+        let (bucket, key) = location
+            .split_once('/')
+            .ok_or_else(|| anyhow!("invalid inventory manifest location: s3://{location}"))?;
+        tracing::info!("reading inventory manifest: s3://{bucket}/{key}");
+        let storage = stores.open_location(bucket).await?;
+        let manifest: Manifest = serde_json::from_slice(&read_file(storage.as_ref(), key).await?)?;
+
+        let destination = manifest.destination_bucket().to_string();
+        let mut s3 = AmazonS3Builder::from_env().with_bucket_name(&destination);
+        if let Some(region) = stores.client().and_then(|x| x.config().region()) {
+            s3 = s3.with_region(region.as_ref());
+        }
+        let ctx = SessionContext::new();
+        ctx.runtime_env()
+            .register_object_store(&Url::parse(&format!("s3://{destination}"))?, Arc::new(s3.build()?));
+        let paths = manifest
+            .files
+            .iter()
+            .map(|file| format!("s3://{destination}/{}", file.key))
+            .collect();
+        Ok(Self { manifest, ctx, paths })
+    }
+
+    /// report files are looked up by their key in the directories above the manifest,
+    /// so a copy of the destination bucket can be read from disk
+    pub fn from_local(path: &Path) -> Result<Self> {
+        tracing::info!("reading inventory manifest: {}", path.display());
+        let manifest: Manifest = serde_json::from_slice(&std::fs::read(path)?)?;
+        let paths = manifest
+            .files
+            .iter()
+            .map(|file| {
+                path.ancestors()
+                    .skip(1)
+                    .map(|dir| dir.join(&file.key))
+                    .find(|x| x.is_file())
+                    .and_then(|x| x.canonicalize().ok())
+                    .map(|x: PathBuf| x.to_string_lossy().to_string())
+                    .ok_or_else(|| anyhow!("inventory file: {} not found above: {}", file.key, path.display()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            manifest,
+            ctx: SessionContext::new(),
+            paths,
+        })
+    }
+
+    async fn read(&self) -> Result<DataFrame> {
+        let df = match self.manifest.file_format {
+            InventoryFormat::Csv => {
+                let schema = Schema::new(
+                    self.manifest
+                        .csv_columns()
+                        .into_iter()
+                        .map(|name| Field::new(name, DataType::Utf8, true))
+                        .collect::<Vec<_>>(),
+                );
+                let compression = match self.paths.first() {
+                    Some(path) if path.ends_with(".gz") => FileCompressionType::GZIP,
+                    _ => FileCompressionType::UNCOMPRESSED,
+                };
+                let options = CsvReadOptions::new()
+                    .has_header(false)
+                    .schema(&schema)
+                    .file_extension("")
+                    .file_compression_type(compression);
+                self.ctx.read_csv(self.paths.clone(), options).await?
+            }
+            InventoryFormat::Parquet => {
+                let options = ParquetReadOptions {
+                    file_extension: "",
+                    ..Default::default()
+                };
+                self.ctx.read_parquet(self.paths.clone(), options).await?
+            }
+            InventoryFormat::Orc => {
+                return Err(anyhow!("orc inventory reports are not supported, use csv or parquet"));
+            }
+        };
+
+        let columns = COLUMNS
+            .iter()
+            .filter(|name| df.schema().field_with_unqualified_name(name).is_ok())
+            .map(|name| {
+                let data_type = if *name == "size" { DataType::Int64 } else { DataType::Utf8 };
+                cast(col(*name), data_type).alias(*name)
+            })
+            .collect::<Vec<_>>();
+        Ok(df.select(columns)?)
+    }
+
+    /// objects of the report under the prefix, page by page
+    /// with `versions` noncurrent versions and delete markers are kept,
+    /// the report must then be configured with all versions
+    pub async fn pages(self, prefix: &str, versions: bool) -> Result<InventoryPages> {
+        if self.paths.is_empty() {
+            return Err(anyhow!("inventory manifest has no files"));
+        }
+        let stream = self.read().await?.execute_stream().await?;
+        Ok(InventoryPages {
+            stream,
+            prefix: prefix.to_string(),
+            // csv reports have url encoded keys
+            encoded_keys: self.manifest.file_format == InventoryFormat::Csv,
+            versions,
+        })
+    }
+}
+
+pub struct InventoryPages {
+    stream: SendableRecordBatchStream,
+    prefix: String,
+    encoded_keys: bool,
+    versions: bool,
+}
+
+impl InventoryPages {
+    pub async fn next_page(&mut self) -> Result<Option<Vec<(String, ObjectInfo)>>> {
+        match self.stream.next().await.transpose()? {
+            Some(batch) => Ok(Some(objects(&batch, &self.prefix, self.encoded_keys, self.versions)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// objects of a report batch, noncurrent versions and delete markers are skipped unless `versions`
+pub fn objects(
+    batch: &RecordBatch,
+    prefix: &str,
+    encoded_keys: bool,
+    versions: bool,
+) -> Result<Vec<(String, ObjectInfo)>> {
+    let string = |name: &str| batch.column_by_name(name).map(|x| x.as_string::<i32>());
+    let keys = string("key").ok_or_else(|| anyhow!("inventory report has no key column"))?;
+    let sizes = batch.column_by_name("size").map(|x| x.as_primitive::<Int64Type>());
+    let value = |col: Option<&datafusion::arrow::array::StringArray>, i: usize| {
+        col.filter(|x| x.is_valid(i))
+            .map(|x| x.value(i))
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+    };
+    let flag = |name: &str, i: usize| value(string(name), i).map(|x| x.eq_ignore_ascii_case("true"));
+
+    let mut objects = vec![];
+    for i in 0..batch.num_rows() {
+        let Some(key) = value(Some(keys), i) else {
+            continue;
+        };
+        let key = if encoded_keys { decode_key(&key) } else { key };
+        let is_latest = flag("is_latest", i);
+        let is_delete_marker = flag("is_delete_marker", i);
+        let current = is_latest != Some(false) && is_delete_marker != Some(true);
+        if !key.starts_with(prefix) || key.ends_with('/') || !(current || versions) {
+            continue;
+        }
+        let inventory = InventoryInfo {
+            is_multipart_uploaded: flag("is_multipart_uploaded", i),
+            replication_status: value(string("replication_status"), i),
+            encryption_status: value(string("encryption_status"), i),
+            object_lock_retain_until_date: value(string("object_lock_retain_until_date"), i),
+            object_lock_mode: value(string("object_lock_mode"), i),
+            object_lock_legal_hold_status: value(string("object_lock_legal_hold_status"), i),
+            intelligent_tiering_access_tier: value(string("intelligent_tiering_access_tier"), i),
+        };
+        let info = ObjectInfo {
+            size: sizes.filter(|x| x.is_valid(i)).map(|x| x.value(i)),
+            last_modified: value(string("last_modified_date"), i).and_then(|x| format_dt(&x)),
+            etag: value(string("e_tag"), i),
+            storage_class: value(string("storage_class"), i),
+            owner: None,
+            checksum_algorithm: None,
+            inventory: Some(inventory),
+            version_id: value(string("version_id"), i),
+            is_latest,
+            is_delete_marker,
+        };
+        objects.push((key, info));
+    }
+    Ok(objects)
+}
+
+/// `LastModifiedDate` -> `last_modified_date`, `ETag` -> `e_tag`
+fn snake_case(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.trim().chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                out.push('_');
+            }
+            out.push(c.to_ascii_lowercase());
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// keys of csv reports and s3 event notifications are url encoded with `+` for spaces
+pub fn decode_key(key: &str) -> String {
+    let bytes = key.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => out.push(b' '),
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .filter(|x| x.iter().all(u8::is_ascii_hexdigit))
+                    .and_then(|x| std::str::from_utf8(x).ok());
+                match hex.and_then(|x| u8::from_str_radix(x, 16).ok()) {
+                    Some(byte) => {
+                        out.push(byte);
+                        i += 2;
+                    }
+                    None => out.push(b'%'),
+                }
+            }
+            byte => out.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// same format as the listed last modified, e.g. `2024-05-01T10:00:00Z`
+pub fn format_dt(dt: &str) -> Option<String> {
+    let dt = match DateTime::parse_from_rfc3339(dt) {
+        Ok(dt) => dt.naive_utc(),
+        Err(_) => NaiveDateTime::parse_from_str(dt, "%Y-%m-%dT%H:%M:%S%.f").ok()?,
+    };
+    Some(dt.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use datafusion::arrow::array::{BooleanArray, Int64Array, TimestampMillisecondArray};
+    use parquet::arrow::ArrowWriter;
+    use rstest::rstest;
+    use uuid::Uuid;
+
+    const FIXTURE: &str = concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/tests/fixtures/inventory/inventory-bucket/source-bucket/daily/2024-05-02T01-00Z/manifest.json"
+    );
+
+    #[rstest]
+    #[case("LastModifiedDate", "last_modified_date")]
+    #[case(" ETag", "e_tag")]
+    #[case("IntelligentTieringAccessTier", "intelligent_tiering_access_tier")]
+    fn test_snake_case(#[case] name: &str, #[case] expected: &str) {
+        assert_eq!(snake_case(name), expected);
+    }
+
+    #[rstest]
+    #[case("raw/a+b%2Bc.csv", "raw/a b+c.csv")]
+    #[case("raw/%C3%A9t%C3%A9.txt", "raw/été.txt")]
+    #[case("raw/100%", "raw/100%")]
+    #[case("raw/%zz", "raw/%zz")]
+    fn test_decode_key(#[case] key: &str, #[case] expected: &str) {
+        assert_eq!(decode_key(key), expected);
+    }
+
+    #[rstest]
+    #[case("2024-05-01T10:00:00.000Z", Some("2024-05-01T10:00:00Z"))]
+    #[case("2024-05-01T10:00:00", Some("2024-05-01T10:00:00Z"))]
+    #[case("2024-05-01T12:00:00+02:00", Some("2024-05-01T10:00:00Z"))]
+    #[case("yesterday", None)]
+    fn test_format_dt(#[case] dt: &str, #[case] expected: Option<&str>) {
+        assert_eq!(format_dt(dt).as_deref(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_csv_inventory() -> Result<()> {
+        let inventory = Inventory::from_local(Path::new(FIXTURE))?;
+        assert_eq!(inventory.manifest.source_bucket, "source-bucket");
+        assert_eq!(inventory.manifest.destination_bucket(), "inventory-bucket");
+
+        let mut pages = inventory.pages("raw/", false).await?;
+        let mut objects = vec![];
+        while let Some(page) = pages.next_page().await? {
+            objects.extend(page);
+        }
+        objects.sort_by(|a, b| a.0.cmp(&b.0));
+        let keys = objects.iter().map(|x| x.0.as_str()).collect::<Vec<_>>();
+        assert_eq!(keys, vec!["raw/a b.csv", "raw/images/scan.png"]);
+
+        let (_, info) = &objects[0];
+        assert_eq!(info.size, Some(1024));
+        assert_eq!(info.last_modified.as_deref(), Some("2024-05-01T10:00:00Z"));
+        assert_eq!(info.etag.as_deref(), Some("d41d8cd98f00b204e9800998ecf8427e"));
+        assert_eq!(info.storage_class.as_deref(), Some("STANDARD"));
+        let extra = info.inventory.clone().unwrap_or_default();
+        assert_eq!(extra.is_multipart_uploaded, Some(false));
+        assert_eq!(extra.encryption_status.as_deref(), Some("SSE-S3"));
+        assert_eq!(extra.replication_status, None);
+        assert_eq!(
+            objects[1].1.inventory.as_ref().and_then(|x| x.intelligent_tiering_access_tier.as_deref()),
+            Some("ARCHIVE_ACCESS")
+        );
+
+        // with versions the noncurrent version and the delete marker are kept
+        let mut pages = Inventory::from_local(Path::new(FIXTURE))?.pages("raw/", true).await?;
+        let mut objects = vec![];
+        while let Some(page) = pages.next_page().await? {
+            objects.extend(page);
+        }
+        objects.sort_by(|a, b| a.0.cmp(&b.0));
+        let versions = objects
+            .iter()
+            .map(|(key, x)| (key.as_str(), x.version_id.is_some(), x.is_latest, x.is_delete_marker))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            versions,
+            vec![
+                ("raw/a b.csv", false, Some(true), Some(false)),
+                ("raw/images/scan.png", false, Some(true), Some(false)),
+                ("raw/old.csv", true, Some(false), Some(false)),
+                ("raw/removed.csv", true, Some(true), Some(true)),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_parquet_inventory() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("inventory-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join("data"))?;
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("bucket", DataType::Utf8, false),
+            Field::new("key", DataType::Utf8, false),
+            Field::new("size", DataType::Int64, true),
+            Field::new(
+                "last_modified_date",
+                DataType::Timestamp(datafusion::arrow::datatypes::TimeUnit::Millisecond, None),
+                true,
+            ),
+            Field::new("is_multipart_uploaded", DataType::Boolean, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(datafusion::arrow::array::StringArray::from(vec!["source-bucket"; 2])),
+                Arc::new(datafusion::arrow::array::StringArray::from(vec!["raw/a+b.parquet", "other/c.txt"])),
+                Arc::new(Int64Array::from(vec![Some(10), None])),
+                Arc::new(TimestampMillisecondArray::from(vec![1714557600000, 1714557600000])),
+                Arc::new(BooleanArray::from(vec![true, false])),
+            ],
+        )?;
+        let mut writer = ArrowWriter::try_new(std::fs::File::create(dir.join("data/part-0.parquet"))?, schema, None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+        let manifest = r#"{
+            "sourceBucket": "source-bucket",
+            "destinationBucket": "arn:aws:s3:::inventory-bucket",
+            "fileFormat": "Parquet",
+            "fileSchema": "message s3.inventory { required binary bucket (STRING); }",
+            "files": [{"key": "data/part-0.parquet", "size": 1}]
+        }"#;
+        std::fs::write(dir.join("manifest.json"), manifest)?;
+
+        let mut pages = Inventory::from_local(&dir.join("manifest.json"))?.pages("raw/", false).await?;
+        let mut objects = vec![];
+        while let Some(page) = pages.next_page().await? {
+            objects.extend(page);
+        }
+        std::fs::remove_dir_all(&dir)?;
+
+        assert_eq!(objects.len(), 1);
+        let (key, info) = &objects[0];
+        // keys of parquet reports are not encoded
+        assert_eq!(key, "raw/a+b.parquet");
+        assert_eq!(info.size, Some(10));
+        assert_eq!(info.last_modified.as_deref(), Some("2024-05-01T10:00:00Z"));
+        assert_eq!(info.inventory.as_ref().and_then(|x| x.is_multipart_uploaded), Some(true));
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let dir = std::env::temp_dir().join(format!("inventory-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let manifest = r#"{"sourceBucket": "a", "destinationBucket": "b", "fileFormat": "CSV",
+            "fileSchema": "Bucket, Key", "files": [{"key": "data/missing.csv.gz"}]}"#;
+        std::fs::write(dir.join("manifest.json"), manifest).unwrap();
+        assert!(Inventory::from_local(&dir.join("manifest.json")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+use std::sync::Arc;
+
+use crate::file_data::FileData;
+
+use anyhow::{anyhow, Result};
+use datafusion::arrow::array::{
+    Array, ArrayRef, Float64Array, Int64Array, RecordBatch, StringArray,
+};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// column extracted from the object key, e.g.
+/// `{"name": "order_id", "source": "regex", "pattern": "orders/(\\d+)/", "type": "int64"}`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FieldSpec {
+    pub name: String,
+    #[serde(flatten)]
+    pub source: FieldSource,
+    #[serde(default, rename = "type")]
+    pub field_type: FieldType,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum FieldSource {
+    /// named group with the field name, first group or whole match
+    Regex { pattern: String },
+    /// path segment, negative index counts from the end
+    Segment { index: i64 },
+    /// key without the file name
+    ParentDir,
+    /// number of directories above the file
+    Depth,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldType {
+    #[default]
+    Utf8,
+    Int64,
+    Float64,
+}
+
+impl FieldType {
+    fn data_type(&self) -> DataType {
+        match self {
+            FieldType::Utf8 => DataType::Utf8,
+            FieldType::Int64 => DataType::Int64,
+            FieldType::Float64 => DataType::Float64,
+        }
+    }
+}
+
+struct KeyField {
+    spec: FieldSpec,
+    regex: Option<Regex>,
+}
+
+impl KeyField {
+    fn extract<'a>(&self, key: &'a str) -> Option<&'a str> {
+        match &self.spec.source {
+            FieldSource::Regex { .. } => {
+                let captures = self.regex.as_ref()?.captures(key)?;
+                captures
+                    .name(&self.spec.name)
+                    .or_else(|| captures.get(1))
+                    .or_else(|| captures.get(0))
+                    .map(|x| x.as_str())
+            }
+            FieldSource::Segment { index } => {
+                let segments = key.split('/').collect::<Vec<_>>();
+                let i = if *index < 0 {
+                    segments.len().checked_sub(index.unsigned_abs() as usize)?
+                } else {
+                    *index as usize
+                };
+                segments.get(i).copied()
+            }
+            FieldSource::ParentDir => key.rsplit_once('/').map(|(dir, _)| dir),
+            FieldSource::Depth => None,
+        }
+    }
+
+    fn to_array(&self, keys: &StringArray) -> ArrayRef {
+        if self.spec.source == FieldSource::Depth {
+            let depths = keys
+                .iter()
+                .map(|key| key.map(|x| x.matches('/').count() as i64))
+                .collect::<Vec<_>>();
+            return Arc::new(Int64Array::from(depths));
+        }
+
+        let values = keys
+            .iter()
+            .map(|key| key.and_then(|x| self.extract(x)))
+            .collect::<Vec<_>>();
+        match self.spec.field_type {
+            FieldType::Utf8 => Arc::new(StringArray::from(values)),
+            FieldType::Int64 => Arc::new(Int64Array::from(
+                values
+                    .iter()
+                    .map(|x| x.and_then(|v| v.parse().ok()))
+                    .collect::<Vec<_>>(),
+            )),
+            FieldType::Float64 => Arc::new(Float64Array::from(
+                values
+                    .iter()
+                    .map(|x| x.and_then(|v| v.parse().ok()))
+                    .collect::<Vec<_>>(),
+            )),
+        }
+    }
+
+    fn field(&self) -> Field {
+        let data_type = match self.spec.source {
+            FieldSource::Depth => DataType::Int64,
+            _ => self.spec.field_type.data_type(),
+        };
+        Field::new(&self.spec.name, data_type, true)
+    }
+}
+
+/// extra index columns extracted from the object key
+#[derive(Default)]
+pub struct KeyFields {
+    fields: Vec<KeyField>,
+}
+
+impl KeyFields {
+    pub fn try_new(specs: &[FieldSpec]) -> Result<Self> {
+        let schema = FileData::schema();
+        let mut fields: Vec<KeyField> = vec![];
+        for spec in specs {
+            if schema.field_with_name(&spec.name).is_ok()
+                || fields.iter().any(|x| x.spec.name == spec.name)
+            {
+                return Err(anyhow!("duplicated column name in fields: {}", spec.name));
+            }
+            let regex = match &spec.source {
+                FieldSource::Regex { pattern } => Some(Regex::new(pattern)?),
+                _ => None,
+            };
+            fields.push(KeyField {
+                spec: spec.clone(),
+                regex,
+            });
+        }
+        Ok(Self { fields })
+    }
+
+    pub fn schema(&self, schema: Schema) -> Schema {
+        let fields = schema
+            .fields()
+            .iter()
+            .map(|x| x.as_ref().clone())
+            .chain(self.fields.iter().map(|x| x.field()))
+            .collect::<Vec<_>>();
+        Schema::new(fields)
+    }
+
+    /// appends extracted columns to the batch, keys are read from file_path
+    pub fn append(&self, batch: RecordBatch) -> Result<RecordBatch> {
+        if self.fields.is_empty() {
+            return Ok(batch);
+        }
+        let keys = batch
+            .column_by_name("file_path")
+            .and_then(|col| col.as_any().downcast_ref::<StringArray>())
+            .ok_or_else(|| anyhow!("batch has no file_path column"))?;
+        let mut columns = batch.columns().to_vec();
+        columns.extend(self.fields.iter().map(|x| x.to_array(keys)));
+        let schema: SchemaRef = Arc::new(self.schema(batch.schema().as_ref().clone()));
+        Ok(RecordBatch::try_new(schema, columns)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use datafusion::arrow::array::AsArray;
+    use datafusion::arrow::datatypes::Int64Type;
+    use rstest::rstest;
+
+    fn spec(name: &str, source: FieldSource, field_type: FieldType) -> FieldSpec {
+        FieldSpec {
+            name: name.to_string(),
+            source,
+            field_type,
+        }
+    }
+
+    #[rstest]
+    #[case(FieldSource::Regex { pattern: r"orders/(?P<x>\d+)/".to_string() }, Some("123"))]
+    #[case(FieldSource::Regex { pattern: r"orders/(\d+)/".to_string() }, Some("123"))]
+    #[case(FieldSource::Regex { pattern: r"studies".to_string() }, None)]
+    #[case(FieldSource::Segment { index: 0 }, Some("data"))]
+    #[case(FieldSource::Segment { index: -2 }, Some("mri"))]
+    #[case(FieldSource::Segment { index: 10 }, None)]
+    #[case(FieldSource::Segment { index: -10 }, None)]
+    #[case(FieldSource::ParentDir, Some("data/orders/123/mri"))]
+    fn test_extract(#[case] source: FieldSource, #[case] expected: Option<&str>) {
+        let fields = KeyFields::try_new(&[spec("x", source, FieldType::Utf8)]).unwrap();
+        assert_eq!(fields.fields[0].extract("data/orders/123/mri/scan.dcm"), expected);
+    }
+
+    #[test]
+    fn test_append() -> Result<()> {
+        let specs = [
+            spec("order_id", FieldSource::Regex { pattern: r"orders/(\d+)/".to_string() }, FieldType::Int64),
+            spec("depth", FieldSource::Depth, FieldType::Utf8),
+        ];
+        let fields = KeyFields::try_new(&specs)?;
+        let schema = Schema::new(vec![Field::new("file_path", DataType::Utf8, true)]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![Arc::new(StringArray::from(vec![Some("orders/1/a.txt"), Some("b.txt"), None]))],
+        )?;
+        let batch = fields.append(batch)?;
+        let order_ids = batch.column_by_name("order_id").unwrap().as_primitive::<Int64Type>();
+        assert_eq!(order_ids.iter().collect::<Vec<_>>(), vec![Some(1), None, None]);
+        let depths = batch.column_by_name("depth").unwrap().as_primitive::<Int64Type>();
+        assert_eq!(depths.iter().collect::<Vec<_>>(), vec![Some(2), Some(0), None]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicated_name() {
+        let specs = [spec("file_name", FieldSource::ParentDir, FieldType::Utf8)];
+        assert!(KeyFields::try_new(&specs).is_err());
+    }
+
+    #[test]
+    fn test_deserialize() -> Result<()> {
+        let specs: Vec<FieldSpec> = serde_json::from_str(
+            r#"[
+                {"name": "order_id", "source": "regex", "pattern": "orders/(\\d+)/", "type": "int64"},
+                {"name": "study", "source": "segment", "index": 1},
+                {"name": "parent_dir", "source": "parent_dir"}
+            ]"#,
+        )?;
+        assert_eq!(specs[0].field_type, FieldType::Int64);
+        assert_eq!(specs[1].source, FieldSource::Segment { index: 1 });
+        assert_eq!(specs[2].source, FieldSource::ParentDir);
+        Ok(())
+    }
+}
@@ -1,56 +1,481 @@
+pub mod archives;
+pub mod catalog;
+pub mod cli;
+pub mod compact;
 pub mod config;
+pub mod deletions;
+pub mod delta;
+pub mod enrichment;
+pub mod events;
+pub mod duplicates;
+pub mod extractors;
 pub mod file_data;
+pub mod hashing;
+pub mod index_writer;
+pub mod inventory;
+pub mod key_fields;
+pub mod lister;
+pub mod manifest;
+pub mod partition;
+pub mod schema_version;
+pub mod snapshot;
+pub mod sorter;
+pub mod source;
+pub mod stats;
+pub mod storage;
 pub mod utils;
+pub mod watermark;
+pub mod writer_options;
 
-use config::Config;
-use datafusion::prelude::SessionContext;
-use file_data::FileData;
-use utils::{aws::list_keys_to_map, datafusion::write_df_to_s3};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+use std::time::Instant;
 
-use std::path::Path;
+use catalog::write_catalog;
+use compact::{run_keys, Compaction, Generation};
+use config::{Config, Mode, OutputFormat};
+use deletions::DeletionReport;
+use delta::DeltaTable;
+use duplicates::write_duplicates;
+use file_data::FileData;
+use schema_version::migrate_file;
+use snapshot::Snapshot;
+use source::{index_source, SourceOptions};
+use stats::{read_footers, unreadable, IndexStats, Verification};
+use index_writer::IndexWriter;
+use manifest::RunManifest;
+use storage::{ObjectPages, StorageRef, Stores};
+use utils::aws::ObjectInfo;
+use utils::constants::SOURCE_WORKERS;
+use watermark::{SourceWatermark, Watermark};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use aws_sdk_s3::Client;
+use chrono::Utc;
+use tokio::sync::{mpsc, Semaphore};
+use tokio::task::JoinSet;
 use uuid::Uuid;
 
 pub async fn handler(client: Client, config: Config) -> Result<()> {
     tracing::info!("start running handler for data indexer");
-    tracing::info!(
-        "reading data from: {}{}",
-        &config.prefix_source,
-        &config.item_name
-    );
-    let prefix = format!("{}{}", &config.prefix_source, &config.item_name);
-    let files = list_keys_to_map(client.clone(), &config.bucket_source, &prefix).await?;
-
-    tracing::info!("start processing data");
-    let mut file_data_all = vec![];
-    for (file, file_metatada) in files {
-        let path = Path::new(&file);
-        let file_name = path.file_name().map(|x| x.to_string_lossy().to_string());
-        let file_type = path.extension().map(|x| x.to_string_lossy().to_string());
-        let file_url = Some(format!("s3://{}/{}", &config.bucket_source, file));
-        let file_size = file_metatada.0;
-        let dt = file_metatada.1;
-        let file_data = FileData {
-            file_name,
-            file_type,
-            file_path: Some(file),
-            file_size,
-            file_url,
-            dt,
+    let stores = Stores::open(Some(client), &config).await?;
+    run(&stores, config).await
+}
+
+/// runs the mode of the config, buckets and directories of the config must be in the stores
+pub async fn run(stores: &Stores, config: Config) -> Result<()> {
+    let target = stores.get(&config.bucket_target)?;
+    match config.args.mode() {
+        Mode::Index => index_handler(stores, target, &config).await,
+        Mode::Catalog => catalog_handler(target, &config).await,
+        Mode::Compact => compact_handler(target, &config).await,
+        Mode::Verify => verify_handler(target, &config).await,
+        Mode::Stats => stats_handler(target, &config).await,
+        Mode::Migrate => migrate_handler(target, &config).await,
+        Mode::Vacuum => vacuum_handler(target, &config).await,
+    }
+}
+
+/// indexes the sources and records the run in a manifest, also when it fails
+async fn index_handler(stores: &Stores, target: StorageRef, config: &Config) -> Result<()> {
+    let started = Instant::now();
+    let id = Uuid::new_v4().to_string();
+    let mut manifest = RunManifest::new(&id, &Utc::now().to_rfc3339(), config);
+    let res = index(stores, target.clone(), config, &mut manifest).await;
+    if config.args.is_dry_run() {
+        return res;
+    }
+    manifest.finish(&res, started.elapsed().as_secs_f64());
+    match manifest.save(target.as_ref(), &config.prefix_target).await {
+        Ok(key) => tracing::info!("written run manifest: {}", key),
+        Err(e) if res.is_err() => tracing::error!("failed to write run manifest: {e:?}"),
+        Err(e) => return Err(e),
+    }
+    res
+}
+
+async fn index(stores: &Stores, target: StorageRef, config: &Config, manifest: &mut RunManifest) -> Result<()> {
+    let dry_run = config.args.is_dry_run();
+    let run_started = manifest.started.clone();
+
+    let sources = config.sources()?;
+    let incremental = config.args.is_incremental();
+    let mut track_deletions = config.args.with_deletions();
+    if track_deletions && incremental && config.args.is_append_only() {
+        tracing::warn!("append only runs list new keys only, deletions are not tracked");
+        track_deletions = false;
+    }
+    let watermark = if incremental || track_deletions {
+        Watermark::load(target.as_ref(), &config.prefix_target).await?
+    } else {
+        None
+    };
+    let snapshot = match &watermark {
+        Some(watermark) => {
+            let urls = sources.iter().map(|x| x.url()).collect::<Vec<_>>();
+            Snapshot::load(target.as_ref(), &watermark.snapshot, &urls).await?
+        }
+        None => Snapshot::default(),
+    };
+    // full runs index every listed file, their snapshot is only used to find deletions
+    let watermark = watermark.filter(|_| incremental);
+    match &watermark {
+        Some(watermark) => tracing::info!("running incremental index with {}", watermark),
+        None => tracing::info!("running full index"),
+    }
+
+    let id = manifest.run.clone();
+    // the files of a delta run are written into the table and committed at the end
+    let delta = config.args.output() == OutputFormat::Delta;
+    let mut writer = IndexWriter::for_run(target.clone(), config, &format!("id={id}-table=data_index.parquet"))?;
+    let summary = &mut manifest.summary;
+    summary.sources = sources.len();
+
+    let options = Arc::new(SourceOptions {
+        snapshot,
+        incremental,
+        track_deletions,
+        ..SourceOptions::from_config(config)
+    });
+
+    tracing::info!("start processing data from {} sources", sources.len());
+    let (tx, mut rx) = mpsc::channel::<Vec<FileData>>(SOURCE_WORKERS * 2);
+    let sem = Arc::new(Semaphore::new(SOURCE_WORKERS));
+    let mut tasks = JoinSet::new();
+    for source in sources.iter().cloned() {
+        let start_after = watermark
+            .as_ref()
+            .filter(|_| config.args.is_append_only())
+            .and_then(|x| x.source(&source.url(), sources.len() == 1))
+            .and_then(|x| x.last_key);
+        let stores = stores.clone();
+        let options = options.clone();
+        let tx = tx.clone();
+        let sem = sem.clone();
+        tasks.spawn(async move {
+            let _permit = sem.acquire_owned().await?;
+            let listing = index_source(stores, source.clone(), start_after, options, tx).await?;
+            Ok::<_, anyhow::Error>((source, listing))
+        });
+    }
+    drop(tx);
+
+    // pages of all sources go into the same files, so the run has one snapshot
+    let mut urls = BTreeSet::new();
+    while let Some(page) = rx.recv().await {
+        summary.add(&page);
+        if delta {
+            urls.extend(page.iter().filter_map(|x| x.file_url.clone()));
+        }
+        if !dry_run {
+            writer.write(&page).await?;
+        }
+    }
+    let mut listings = vec![];
+    while let Some(task) = tasks.join_next().await {
+        listings.push(task??);
+    }
+    listings.sort_by_key(|(source, _)| source.url());
+    summary.listed = listings.iter().map(|(_, x)| x.listed).sum::<usize>();
+    summary.errors = listings.iter().map(|(_, x)| x.errors).sum::<usize>();
+    tracing::info!("listed files: {} new or changed: {}", summary.listed, summary.new_or_changed);
+
+    if track_deletions {
+        let mut deleted = vec![];
+        let mut tombstones = vec![];
+        for (source, _) in &listings {
+            for (url, entry) in options.snapshot.deleted(&source.url()) {
+                let Some(key) = source.key(&url) else {
+                    continue;
+                };
+                let info = ObjectInfo {
+                    size: entry.file_size,
+                    last_modified: entry.dt.clone(),
+                    ..Default::default()
+                };
+                let tombstone = FileData::tombstone(&source.bucket, key.to_string(), info, &run_started)
+                    .with_dataset(source.dataset.as_deref());
+                tombstones.push(tombstone);
+                deleted.push((url, entry));
+            }
+        }
+        tracing::info!("deleted files since the previous run: {}", deleted.len());
+        summary.deleted = deleted.len();
+        if delta {
+            urls.extend(tombstones.iter().filter_map(|x| x.file_url.clone()));
+        }
+        if !deleted.is_empty() && !dry_run {
+            writer.write(&tombstones).await?;
+            let report = DeletionReport::new(&id, &run_started, &deleted);
+            let key = report.save(target.as_ref(), &config.prefix_target).await?;
+            tracing::info!("written deletion report: {}", key);
+            manifest.outputs.push(key);
+        }
+    }
+
+    if dry_run {
+        writer.discard();
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        return Ok(());
+    }
+
+    let mut snapshot_keys = watermark.as_ref().map(|x| x.snapshot.clone()).unwrap_or_default();
+    if writer.rows() == 0 && !snapshot_keys.is_empty() {
+        tracing::info!("no new or changed files found");
+        writer.discard();
+    } else {
+        let schema = writer.schema();
+        let keys = writer.finish().await?;
+        tracing::info!("written index files: {:?}", keys);
+        manifest.outputs.extend(keys.iter().cloned());
+        if delta {
+            // the snapshot of a delta run is the table after the merge
+            let table = DeltaTable::new(target.clone(), config.args.combined_prefix())
+                .with_options(config.args.writer_options()?);
+            let state = table.merge(&keys, &urls, &schema).await?;
+            snapshot_keys = state.files.keys().map(|x| table.key(x)).collect();
+        } else {
+            snapshot_keys.extend(keys);
+        }
+    }
+
+    let mut sources_watermark = watermark.as_ref().map(|x| x.sources.clone()).unwrap_or_default();
+    for (source, listing) in listings {
+        let previous = watermark
+            .as_ref()
+            .and_then(|x| x.source(&source.url(), sources.len() == 1))
+            .unwrap_or_default();
+        let source_watermark = SourceWatermark {
+            last_modified: previous.last_modified.max(listing.watermark.last_modified),
+            last_key: previous.last_key.max(listing.watermark.last_key),
         };
-        file_data_all.push(file_data);
+        sources_watermark.insert(source.url(), source_watermark);
+    }
+    let watermark = Watermark {
+        last_run: run_started,
+        last_modified: sources_watermark.values().filter_map(|x| x.last_modified.clone()).max(),
+        last_key: sources_watermark.values().filter_map(|x| x.last_key.clone()).max(),
+        snapshot: snapshot_keys,
+        sources: sources_watermark,
+    };
+    tracing::info!("saving {}", watermark);
+    watermark.save(target.as_ref(), &config.prefix_target).await?;
+
+    if config.args.with_catalog() {
+        catalog_handler(target.clone(), config).await?;
+    }
+    if config.args.with_content_hash() {
+        duplicates_handler(target, config).await?;
+    }
+    Ok(())
+}
+
+/// combined files of the current generation and the per-run files not compacted yet,
+/// or the files of the delta table
+async fn index_keys(target: StorageRef, config: &Config) -> Result<Vec<String>> {
+    if config.args.output() == OutputFormat::Delta {
+        let table = DeltaTable::new(target, config.args.combined_prefix());
+        let state = table.load().await?;
+        return Ok(state.files.keys().map(|x| table.key(x)).collect());
+    }
+    let generation = Generation::load(target.as_ref(), config.args.combined_prefix()).await?;
+    let mut keys = generation.map(|x| x.keys).unwrap_or_default();
+    keys.extend(uncompacted_keys(target, config).await?);
+    Ok(keys)
+}
+
+async fn uncompacted_keys(target: StorageRef, config: &Config) -> Result<Vec<String>> {
+    run_keys(
+        target,
+        &config.prefix_target,
+        &[
+            config.args.combined_prefix(),
+            config.args.catalog_prefix(),
+            config.args.duplicates_prefix(),
+        ],
+    )
+    .await
+}
+
+async fn catalog_handler(target: StorageRef, config: &Config) -> Result<()> {
+    tracing::info!("building catalog from: {}", &config.prefix_target);
+    let keys = index_keys(target.clone(), config).await?;
+    if config.args.is_dry_run() {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "index_files": keys }))?);
+        return Ok(());
+    }
+    let key = write_catalog(target.as_ref(), &keys, config.args.catalog_prefix()).await?;
+    tracing::info!("written catalog: {}", key);
+    Ok(())
+}
+
+async fn duplicates_handler(target: StorageRef, config: &Config) -> Result<()> {
+    tracing::info!("finding duplicated files in: {}", &config.prefix_target);
+    let keys = index_keys(target.clone(), config).await?;
+    let key = write_duplicates(target.as_ref(), &keys, config.args.duplicates_prefix()).await?;
+    tracing::info!("written duplicates: {}", key);
+    Ok(())
+}
+
+async fn compact_handler(target: StorageRef, config: &Config) -> Result<()> {
+    let combined_prefix = config.args.combined_prefix();
+    tracing::info!("compacting index files from: {} to: {}", &config.prefix_target, combined_prefix);
+    let compaction = Compaction::try_new(target.clone(), config)?;
+    let current = Generation::load(target.as_ref(), combined_prefix).await?;
+    let runs = uncompacted_keys(target.clone(), config).await?;
+    if config.args.is_dry_run() {
+        let summary = serde_json::json!({
+            "generation": current.as_ref().map(|x| x.generation.clone()),
+            "generation_files": current.as_ref().map(|x| x.keys.len()).unwrap_or_default(),
+            "run_files": runs,
+        });
+        println!("{}", serde_json::to_string_pretty(&summary)?);
+        return Ok(());
+    }
+    let Some(next) = compaction.run(current.as_ref(), &runs).await? else {
+        tracing::info!("no index files to compact");
+        return Ok(());
+    };
+    tracing::info!("written {} rows to: {:?}", next.rows, next.keys);
+
+    let mut compacted = runs.clone();
+    compacted.extend(current.iter().flat_map(|x| x.keys.clone()));
+    compaction.replace(current, &next, runs).await?;
+
+    // incremental runs diff against the combined files from now on
+    if let Some(mut watermark) = Watermark::load(target.as_ref(), &config.prefix_target).await? {
+        watermark.snapshot.retain(|x| !compacted.contains(x));
+        watermark.snapshot.extend(next.keys.clone());
+        watermark.save(target.as_ref(), &config.prefix_target).await?;
+    }
+
+    if config.args.with_catalog() {
+        catalog_handler(target.clone(), config).await?;
+    }
+    if config.args.with_content_hash() {
+        duplicates_handler(target, config).await?;
+    }
+    Ok(())
+}
+
+async fn migrate_handler(target: StorageRef, config: &Config) -> Result<()> {
+    tracing::info!("migrating index files of: {}", &config.prefix_target);
+    let keys = index_keys(target.clone(), config).await?;
+    if config.args.is_dry_run() {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "index_files": keys }))?);
+        return Ok(());
     }
+    let options = config.args.writer_options()?;
+    let mut migrated = 0;
+    for key in &keys {
+        if migrate_file(target.as_ref(), key, &options).await? {
+            migrated += 1;
+        }
+    }
+    tracing::info!("upgraded {} of {} index files", migrated, keys.len());
+
+    // catalog and duplicates are rebuilt with the current schema
+    if config.args.with_catalog() {
+        catalog_handler(target.clone(), config).await?;
+    }
+    if config.args.with_content_hash() {
+        duplicates_handler(target, config).await?;
+    }
+    Ok(())
+}
 
-    tracing::info!("start converting to df");
-    let ctx = SessionContext::new();
-    let df = FileData::to_df(ctx, &file_data_all).await?;
+/// deletes files the delta table no longer references once they are older than the retention
+async fn vacuum_handler(target: StorageRef, config: &Config) -> Result<()> {
+    let retention = config.args.vacuum_retention_hours();
+    tracing::info!("vacuuming delta table: {} with retention: {}h", config.args.combined_prefix(), retention);
+    let table = DeltaTable::new(target.clone(), config.args.combined_prefix());
+    let stale = table.stale_files(retention).await?;
+    if config.args.is_dry_run() {
+        println!("{}", serde_json::to_string_pretty(&serde_json::json!({ "stale_files": stale }))?);
+        return Ok(());
+    }
+    let deleted = stale.len();
+    target.delete(stale).await?;
+    tracing::info!("deleted {} files of the delta table", deleted);
+    Ok(())
+}
 
-    let id = Uuid::new_v4();
-    let prefix_target = &config.prefix_target;
-    let key = format!("{prefix_target}id={id}-table=data_index.parquet");
-    tracing::info!("writing file to s3: {}", key);
-    write_df_to_s3(client, &config.bucket_target, &key, df).await?;
+/// index files of the target bucket with their size
+async fn listed_index_files(target: StorageRef, config: &Config) -> Result<HashMap<String, ObjectInfo>> {
+    let mut prefixes = vec![config.prefix_target.as_str()];
+    let combined_prefix = config.args.combined_prefix();
+    if !combined_prefix.starts_with(&config.prefix_target) {
+        prefixes.push(combined_prefix);
+    }
+    let mut listed = HashMap::new();
+    for prefix in prefixes {
+        let mut pages = ObjectPages::new(target.clone(), prefix, None);
+        while let Some(page) = pages.next_page().await? {
+            listed.extend(page);
+        }
+    }
+    Ok(listed)
+}
+
+async fn stats_handler(target: StorageRef, config: &Config) -> Result<()> {
+    tracing::info!("reading index stats of: {}", &config.prefix_target);
+    let watermark = Watermark::load(target.as_ref(), &config.prefix_target).await?;
+    let (generation, keys, uncompacted) = match config.args.output() {
+        OutputFormat::Parquet => {
+            let generation = Generation::load(target.as_ref(), config.args.combined_prefix()).await?;
+            let uncompacted = uncompacted_keys(target.clone(), config).await?;
+            let mut keys = generation.as_ref().map(|x| x.keys.clone()).unwrap_or_default();
+            keys.extend(uncompacted.iter().cloned());
+            (generation.map(|x| x.generation), keys, uncompacted)
+        }
+        OutputFormat::Delta => {
+            let table = DeltaTable::new(target.clone(), config.args.combined_prefix());
+            let state = table.load().await?;
+            let keys = state.files.keys().map(|x| table.key(x)).collect();
+            (state.version.map(|x| format!("version={x}")), keys, vec![])
+        }
+    };
+
+    let listed = listed_index_files(target.clone(), config).await?;
+    let records = read_footers(target, &keys, &listed).await?;
+    let stats = IndexStats {
+        generation,
+        last_run: watermark.as_ref().map(|x| x.last_run.clone()),
+        last_modified: watermark.and_then(|x| x.last_modified),
+        index_files: records.len(),
+        uncompacted_files: uncompacted.len(),
+        index_bytes: records.iter().filter_map(|x| x.file_size).sum(),
+        rows: records
+            .iter()
+            .filter_map(|x| x.metadata.as_ref().and_then(|m| m.row_count))
+            .sum(),
+    };
+    println!("{}", serde_json::to_string_pretty(&stats)?);
+    Ok(())
+}
+
+async fn verify_handler(target: StorageRef, config: &Config) -> Result<()> {
+    tracing::info!("verifying index files of: {}", &config.prefix_target);
+    let watermark = Watermark::load(target.as_ref(), &config.prefix_target).await?;
+    let mut keys = index_keys(target.clone(), config).await?;
+    keys.extend(watermark.map(|x| x.snapshot).unwrap_or_default());
+    keys.sort();
+    keys.dedup();
+
+    let listed = listed_index_files(target.clone(), config).await?;
+    let records = read_footers(target, &keys, &listed).await?;
+    let verification = Verification {
+        checked: keys.len(),
+        missing: keys.iter().filter(|x| !listed.contains_key(*x)).cloned().collect(),
+        unreadable: unreadable(&records),
+    };
+    println!("{}", serde_json::to_string_pretty(&verification)?);
+    if !verification.is_ok() {
+        return Err(anyhow!(
+            "index verification failed, missing files: {} unreadable files: {}",
+            verification.missing.len(),
+            verification.unreadable.len()
+        ));
+    }
     Ok(())
 }
@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+
+use crate::inventory::InventoryPages;
+use crate::storage::{ObjectPages, StorageRef};
+use crate::utils::aws::ObjectInfo;
+use crate::utils::constants::*;
+
+use anyhow::Result;
+use tokio::task::JoinSet;
+
+/// keys under the prefix after `start_after` up to and including `end`
+#[derive(Debug, Clone, PartialEq)]
+struct Shard {
+    prefix: String,
+    start_after: Option<String>,
+    end: Option<String>,
+}
+
+impl Shard {
+    fn contains(&self, key: &str) -> bool {
+        self.end.as_deref().map(|end| key <= end).unwrap_or(true)
+    }
+}
+
+/// lists a prefix with concurrent list requests: common prefixes found
+/// with a delimiter become shards, shards with many pages are split by key range
+pub struct ShardedLister {
+    storage: StorageRef,
+    prefix: String,
+    start_after: Option<String>,
+    workers: usize,
+    split_pages: usize,
+}
+
+impl ShardedLister {
+    pub fn new(storage: StorageRef, prefix: &str, start_after: Option<&str>) -> Self {
+        Self {
+            storage,
+            prefix: prefix.to_string(),
+            start_after: start_after.map(|x| x.to_string()),
+            workers: LIST_WORKERS,
+            split_pages: LIST_SPLIT_PAGES,
+        }
+    }
+
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// all objects sorted by key, the result does not depend on the order requests finish
+    pub async fn list(&self) -> Result<Vec<(String, ObjectInfo)>> {
+        let (mut objects, shards) = self.discover().await?;
+        tracing::info!("listing {} shards of: {}", shards.len(), self.prefix);
+
+        let mut queue = VecDeque::from(shards);
+        let mut tasks = JoinSet::new();
+        while !queue.is_empty() || !tasks.is_empty() {
+            while tasks.len() < self.workers {
+                let Some(shard) = queue.pop_front() else {
+                    break;
+                };
+                let storage = self.storage.clone();
+                let split_pages = self.split_pages;
+                tasks.spawn(async move { list_shard(storage, shard, split_pages).await });
+            }
+            if let Some(task) = tasks.join_next().await {
+                let (listed, rest) = task??;
+                objects.extend(listed);
+                queue.extend(rest);
+            }
+        }
+
+        objects.sort_by(|a, b| a.0.cmp(&b.0));
+        objects.dedup_by(|a, b| a.0 == b.0);
+        Ok(objects)
+    }
+
+    /// objects directly under the prefix and shards for its common prefixes,
+    /// a single common prefix is expanded until there are enough shards
+    async fn discover(&self) -> Result<(Vec<(String, ObjectInfo)>, Vec<Shard>)> {
+        let mut objects = vec![];
+        let mut prefixes = vec![self.prefix.clone()];
+        for _ in 0..LIST_SHARD_DEPTH {
+            let mut next = vec![];
+            for prefix in &prefixes {
+                let (listed, common) = list_level(&self.storage, prefix).await?;
+                objects.extend(
+                    listed
+                        .into_iter()
+                        .filter(|(key, _)| self.start_after.as_ref().map(|x| key > x).unwrap_or(true)),
+                );
+                next.extend(common);
+            }
+            prefixes = next;
+            if prefixes.is_empty() || prefixes.len() >= self.workers {
+                break;
+            }
+        }
+
+        let shards = prefixes
+            .into_iter()
+            .map(|prefix| Shard {
+                prefix,
+                start_after: self.start_after.clone(),
+                end: None,
+            })
+            .collect();
+        Ok((objects, shards))
+    }
+}
+
+/// pages of a source, listed page by page or all shards at once
+pub enum SourcePages {
+    Sequential(ObjectPages),
+    Sharded(VecDeque<Vec<(String, ObjectInfo)>>),
+    Inventory(InventoryPages),
+}
+
+impl SourcePages {
+    pub async fn sharded(lister: ShardedLister) -> Result<Self> {
+        let objects = lister.list().await?;
+        let mut pages = VecDeque::new();
+        let mut objects = objects.into_iter().peekable();
+        while objects.peek().is_some() {
+            pages.push_back(objects.by_ref().take(LIST_PAGE_SIZE).collect());
+        }
+        Ok(Self::Sharded(pages))
+    }
+
+    pub async fn next_page(&mut self) -> Result<Option<Vec<(String, ObjectInfo)>>> {
+        match self {
+            SourcePages::Sequential(pages) => pages.next_page().await,
+            SourcePages::Sharded(pages) => Ok(pages.pop_front()),
+            SourcePages::Inventory(pages) => pages.next_page().await,
+        }
+    }
+}
+
+/// one level of the prefix with the `/` delimiter
+async fn list_level(storage: &StorageRef, prefix: &str) -> Result<(Vec<(String, ObjectInfo)>, Vec<String>)> {
+    let mut objects = vec![];
+    let mut prefixes = vec![];
+    let mut next = None;
+    loop {
+        let page = storage.list_page(prefix, true, None, next).await?;
+        objects.extend(page.objects);
+        prefixes.extend(page.prefixes);
+        next = page.next;
+        if next.is_none() {
+            return Ok((objects, prefixes));
+        }
+    }
+}
+
+/// lists the shard, after `split_pages` pages the rest of the range
+/// is returned as two new shards to be listed concurrently
+async fn list_shard(
+    storage: StorageRef,
+    shard: Shard,
+    split_pages: usize,
+) -> Result<(Vec<(String, ObjectInfo)>, Vec<Shard>)> {
+    let mut pages = ObjectPages::new(storage, &shard.prefix, shard.start_after.as_deref());
+    let mut objects: Vec<(String, ObjectInfo)> = vec![];
+    let mut n = 0;
+    while let Some(page) = pages.next_page().await? {
+        n += 1;
+        let done = page.last().map(|(key, _)| !shard.contains(key)).unwrap_or(false);
+        objects.extend(page.into_iter().filter(|(key, _)| shard.contains(key)));
+        if done || pages.is_done() {
+            return Ok((objects, vec![]));
+        }
+        if n < split_pages {
+            continue;
+        }
+        let Some(last) = objects.last().map(|x| x.0.clone()) else {
+            continue;
+        };
+        let upper = shard.end.clone().unwrap_or_else(|| format!("{}\u{7f}", shard.prefix));
+        if let Some(mid) = midpoint(&last, &upper) {
+            let rest = vec![
+                Shard {
+                    prefix: shard.prefix.clone(),
+                    start_after: Some(last),
+                    end: Some(mid.clone()),
+                },
+                Shard {
+                    prefix: shard.prefix.clone(),
+                    start_after: Some(mid),
+                    end: shard.end.clone(),
+                },
+            ];
+            return Ok((objects, rest));
+        }
+    }
+    Ok((objects, vec![]))
+}
+
+/// printable ascii key between `low` and `high`, none if they are too close
+/// or the keys are not ascii at the position they differ
+fn midpoint(low: &str, high: &str) -> Option<String> {
+    if low >= high {
+        return None;
+    }
+    let low = low.as_bytes();
+    let high = high.as_bytes();
+    let i = low.iter().zip(high).take_while(|(a, b)| a == b).count();
+    let mut mid = low[..i].to_vec();
+    let l = low.get(i).copied().unwrap_or(b' ' - 1);
+    let h = high.get(i).copied()?;
+    if !h.is_ascii() || !l.is_ascii() {
+        return None;
+    }
+    let m = l + (h - l) / 2;
+    if m > l {
+        mid.push(m);
+    } else {
+        // adjacent characters, go one level deeper below the end of the range
+        mid.push(l);
+        let next = low.get(i + 1).copied().unwrap_or(b' ' - 1);
+        if !next.is_ascii() || next >= b'~' {
+            return None;
+        }
+        mid.push(next + (b'~' + 1 - next) / 2);
+    }
+    let mid = String::from_utf8(mid).ok()?;
+    (mid.as_bytes() > low && mid.as_bytes() < high).then_some(mid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("data/a", "data/z")]
+    #[case("data/a", "data/b")]
+    #[case("data/2024/01/x.csv", "data/\u{7f}")]
+    #[case("data/", "data/\u{7f}")]
+    #[case("a", "a0")]
+    fn test_midpoint(#[case] low: &str, #[case] high: &str) {
+        let mid = midpoint(low, high).unwrap();
+        assert!(low < mid.as_str() && mid.as_str() < high, "{low} < {mid} < {high}");
+    }
+
+    #[rstest]
+    #[case("data/b", "data/a")]
+    #[case("data/a", "data/a")]
+    #[case("a~", "b")]
+    fn test_midpoint_none(#[case] low: &str, #[case] high: &str) {
+        assert_eq!(midpoint(low, high), None);
+    }
+
+    #[test]
+    fn test_shard_contains() {
+        let shard = Shard {
+            prefix: "data/".to_string(),
+            start_after: Some("data/a".to_string()),
+            end: Some("data/m".to_string()),
+        };
+        assert!(shard.contains("data/b"));
+        assert!(shard.contains("data/m"));
+        assert!(!shard.contains("data/m0"));
+    }
+}
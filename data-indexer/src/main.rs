@@ -1,20 +1,21 @@
 use std::time::Instant;
 
-use data_indexer::config::Config;
+use data_indexer::cli::Cli;
 use data_indexer::handler;
 use data_indexer::utils::aws::get_aws_client;
-use data_indexer::utils::constants::*;
 use data_indexer::utils::tracing::init_tracing;
 
 use anyhow::Result;
+use clap::Parser;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
     init_tracing();
     tracing::info!("start processing");
     let now = Instant::now();
-    let client = get_aws_client(REGION).await;
-    let config = Config::new()?;
+    let config = cli.config()?;
+    let client = get_aws_client(config.args.region()).await;
     handler(client, config).await?;
     tracing::info!("end processing elapsed: {:.2?}", now.elapsed());
     Ok(())
@@ -0,0 +1,95 @@
+use crate::config::{Config, Mode};
+use crate::stats::RunSummary;
+use crate::storage::Storage;
+use crate::utils::constants::{MANIFESTS_DIR, MANIFEST_FILE};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// durable record of an index run, one per run under `_manifests/`
+/// and the last one as `_manifest.json` next to the index files
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RunManifest {
+    pub run: String,
+    pub mode: Mode,
+    pub started: String,
+    pub finished: Option<String>,
+    pub duration_secs: f64,
+    pub success: bool,
+    pub error: Option<String>,
+    /// source urls listed in the run
+    pub sources: Vec<String>,
+    pub summary: RunSummary,
+    /// index files and deletion report written by the run
+    pub outputs: Vec<String>,
+    pub config: Config,
+}
+
+impl RunManifest {
+    pub fn new(run: &str, started: &str, config: &Config) -> Self {
+        Self {
+            run: run.to_string(),
+            mode: config.args.mode(),
+            started: started.to_string(),
+            finished: None,
+            duration_secs: 0.0,
+            success: false,
+            error: None,
+            sources: config
+                .sources()
+                .map(|x| x.iter().map(|x| x.url()).collect())
+                .unwrap_or_default(),
+            summary: RunSummary::default(),
+            outputs: vec![],
+            config: config.clone(),
+        }
+    }
+
+    pub fn key(prefix_target: &str, run: &str) -> String {
+        format!("{prefix_target}{MANIFESTS_DIR}id={run}-manifest.json")
+    }
+
+    pub fn latest_key(prefix_target: &str) -> String {
+        format!("{prefix_target}{MANIFEST_FILE}")
+    }
+
+    pub fn finish(&mut self, res: &Result<()>, duration_secs: f64) {
+        self.finished = Some(chrono::Utc::now().to_rfc3339());
+        self.duration_secs = duration_secs;
+        self.success = res.is_ok();
+        self.error = res.as_ref().err().map(|e| format!("{e:#}"));
+    }
+
+    /// writes the manifest of the run, then replaces the latest one
+    pub async fn save(&self, storage: &dyn Storage, prefix_target: &str) -> Result<String> {
+        let key = Self::key(prefix_target, &self.run);
+        let data = bytes::Bytes::from(serde_json::to_vec_pretty(self)?);
+        storage.put(&key, data.clone()).await?;
+        storage.put(&Self::latest_key(prefix_target), data).await?;
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_finish() -> Result<()> {
+        let config = Config::create("raw", "index", "mri/", "index/", "", r#"{"incremental": true}"#)?;
+        let mut manifest = RunManifest::new("1", "2024-05-02T00:00:00Z", &config);
+        assert_eq!(manifest.sources, vec!["s3://raw/mri/"]);
+
+        manifest.finish(&Err(anyhow!("listing failed")), 1.5);
+        assert!(!manifest.success);
+        assert_eq!(manifest.error.as_deref(), Some("listing failed"));
+
+        let json = serde_json::to_value(&manifest)?;
+        assert_eq!(json["summary"]["listed"], 0);
+        assert_eq!(json["config"]["args"]["incremental"], true);
+        assert_eq!(RunManifest::key("index/", "1"), "index/_manifests/id=1-manifest.json");
+        Ok(())
+    }
+}
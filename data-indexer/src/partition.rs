@@ -0,0 +1,142 @@
+use std::str::FromStr;
+
+use crate::file_data::FileData;
+
+use anyhow::{anyhow, Error, Result};
+use datafusion::arrow::array::{Array, RecordBatch, StringArray};
+
+/// value used by hive for partitions without a value
+pub const HIVE_DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+
+/// column the index output can be hive partitioned by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PartitionColumn {
+    Year,
+    Month,
+    FileType,
+}
+
+impl PartitionColumn {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PartitionColumn::Year => "year",
+            PartitionColumn::Month => "month",
+            PartitionColumn::FileType => "file_type",
+        }
+    }
+
+    pub fn value(&self, dt: Option<&str>, file_type: Option<&str>) -> Option<String> {
+        match self {
+            PartitionColumn::Year => dt.and_then(|x| x.get(0..4)).map(|x| x.to_string()),
+            PartitionColumn::Month => dt.and_then(|x| x.get(5..7)).map(|x| x.to_string()),
+            PartitionColumn::FileType => file_type.map(|x| x.to_string()),
+        }
+    }
+}
+
+impl FromStr for PartitionColumn {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "year" => Ok(PartitionColumn::Year),
+            "month" => Ok(PartitionColumn::Month),
+            "file_type" => Ok(PartitionColumn::FileType),
+            _ => Err(anyhow!("unsupported partition column: {s}")),
+        }
+    }
+}
+
+/// hive style path of the record, e.g. `year=2021/month=03/file_type=csv/`
+pub fn partition_path(columns: &[PartitionColumn], record: &FileData) -> String {
+    hive_path(columns, record.dt.as_deref(), record.file_type.as_deref())
+}
+
+/// hive style paths of all rows of an index batch
+pub fn partition_paths(columns: &[PartitionColumn], batch: &RecordBatch) -> Result<Vec<String>> {
+    let dts = string_column(batch, "dt")?;
+    let file_types = string_column(batch, "file_type")?;
+    let paths = (0..batch.num_rows())
+        .map(|i| {
+            let dt = dts.is_valid(i).then(|| dts.value(i));
+            let file_type = file_types.is_valid(i).then(|| file_types.value(i));
+            hive_path(columns, dt, file_type)
+        })
+        .collect();
+    Ok(paths)
+}
+
+fn string_column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a StringArray> {
+    batch
+        .column_by_name(name)
+        .and_then(|col| col.as_any().downcast_ref::<StringArray>())
+        .ok_or_else(|| anyhow!("batch has no {name} column"))
+}
+
+fn hive_path(columns: &[PartitionColumn], dt: Option<&str>, file_type: Option<&str>) -> String {
+    columns
+        .iter()
+        .map(|col| {
+            let value = col
+                .value(dt, file_type)
+                .filter(|x| !x.is_empty())
+                .map(|x| x.replace(['/', '='], "_"))
+                .unwrap_or_else(|| HIVE_DEFAULT_PARTITION.to_string());
+            format!("{}={}/", col.name(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::utils::aws::ObjectInfo;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(vec![], "")]
+    #[case(vec![PartitionColumn::Year], "year=2021/")]
+    #[case(vec![PartitionColumn::Year, PartitionColumn::Month, PartitionColumn::FileType], "year=2021/month=03/file_type=csv/")]
+    #[case(vec![PartitionColumn::FileType, PartitionColumn::Year], "file_type=csv/year=2021/")]
+    fn test_partition_path(#[case] columns: Vec<PartitionColumn>, #[case] expected: &str) {
+        let info = ObjectInfo {
+            last_modified: Some("2021-03-04T05:06:07Z".to_string()),
+            ..Default::default()
+        };
+        let record = FileData::new("bucket", "foo/bar.csv".to_string(), info);
+        assert_eq!(partition_path(&columns, &record), expected);
+    }
+
+    #[test]
+    fn test_partition_path_default() {
+        let record = FileData::new("bucket", "foo/bar".to_string(), ObjectInfo::default());
+        let columns = [PartitionColumn::Year, PartitionColumn::FileType];
+        assert_eq!(
+            partition_path(&columns, &record),
+            "year=__HIVE_DEFAULT_PARTITION__/file_type=__HIVE_DEFAULT_PARTITION__/"
+        );
+    }
+
+    #[test]
+    fn test_partition_paths() -> Result<()> {
+        let records = [
+            FileData::new("bucket", "a.csv".to_string(), ObjectInfo {
+                last_modified: Some("2021-03-04T05:06:07Z".to_string()),
+                ..Default::default()
+            }),
+            FileData::new("bucket", "b".to_string(), ObjectInfo::default()),
+        ];
+        let batch = FileData::to_record_batch(&records)?;
+        let columns = [PartitionColumn::Year, PartitionColumn::FileType];
+        assert_eq!(
+            partition_paths(&columns, &batch)?,
+            vec![
+                "year=2021/file_type=csv/",
+                "year=__HIVE_DEFAULT_PARTITION__/file_type=__HIVE_DEFAULT_PARTITION__/",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use crate::catalog::partition_values;
+use crate::file_data::{derive_temporal, FileData};
+use crate::storage::{read_file, Storage};
+use crate::utils::constants::{SCHEMA_VERSION, SCHEMA_VERSION_KEY};
+use crate::utils::datafusion::write_batches_with;
+use crate::writer_options::WriterOptions;
+
+use anyhow::{anyhow, Result};
+use datafusion::arrow::array::{new_null_array, RecordBatch};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::Schema;
+use parquet::arrow::ParquetRecordBatchStreamBuilder;
+use parquet::file::metadata::FileMetaData;
+use parquet::format::KeyValue;
+use tokio_stream::StreamExt;
+
+/// key-value metadata written into the footer of every file of the index
+pub fn metadata() -> Vec<KeyValue> {
+    vec![KeyValue::new(SCHEMA_VERSION_KEY.to_string(), SCHEMA_VERSION.to_string())]
+}
+
+/// files written before the version was stored are version 0
+pub fn file_version(metadata: &FileMetaData) -> Result<u32> {
+    let value = metadata
+        .key_value_metadata()
+        .and_then(|kv| kv.iter().find(|x| x.key == SCHEMA_VERSION_KEY))
+        .and_then(|x| x.value.as_deref());
+    match value {
+        Some(value) => value
+            .parse()
+            .map_err(|_| anyhow!("invalid {SCHEMA_VERSION_KEY}: {value}")),
+        None => Ok(0),
+    }
+}
+
+/// files of a newer indexer may have columns this one would drop
+pub fn check(key: &str, version: u32) -> Result<()> {
+    if version > SCHEMA_VERSION {
+        return Err(anyhow!(
+            "index file: {key} has schema version: {version}, this data-indexer writes version: {SCHEMA_VERSION}"
+        ));
+    }
+    Ok(())
+}
+
+/// schema version and batches of an index file
+pub async fn read_versioned(storage: &dyn Storage, key: &str) -> Result<(u32, Vec<RecordBatch>)> {
+    let buf = read_file(storage, key).await?;
+    let builder = ParquetRecordBatchStreamBuilder::new(Cursor::new(buf)).await?;
+    let version = file_version(builder.metadata().file_metadata())?;
+    let mut stream = builder.build()?;
+    let mut batches = vec![];
+    while let Some(batch) = stream.next().await.transpose()? {
+        batches.push(batch);
+    }
+    Ok((version, batches))
+}
+
+/// columns are only ever added to `FileData`, so a batch of an older file is upgraded
+/// by adding the missing ones, temporal columns from dt and the others as nulls,
+/// partition columns stay in the path and columns unknown to `FileData`, e.g. key fields, are kept
+pub fn upgrade(batch: &RecordBatch, partition_values: &HashMap<String, Option<String>>) -> Result<RecordBatch> {
+    let current = FileData::schema();
+    let mut fields = vec![];
+    let mut columns = vec![];
+    for field in current.fields() {
+        if partition_values.contains_key(field.name()) {
+            continue;
+        }
+        let col = match batch.column_by_name(field.name()) {
+            Some(col) => cast(col, field.data_type())?,
+            None => derive_temporal(field.name(), batch)
+                .unwrap_or_else(|| new_null_array(field.data_type(), batch.num_rows())),
+        };
+        fields.push(field.clone());
+        columns.push(col);
+    }
+    let schema = batch.schema();
+    for (field, col) in schema.fields().iter().zip(batch.columns()) {
+        if current.field_with_name(field.name()).is_err() {
+            fields.push(field.clone());
+            columns.push(col.clone());
+        }
+    }
+    Ok(RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)?)
+}
+
+/// rewrites the index file in place with the current schema,
+/// returns false if it already has the current version
+pub async fn migrate_file(storage: &dyn Storage, key: &str, options: &WriterOptions) -> Result<bool> {
+    let (version, batches) = read_versioned(storage, key).await?;
+    check(key, version)?;
+    if version == SCHEMA_VERSION {
+        return Ok(false);
+    }
+    tracing::info!("upgrading index file: {} from schema version: {} to: {}", key, version, SCHEMA_VERSION);
+    let values = partition_values(key);
+    let mut upgraded = batches
+        .iter()
+        .map(|batch| upgrade(batch, &values))
+        .collect::<Result<Vec<_>>>()?;
+    if upgraded.is_empty() {
+        let batch = RecordBatch::new_empty(Arc::new(FileData::schema()));
+        upgraded.push(upgrade(&batch, &values)?);
+    }
+    write_batches_with(storage, key, upgraded, options).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::storage::ObjectStoreStorage;
+    use crate::utils::aws::ObjectInfo;
+
+    use datafusion::arrow::array::{Array, AsArray, Int32Array, StringArray};
+    use datafusion::arrow::datatypes::{DataType, Field, Int32Type, TimestampMicrosecondType};
+    use parquet::arrow::AsyncArrowWriter;
+
+    async fn write_legacy(storage: &dyn Storage, key: &str, batch: RecordBatch) -> Result<()> {
+        let mut writer = AsyncArrowWriter::try_new(storage.writer(key, 1024), batch.schema(), None)?;
+        writer.write(&batch).await?;
+        writer.close().await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_upgrade() -> Result<()> {
+        let schema = Schema::new(vec![
+            Field::new("file_url", DataType::Utf8, true),
+            Field::new("file_type", DataType::Utf8, true),
+            Field::new("site", DataType::Utf8, true),
+        ]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(StringArray::from(vec!["s3://raw/a.csv"])),
+                Arc::new(StringArray::from(vec!["csv"])),
+                Arc::new(StringArray::from(vec!["berlin"])),
+            ],
+        )?;
+        let values = HashMap::from([("file_type".to_string(), Some("csv".to_string()))]);
+        let upgraded = upgrade(&batch, &values)?;
+
+        assert!(upgraded.column_by_name("file_type").is_none());
+        assert_eq!(upgraded.column_by_name("content_sha256").map(|x| x.null_count()), Some(1));
+        assert!(upgraded.column_by_name("site").is_some());
+        assert_eq!(upgraded.num_columns(), FileData::schema().fields().len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_upgrade_temporal() -> Result<()> {
+        let schema = Schema::new(vec![Field::new("dt", DataType::Utf8, true)]);
+        let dts = StringArray::from(vec![Some("2021-03-04T05:06:07Z"), None]);
+        let batch = RecordBatch::try_new(Arc::new(schema), vec![Arc::new(dts)])?;
+        let upgraded = upgrade(&batch, &HashMap::new())?;
+
+        let int = |name: &str| upgraded.column_by_name(name).unwrap().as_primitive::<Int32Type>().clone();
+        assert_eq!(int("year"), Int32Array::from(vec![Some(2021), None]));
+        assert_eq!(int("month").value(0), 3);
+        assert_eq!(int("day").value(0), 4);
+        let last_modified = upgraded.column_by_name("last_modified").unwrap();
+        let last_modified = last_modified.as_primitive::<TimestampMicrosecondType>();
+        assert_eq!(last_modified.value(0), 1_614_834_367_000_000);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_migrate_file() -> Result<()> {
+        let storage = ObjectStoreStorage::memory("memory://index");
+        let record = FileData::new("raw", "a.csv".to_string(), ObjectInfo::default());
+        let batch = FileData::to_record_batch(&[record])?;
+        let legacy = batch.project(&[0, 1, 2, 3, 4])?;
+        write_legacy(&storage, "index/a.parquet", legacy).await?;
+
+        assert_eq!(read_versioned(&storage, "index/a.parquet").await?.0, 0);
+        assert!(migrate_file(&storage, "index/a.parquet", &WriterOptions::default()).await?);
+        let (version, batches) = read_versioned(&storage, "index/a.parquet").await?;
+        assert_eq!(version, SCHEMA_VERSION);
+        assert_eq!(batches[0].schema().fields().len(), FileData::schema().fields().len());
+        assert!(!migrate_file(&storage, "index/a.parquet", &WriterOptions::default()).await?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(check("a.parquet", 0).is_ok());
+        assert!(check("a.parquet", SCHEMA_VERSION).is_ok());
+        assert!(check("a.parquet", SCHEMA_VERSION + 1).is_err());
+    }
+}
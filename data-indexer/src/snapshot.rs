@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::storage::{read_file, Storage};
+
+use anyhow::{anyhow, Result};
+use datafusion::arrow::array::{Array, Int64Array, RecordBatch, StringArray};
+use parquet::arrow::{ParquetRecordBatchStreamBuilder, ProjectionMask};
+use tokio_stream::StreamExt;
+
+/// columns of the index files read into the snapshot
+const SNAPSHOT_COLUMNS: [&str; 4] = ["file_url", "file_size", "dt", "deleted_at"];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotEntry {
+    pub file_size: Option<i64>,
+    pub dt: Option<String>,
+}
+
+/// state of the index built from previous runs, keyed by file_url
+/// so equal keys in different buckets are kept apart.
+/// Sharded listings and inventory reports are not in key order, so the listing is diffed
+/// by lookup and the snapshot is held in memory: the url, size and dt of every indexed
+/// object under the sources of the run, about 100 bytes plus the url per object.
+/// Listed objects are marked in place instead of being collected per source
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    entries: HashMap<String, (SnapshotEntry, AtomicBool)>,
+    deleted: HashMap<String, Option<String>>,
+}
+
+impl Snapshot {
+    /// reads the rows under the source urls, batch by batch and only the columns of the diff
+    pub async fn load(storage: &dyn Storage, keys: &[String], prefixes: &[String]) -> Result<Self> {
+        let mut snapshot = Self::default();
+        for key in keys {
+            tracing::info!("reading snapshot file: {}", key);
+            let builder = ParquetRecordBatchStreamBuilder::new(Cursor::new(read_file(storage, key).await?)).await?;
+            let columns = SNAPSHOT_COLUMNS.iter().filter_map(|x| builder.schema().index_of(x).ok());
+            let mask = ProjectionMask::roots(builder.parquet_schema(), columns);
+            let mut stream = builder.with_projection(mask).build()?;
+            while let Some(batch) = stream.next().await.transpose()? {
+                snapshot.add_batch(&batch, prefixes)?;
+            }
+        }
+        snapshot.remove_deleted();
+        tracing::info!("snapshot entries: {}", snapshot.len());
+        Ok(snapshot)
+    }
+
+    fn add_batch(&mut self, batch: &RecordBatch, prefixes: &[String]) -> Result<()> {
+        let file_urls = string_column(batch, "file_url")?;
+        let dts = string_column(batch, "dt")?;
+        let file_sizes = batch
+            .column_by_name("file_size")
+            .and_then(|col| col.as_any().downcast_ref::<Int64Array>())
+            .ok_or_else(|| anyhow!("snapshot file has no file_size column"))?;
+        // files written before deletion tracking have no tombstones
+        let deleted_ats = string_column(batch, "deleted_at").ok();
+
+        for i in 0..batch.num_rows() {
+            if file_urls.is_null(i) || !prefixes.iter().any(|x| file_urls.value(i).starts_with(x.as_str())) {
+                continue;
+            }
+            let entry = SnapshotEntry {
+                file_size: (!file_sizes.is_null(i)).then(|| file_sizes.value(i)),
+                dt: (!dts.is_null(i)).then(|| dts.value(i).to_string()),
+            };
+            if deleted_ats.is_some_and(|x| x.is_valid(i)) {
+                self.insert_deleted(file_urls.value(i).to_string(), entry.dt);
+            } else {
+                self.insert(file_urls.value(i).to_string(), entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// keeps the newest tombstone if the path was deleted more than once
+    pub fn insert_deleted(&mut self, file_url: String, dt: Option<String>) {
+        match self.deleted.get(&file_url) {
+            Some(current) if *current >= dt => (),
+            _ => {
+                self.deleted.insert(file_url, dt);
+            }
+        }
+    }
+
+    /// drops entries whose tombstone is not older than the entry,
+    /// a file created again after its deletion has a newer dt
+    pub fn remove_deleted(&mut self) {
+        let deleted = std::mem::take(&mut self.deleted);
+        self.entries
+            .retain(|path, (entry, _)| deleted.get(path).map(|dt| entry.dt > *dt).unwrap_or(true));
+    }
+
+    /// marks the object as listed in the current run
+    pub fn mark_listed(&self, file_url: &str) {
+        if let Some((_, listed)) = self.entries.get(file_url) {
+            listed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// entries under the url prefix not listed in the current run, sorted by url
+    pub fn deleted(&self, prefix: &str) -> Vec<(String, SnapshotEntry)> {
+        let mut deleted = self
+            .entries
+            .iter()
+            .filter(|(path, (_, listed))| path.starts_with(prefix) && !listed.load(Ordering::Relaxed))
+            .map(|(path, (entry, _))| (path.clone(), entry.clone()))
+            .collect::<Vec<_>>();
+        deleted.sort_by(|a, b| a.0.cmp(&b.0));
+        deleted
+    }
+
+    /// keeps the newest entry if the path was indexed more than once
+    pub fn insert(&mut self, file_url: String, entry: SnapshotEntry) {
+        match self.entries.get(&file_url) {
+            Some((current, _)) if current.dt >= entry.dt => (),
+            _ => {
+                self.entries.insert(file_url, (entry, AtomicBool::new(false)));
+            }
+        }
+    }
+
+    pub fn get(&self, file_url: &str) -> Option<&SnapshotEntry> {
+        self.entries.get(file_url).map(|(entry, _)| entry)
+    }
+
+    /// object is new or its size or last modified time differs from the snapshot
+    pub fn is_changed(&self, file_url: &str, entry: &SnapshotEntry) -> bool {
+        match self.get(file_url) {
+            Some(current) => current != entry,
+            None => true,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn string_column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a StringArray> {
+    batch
+        .column_by_name(name)
+        .and_then(|col| col.as_any().downcast_ref::<StringArray>())
+        .ok_or_else(|| anyhow!("snapshot file has no {name} column"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::file_data::FileData;
+    use crate::storage::{ObjectStoreStorage, StorageRef};
+    use crate::utils::aws::ObjectInfo;
+    use crate::utils::datafusion::write_batches;
+
+    use rstest::rstest;
+
+    fn entry(file_size: i64, dt: &str) -> SnapshotEntry {
+        SnapshotEntry {
+            file_size: Some(file_size),
+            dt: Some(dt.to_string()),
+        }
+    }
+
+    #[rstest]
+    #[case("a.txt", entry(1, "2024-01-01T00:00:00Z"), false)]
+    #[case("a.txt", entry(2, "2024-01-01T00:00:00Z"), true)]
+    #[case("a.txt", entry(1, "2024-02-01T00:00:00Z"), true)]
+    #[case("b.txt", entry(1, "2024-01-01T00:00:00Z"), true)]
+    fn test_is_changed(#[case] path: &str, #[case] input: SnapshotEntry, #[case] expected: bool) {
+        let mut snapshot = Snapshot::default();
+        snapshot.insert("a.txt".to_string(), entry(1, "2024-01-01T00:00:00Z"));
+        assert_eq!(snapshot.is_changed(path, &input), expected);
+    }
+
+    #[test]
+    fn test_insert_keeps_newest() {
+        let mut snapshot = Snapshot::default();
+        snapshot.insert("a.txt".to_string(), entry(2, "2024-02-01T00:00:00Z"));
+        snapshot.insert("a.txt".to_string(), entry(1, "2024-01-01T00:00:00Z"));
+        assert_eq!(snapshot.get("a.txt"), Some(&entry(2, "2024-02-01T00:00:00Z")));
+        assert_eq!(snapshot.len(), 1);
+    }
+
+    #[test]
+    fn test_remove_deleted() {
+        let mut snapshot = Snapshot::default();
+        snapshot.insert("a.txt".to_string(), entry(1, "2024-01-01T00:00:00Z"));
+        snapshot.insert("b.txt".to_string(), entry(1, "2024-03-01T00:00:00Z"));
+        snapshot.insert_deleted("a.txt".to_string(), Some("2024-01-01T00:00:00Z".to_string()));
+        snapshot.insert_deleted("b.txt".to_string(), Some("2024-02-01T00:00:00Z".to_string()));
+        snapshot.remove_deleted();
+        assert_eq!(snapshot.get("a.txt"), None);
+        assert_eq!(snapshot.get("b.txt"), Some(&entry(1, "2024-03-01T00:00:00Z")));
+    }
+
+    #[test]
+    fn test_deleted() {
+        let mut snapshot = Snapshot::default();
+        snapshot.insert("s3://a/b.txt".to_string(), entry(1, "2024-01-01T00:00:00Z"));
+        snapshot.insert("s3://a/a.txt".to_string(), entry(1, "2024-01-01T00:00:00Z"));
+        snapshot.insert("s3://a/c.txt".to_string(), entry(1, "2024-01-01T00:00:00Z"));
+        snapshot.insert("s3://b/d.txt".to_string(), entry(1, "2024-01-01T00:00:00Z"));
+        snapshot.mark_listed("s3://a/c.txt");
+        snapshot.mark_listed("s3://a/new.txt");
+        let deleted = snapshot.deleted("s3://a/");
+        assert_eq!(
+            deleted.iter().map(|x| x.0.as_str()).collect::<Vec<_>>(),
+            vec!["s3://a/a.txt", "s3://a/b.txt"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_load() -> Result<()> {
+        let storage: StorageRef = Arc::new(ObjectStoreStorage::memory("memory://index"));
+        let info = |dt: &str| ObjectInfo {
+            size: Some(1),
+            last_modified: Some(dt.to_string()),
+            ..Default::default()
+        };
+        let records = vec![
+            FileData::new("a", "x/a.txt".to_string(), info("2024-01-01T00:00:00Z")),
+            FileData::new("a", "x/b.txt".to_string(), info("2024-01-01T00:00:00Z")),
+            FileData::tombstone("a", "x/b.txt".to_string(), info("2024-01-01T00:00:00Z"), "2024-02-01T00:00:00Z"),
+            FileData::new("a", "y/c.txt".to_string(), info("2024-01-01T00:00:00Z")),
+            FileData::new("b", "x/d.txt".to_string(), info("2024-01-01T00:00:00Z")),
+        ];
+        write_batches(storage.as_ref(), "index/a.parquet", vec![FileData::to_record_batch(&records)?]).await?;
+
+        let keys = ["index/a.parquet".to_string()];
+        let snapshot = Snapshot::load(storage.as_ref(), &keys, &["s3://a/x/".to_string()]).await?;
+        assert_eq!(snapshot.len(), 1);
+        assert_eq!(snapshot.get("s3://a/x/a.txt"), Some(&entry(1, "2024-01-01T00:00:00Z")));
+        Ok(())
+    }
+}
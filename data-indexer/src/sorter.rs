@@ -0,0 +1,241 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::path::PathBuf;
+
+use crate::compact::string_column;
+use crate::index_writer::IndexWriter;
+use crate::utils::constants::*;
+use crate::writer_options::SORT_COLUMN;
+
+use anyhow::Result;
+use datafusion::arrow::array::{Array, RecordBatch, StringArray};
+use datafusion::arrow::compute::{concat_batches, interleave_record_batch, sort_to_indices, take_record_batch};
+use datafusion::arrow::datatypes::SchemaRef;
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use parquet::arrow::ArrowWriter;
+use uuid::Uuid;
+
+/// sorts all rows by file_path with bounded memory: up to `run_rows` rows are sorted in memory,
+/// full runs are spilled to local temp files and merged when the rows are written,
+/// so the row groups of the output hold disjoint file_path ranges
+pub struct ExternalSorter {
+    schema: SchemaRef,
+    run_rows: usize,
+    buffered: Vec<RecordBatch>,
+    buffered_rows: usize,
+    dir: PathBuf,
+    runs: Vec<PathBuf>,
+}
+
+impl ExternalSorter {
+    pub fn new(schema: SchemaRef) -> Self {
+        Self {
+            schema,
+            run_rows: SORT_RUN_ROWS,
+            buffered: vec![],
+            buffered_rows: 0,
+            dir: std::env::temp_dir().join(format!("data-indexer-sort-{}", Uuid::new_v4())),
+            runs: vec![],
+        }
+    }
+
+    pub fn with_run_rows(mut self, run_rows: usize) -> Self {
+        self.run_rows = run_rows.max(1);
+        self
+    }
+
+    pub fn push(&mut self, batch: RecordBatch) -> Result<()> {
+        if batch.num_rows() == 0 {
+            return Ok(());
+        }
+        self.buffered_rows += batch.num_rows();
+        self.buffered.push(batch);
+        if self.buffered_rows >= self.run_rows {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn sorted_buffer(&mut self) -> Result<RecordBatch> {
+        let batch = concat_batches(&self.schema, &std::mem::take(&mut self.buffered))?;
+        self.buffered_rows = 0;
+        let col = batch.column_by_name(SORT_COLUMN).expect("sort column in the index schema");
+        let indices = sort_to_indices(col, None, None)?;
+        Ok(take_record_batch(&batch, &indices)?)
+    }
+
+    fn spill(&mut self) -> Result<()> {
+        let batch = self.sorted_buffer()?;
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(format!("run-{:05}.parquet", self.runs.len()));
+        let mut writer = ArrowWriter::try_new(File::create(&path)?, self.schema.clone(), None)?;
+        writer.write(&batch)?;
+        writer.close()?;
+        tracing::debug!("spilled sorted run of {} rows to: {}", batch.num_rows(), path.display());
+        self.runs.push(path);
+        Ok(())
+    }
+
+    /// writes all rows in order, the runs are merged if any was spilled
+    pub async fn write_to(mut self, writer: &mut IndexWriter) -> Result<()> {
+        if self.runs.is_empty() {
+            let batch = self.sorted_buffer()?;
+            for offset in (0..batch.num_rows()).step_by(SORT_BATCH_ROWS) {
+                let len = SORT_BATCH_ROWS.min(batch.num_rows() - offset);
+                writer.write_batch(&batch.slice(offset, len)).await?;
+            }
+            return Ok(());
+        }
+        if !self.buffered.is_empty() {
+            self.spill()?;
+        }
+        tracing::info!("merging {} sorted runs", self.runs.len());
+
+        let mut cursors = vec![];
+        let mut heap = BinaryHeap::new();
+        for path in &self.runs {
+            let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)?
+                .with_batch_size(SORT_BATCH_ROWS)
+                .build()?;
+            let run = cursors.len();
+            let Some(cursor) = RunCursor::try_new(reader)? else {
+                continue;
+            };
+            heap.push(Reverse((cursor.path(), run)));
+            cursors.push(cursor);
+        }
+
+        // rows are taken by (run, row) from the current batch of each run, the output is
+        // written before a run moves on to its next batch
+        let mut indices = vec![];
+        while let Some(Reverse((_, run))) = heap.pop() {
+            let cursor = &mut cursors[run];
+            indices.push((run, cursor.row));
+            cursor.row += 1;
+            let exhausted = cursor.row == cursor.batch.num_rows();
+            if exhausted || indices.len() == SORT_BATCH_ROWS {
+                let batches = cursors.iter().map(|x| &x.batch).collect::<Vec<_>>();
+                writer.write_batch(&interleave_record_batch(&batches, &indices)?).await?;
+                indices.clear();
+            }
+            let cursor = &mut cursors[run];
+            if exhausted && !cursor.next()? {
+                continue;
+            }
+            heap.push(Reverse((cursor.path(), run)));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ExternalSorter {
+    fn drop(&mut self) {
+        if !self.runs.is_empty() {
+            if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+                tracing::warn!("failed to remove sorted runs in: {}: {e:?}", self.dir.display());
+            }
+        }
+    }
+}
+
+/// current batch and row of a spilled run
+struct RunCursor {
+    reader: ParquetRecordBatchReader,
+    batch: RecordBatch,
+    paths: StringArray,
+    row: usize,
+}
+
+impl RunCursor {
+    fn try_new(mut reader: ParquetRecordBatchReader) -> Result<Option<Self>> {
+        let Some(batch) = reader.next().transpose()? else {
+            return Ok(None);
+        };
+        let paths = string_column(&batch, SORT_COLUMN)?;
+        Ok(Some(Self {
+            reader,
+            batch,
+            paths,
+            row: 0,
+        }))
+    }
+
+    /// file_path of the current row, nulls sort first as in the runs
+    fn path(&self) -> Option<String> {
+        self.paths.is_valid(self.row).then(|| self.paths.value(self.row).to_string())
+    }
+
+    /// moves to the next batch, false at the end of the run
+    fn next(&mut self) -> Result<bool> {
+        let Some(batch) = self.reader.next().transpose()? else {
+            return Ok(false);
+        };
+        self.paths = string_column(&batch, SORT_COLUMN)?;
+        self.batch = batch;
+        self.row = 0;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::file_data::FileData;
+    use crate::key_fields::KeyFields;
+    use crate::storage::{read_file, ObjectStoreStorage, StorageRef};
+    use crate::utils::aws::ObjectInfo;
+    use crate::writer_options::WriterOptions;
+
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(100)]
+    #[case(4)]
+    #[case(1)]
+    #[tokio::test]
+    async fn test_disjoint_row_groups(#[case] run_rows: usize) -> Result<()> {
+        let storage: StorageRef = Arc::new(ObjectStoreStorage::memory("memory://index"));
+        let options = WriterOptions {
+            row_group_size: 3,
+            sort_by_file_path: true,
+            ..Default::default()
+        };
+        let mut writer = IndexWriter::new(storage.clone(), "index/", "a.parquet", 1024, vec![], KeyFields::default())?
+            .with_options(options);
+        let mut sorter = ExternalSorter::new(Arc::new(FileData::schema())).with_run_rows(run_rows);
+        for keys in [["h.csv", "b.csv", "e.csv"], ["d.csv", "a.csv", "i.csv"], ["c.csv", "g.csv", "f.csv"]] {
+            let records = keys
+                .iter()
+                .map(|x| FileData::new("raw", x.to_string(), ObjectInfo::default()))
+                .collect::<Vec<_>>();
+            sorter.push(FileData::to_record_batch(&records)?)?;
+        }
+        sorter.write_to(&mut writer).await?;
+        writer.finish().await?;
+
+        let reader = SerializedFileReader::new(read_file(storage.as_ref(), "index/a.parquet").await?)?;
+        let metadata = reader.metadata();
+        let col = metadata.file_metadata().schema_descr().columns().iter().position(|x| x.name() == SORT_COLUMN);
+        let col = col.expect("file_path column");
+        let ranges = metadata
+            .row_groups()
+            .iter()
+            .map(|group| {
+                let stats = group.column(col).statistics().expect("file_path statistics");
+                let value = |x: Option<&[u8]>| String::from_utf8_lossy(x.expect("min and max")).to_string();
+                (value(stats.min_bytes_opt()), value(stats.max_bytes_opt()))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ranges,
+            vec![("a.csv".into(), "c.csv".into()), ("d.csv".into(), "f.csv".into()), ("g.csv".into(), "i.csv".into())]
+        );
+        assert!(ranges.windows(2).all(|x| x[0].1 < x[1].0));
+        Ok(())
+    }
+}
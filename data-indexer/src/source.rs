@@ -0,0 +1,224 @@
+use std::sync::Arc;
+
+use crate::archives::add_members;
+use crate::config::Config;
+use crate::enrichment::enrich_records;
+use crate::extractors::{extract_metadata, Extractors};
+use crate::file_data::FileData;
+use crate::hashing::hash_records;
+use crate::inventory::Inventory;
+use crate::lister::{ShardedLister, SourcePages};
+use crate::snapshot::{Snapshot, SnapshotEntry};
+use crate::storage::{head_objects, object_url, ObjectPages, StorageRef, Stores};
+use crate::utils::aws::get_aws_client_with_profile;
+use crate::utils::constants::REGION;
+use crate::watermark::SourceWatermark;
+
+use anyhow::{anyhow, Result};
+use aws_sdk_s3::Client;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+/// bucket and prefix indexed in a run, e.g.
+/// `{"bucket": "raw-data", "prefix": "mri/", "dataset": "mri", "profile": "research"}`,
+/// with `inventory` the objects are read from the s3 inventory manifest instead of listed,
+/// `bucket` may also be a `file://` directory
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Source {
+    pub bucket: String,
+    pub prefix: String,
+    pub dataset: Option<String>,
+    pub region: Option<String>,
+    pub profile: Option<String>,
+    /// `s3://bucket/.../manifest.json` or a local path
+    pub inventory: Option<String>,
+}
+
+impl Source {
+    /// `s3://bucket/prefix`, identifies the source in the watermark
+    pub fn url(&self) -> String {
+        object_url(&self.bucket, &self.prefix)
+    }
+
+    /// key of the object from its url
+    pub fn key<'a>(&self, file_url: &'a str) -> Option<&'a str> {
+        file_url.strip_prefix(&object_url(&self.bucket, ""))
+    }
+
+    /// the target client is reused unless the source needs its own region or credentials
+    pub async fn client(&self, client: &Client) -> Client {
+        if self.region.is_none() && self.profile.is_none() {
+            return client.clone();
+        }
+        let region = self.region.as_deref().unwrap_or(REGION);
+        get_aws_client_with_profile(region, self.profile.as_deref()).await
+    }
+}
+
+/// settings shared by the sources of a run
+pub struct SourceOptions {
+    pub snapshot: Snapshot,
+    pub incremental: bool,
+    pub track_deletions: bool,
+    pub head_object: bool,
+    pub extract_metadata: bool,
+    pub sharded_listing: bool,
+    pub content_hash: bool,
+    pub enrich: bool,
+    pub versions: bool,
+    pub archives: bool,
+    pub extractors: Extractors,
+}
+
+impl SourceOptions {
+    /// stages of the config, a dry run only lists
+    pub fn from_config(config: &Config) -> Self {
+        let dry_run = config.args.is_dry_run();
+        Self {
+            snapshot: Snapshot::default(),
+            incremental: false,
+            track_deletions: false,
+            head_object: config.args.with_head_object() && !dry_run,
+            extract_metadata: config.args.with_metadata() && !dry_run,
+            sharded_listing: config.args.with_sharded_listing(),
+            content_hash: config.args.with_content_hash() && !dry_run,
+            enrich: config.args.with_enrichment() && !dry_run,
+            versions: config.args.with_versions(),
+            archives: config.args.with_archives() && !dry_run,
+            extractors: Extractors::default(),
+        }
+    }
+}
+
+/// result of listing one source
+#[derive(Debug, Default)]
+pub struct SourceListing {
+    pub listed: usize,
+    pub watermark: SourceWatermark,
+    /// files whose head, metadata or hash could not be read
+    pub errors: usize,
+}
+
+/// lists the source page by page and sends new or changed files to the writer
+pub async fn index_source(
+    stores: Stores,
+    source: Source,
+    start_after: Option<String>,
+    options: Arc<SourceOptions>,
+    tx: mpsc::Sender<Vec<FileData>>,
+) -> Result<SourceListing> {
+    tracing::info!("reading data from: {}", source.url());
+    let storage = stores.get(&source.bucket)?;
+    let mut pages = if let Some(location) = &source.inventory {
+        // the snapshot decides what changed, inventory reports have no start key
+        let inventory = Inventory::load(&stores, location).await?;
+        if inventory.manifest.source_bucket != source.bucket {
+            return Err(anyhow!(
+                "inventory of bucket: {} configured for source: {}",
+                inventory.manifest.source_bucket,
+                source.url()
+            ));
+        }
+        SourcePages::Inventory(inventory.pages(&source.prefix, options.versions).await?)
+    } else if options.sharded_listing {
+        let lister = ShardedLister::new(storage.clone(), &source.prefix, start_after.as_deref());
+        SourcePages::sharded(lister).await?
+    } else {
+        let pages = ObjectPages::new(storage.clone(), &source.prefix, start_after.as_deref());
+        SourcePages::Sequential(pages.with_versions(options.versions))
+    };
+    let mut listing = SourceListing {
+        watermark: SourceWatermark {
+            last_modified: None,
+            last_key: start_after,
+        },
+        ..Default::default()
+    };
+    while let Some(objects) = pages.next_page().await? {
+        listing.listed += objects.len();
+        let mut file_data_page = vec![];
+        for (file, info) in objects {
+            let entry = SnapshotEntry {
+                file_size: info.size,
+                dt: info.last_modified.clone(),
+            };
+            let record = FileData::new(&source.bucket, file, info).with_dataset(source.dataset.as_deref());
+            let url = record.file_url.clone().unwrap_or_default();
+            listing.watermark.last_modified = listing.watermark.last_modified.max(entry.dt.clone());
+            listing.watermark.last_key = listing.watermark.last_key.max(record.file_path.clone());
+            if options.track_deletions {
+                options.snapshot.mark_listed(&url);
+            }
+            if options.incremental && !options.snapshot.is_changed(&url, &entry) {
+                continue;
+            }
+            file_data_page.push(record);
+        }
+
+        listing.errors += process_page(storage.clone(), &mut file_data_page, &options).await?;
+        if !file_data_page.is_empty() && tx.send(file_data_page).await.is_err() {
+            // the writer stopped, its error is returned by the run
+            break;
+        }
+    }
+    tracing::info!("listed files: {} in: {}", listing.listed, source.url());
+    Ok(listing)
+}
+
+/// runs the enabled stages on the records of a page, returns the number of files that failed,
+/// rows of archive members are added last so the other stages only read the archives
+pub async fn process_page(storage: StorageRef, records: &mut Vec<FileData>, options: &SourceOptions) -> Result<usize> {
+    let mut errors = 0;
+    // the enrichment reads the head as well
+    if options.head_object && !options.enrich {
+        errors += add_head_info(storage.clone(), records).await?;
+    }
+    if options.enrich {
+        errors += enrich_records(storage.clone(), records).await?;
+    }
+    if options.extract_metadata {
+        errors += extract_metadata(storage.clone(), records, &options.extractors).await?;
+    }
+    if options.content_hash {
+        errors += hash_records(storage.clone(), records).await?;
+    }
+    if options.archives {
+        errors += add_members(storage, records).await?;
+    }
+    Ok(errors)
+}
+
+/// returns the number of files without a head
+async fn add_head_info(storage: StorageRef, records: &mut [FileData]) -> Result<usize> {
+    let keys = records
+        .iter()
+        .filter(|x| x.is_current())
+        .filter_map(|x| x.file_path.clone())
+        .collect::<Vec<_>>();
+    let requested = keys.len();
+    let mut heads = head_objects(storage, keys).await?;
+    let errors = requested - heads.len();
+    for file_data in records.iter_mut() {
+        if let Some(head) = file_data.file_path.as_ref().and_then(|x| heads.remove(x)) {
+            file_data.with_head(head);
+        }
+    }
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() -> Result<()> {
+        let source: Source = serde_json::from_str(
+            r#"{"bucket": "raw-data", "prefix": "mri/", "dataset": "mri", "profile": "research"}"#,
+        )?;
+        assert_eq!(source.url(), "s3://raw-data/mri/");
+        assert_eq!(source.region, None);
+        assert_eq!(source.key("s3://raw-data/mri/a.dcm"), Some("mri/a.dcm"));
+        assert_eq!(source.key("s3://other/mri/a.dcm"), None);
+        Ok(())
+    }
+}
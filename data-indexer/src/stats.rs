@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::extractors::{extract_metadata, Extractors, ParquetFooterExtractor};
+use crate::file_data::FileData;
+use crate::storage::StorageRef;
+use crate::utils::aws::ObjectInfo;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct FileTypeSummary {
+    pub files: usize,
+    pub bytes: i64,
+}
+
+/// counts of an index run, printed by `--dry-run` and stored in the run manifest
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RunSummary {
+    pub sources: usize,
+    pub listed: usize,
+    pub new_or_changed: usize,
+    pub bytes: i64,
+    pub deleted: usize,
+    /// files whose head, metadata or hash could not be read
+    pub errors: usize,
+    pub file_types: BTreeMap<String, FileTypeSummary>,
+    /// rows of files inside archives, not counted as files
+    #[serde(default)]
+    pub archive_members: usize,
+}
+
+impl RunSummary {
+    pub fn add(&mut self, records: &[FileData]) {
+        for record in records {
+            if record.member_path.is_some() {
+                self.archive_members += 1;
+                continue;
+            }
+            let size = record.file_size.unwrap_or_default();
+            self.new_or_changed += 1;
+            self.bytes += size;
+            let file_type = record.file_type.clone().unwrap_or_default();
+            let summary = self.file_types.entry(file_type).or_default();
+            summary.files += 1;
+            summary.bytes += size;
+        }
+    }
+}
+
+/// printed by the `stats` command
+#[derive(Serialize, Debug, Default)]
+pub struct IndexStats {
+    pub generation: Option<String>,
+    pub last_run: Option<String>,
+    pub last_modified: Option<String>,
+    pub index_files: usize,
+    pub uncompacted_files: usize,
+    pub index_bytes: i64,
+    pub rows: i64,
+}
+
+/// printed by the `verify` command
+#[derive(Serialize, Debug, Default)]
+pub struct Verification {
+    pub checked: usize,
+    /// referenced by the generation or the watermark but not in the bucket
+    pub missing: Vec<String>,
+    /// footer can't be read or has no file_url column
+    pub unreadable: Vec<String>,
+}
+
+impl Verification {
+    pub fn is_ok(&self) -> bool {
+        self.missing.is_empty() && self.unreadable.is_empty()
+    }
+}
+
+/// index files with the size from the listing and row count and columns from the footer
+pub async fn read_footers(
+    storage: StorageRef,
+    keys: &[String],
+    listed: &HashMap<String, ObjectInfo>,
+) -> Result<Vec<FileData>> {
+    let mut records = keys
+        .iter()
+        .filter_map(|key| listed.get(key).map(|info| FileData::new(storage.url(), key.clone(), info.clone())))
+        .collect::<Vec<_>>();
+    let mut extractors = Extractors::empty();
+    extractors.register(&["parquet"], Arc::new(ParquetFooterExtractor));
+    extract_metadata(storage, &mut records, &extractors).await?;
+    Ok(records)
+}
+
+/// footers without the columns every index file has
+pub fn unreadable(records: &[FileData]) -> Vec<String> {
+    records
+        .iter()
+        .filter(|record| {
+            !record
+                .metadata
+                .as_ref()
+                .and_then(|x| x.columns.as_ref())
+                .is_some_and(|columns| columns.iter().any(|x| x == "file_url"))
+        })
+        .filter_map(|x| x.file_path.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::extractors::FileMetadata;
+
+    fn record(key: &str, size: i64, columns: Option<Vec<&str>>) -> FileData {
+        let info = ObjectInfo {
+            size: Some(size),
+            ..Default::default()
+        };
+        let mut record = FileData::new("bucket", key.to_string(), info);
+        record.metadata = columns.map(|columns| FileMetadata {
+            columns: Some(columns.into_iter().map(|x| x.to_string()).collect()),
+            ..Default::default()
+        });
+        record
+    }
+
+    #[test]
+    fn test_run_summary() {
+        let mut summary = RunSummary::default();
+        summary.add(&[record("a.csv", 1, None), record("b.csv", 2, None), record("c", 4, None)]);
+        assert_eq!(summary.new_or_changed, 3);
+        assert_eq!(summary.bytes, 7);
+        assert_eq!(summary.file_types.get("csv"), Some(&FileTypeSummary { files: 2, bytes: 3 }));
+        assert_eq!(summary.file_types.get(""), Some(&FileTypeSummary { files: 1, bytes: 4 }));
+    }
+
+    #[test]
+    fn test_unreadable() {
+        let records = [
+            record("a.parquet", 1, Some(vec!["file_name", "file_url"])),
+            record("b.parquet", 1, Some(vec!["id"])),
+            record("c.parquet", 1, None),
+        ];
+        assert_eq!(unreadable(&records), vec!["b.parquet", "c.parquet"]);
+    }
+}
@@ -0,0 +1,306 @@
+mod s3;
+mod store;
+
+pub use s3::S3Storage;
+pub use store::ObjectStoreStorage;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::extractors::ByteRange;
+use crate::utils::aws::{HeadInfo, ObjectInfo};
+use crate::utils::constants::*;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use aws_sdk_s3::Client;
+use bytes::Bytes;
+use parquet::arrow::async_writer::AsyncFileWriter;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// one page of a listing, `next` continues it
+#[derive(Debug, Default)]
+pub struct ListPage {
+    pub objects: Vec<(String, ObjectInfo)>,
+    /// common prefixes of the next level, only listed with the delimiter
+    pub prefixes: Vec<String>,
+    pub next: Option<String>,
+}
+
+/// bucket or directory the indexer lists, reads and writes
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// prefix of the file_url of its objects, e.g. `s3://bucket`
+    fn url(&self) -> &str;
+
+    /// objects under the prefix in key order after `start_after`,
+    /// with `delimiter` only the next level and its common prefixes
+    async fn list_page(
+        &self,
+        prefix: &str,
+        delimiter: bool,
+        start_after: Option<&str>,
+        next: Option<String>,
+    ) -> Result<ListPage>;
+
+    /// whole object, `None` if it does not exist
+    /// every version and delete marker under the prefix in key order, newest version first
+    async fn list_versions_page(&self, prefix: &str, next: Option<String>) -> Result<ListPage>;
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>>;
+
+    async fn get_range(&self, key: &str, range: ByteRange) -> Result<Bytes>;
+
+    async fn head(&self, key: &str) -> Result<HeadInfo>;
+
+    /// object tags, empty if the storage has none
+    async fn tags(&self, key: &str) -> Result<BTreeMap<String, String>>;
+
+    async fn put(&self, key: &str, data: Bytes) -> Result<()>;
+
+    /// writes the object only if the key does not exist yet, false if it does
+    async fn put_if_absent(&self, key: &str, data: Bytes) -> Result<bool>;
+
+    async fn delete(&self, keys: Vec<String>) -> Result<()>;
+
+    /// streaming upload, the object is written when the writer completes
+    fn writer(&self, key: &str, part_size: usize) -> Box<dyn AsyncFileWriter>;
+}
+
+pub type StorageRef = Arc<dyn Storage>;
+
+/// `s3://bucket/key`, `file:///dir/key`, plain bucket names are s3 buckets
+pub fn object_url(location: &str, key: &str) -> String {
+    if location.contains("://") {
+        format!("{}/{key}", location.trim_end_matches('/'))
+    } else {
+        format!("s3://{location}/{key}")
+    }
+}
+
+/// opens the bucket or `file://` directory, s3 requests use the client
+pub async fn open(client: Option<&Client>, location: &str) -> Result<StorageRef> {
+    if let Some(path) = location.strip_prefix("file://") {
+        return Ok(Arc::new(ObjectStoreStorage::local(path)?));
+    }
+    if location.starts_with("memory://") {
+        return Err(anyhow!("in-memory storage: {location} can't be opened from the config"));
+    }
+    let bucket = location.strip_prefix("s3://").unwrap_or(location);
+    let client = client.ok_or_else(|| anyhow!("no aws client to open: {location}"))?;
+    Ok(Arc::new(S3Storage::new(client.clone(), bucket)))
+}
+
+/// storages of a run keyed by the bucket or location in the config
+#[derive(Clone, Default)]
+pub struct Stores {
+    client: Option<Client>,
+    stores: HashMap<String, StorageRef>,
+}
+
+impl Stores {
+    pub fn new(client: Option<Client>) -> Self {
+        Self {
+            client,
+            stores: HashMap::new(),
+        }
+    }
+
+    /// target and sources of the config, sources get their own region or profile,
+    /// without a client only `file://` locations can be opened
+    pub async fn open(client: Option<Client>, config: &Config) -> Result<Self> {
+        let mut stores = Self::new(client.clone());
+        let target = open(client.as_ref(), &config.bucket_target).await?;
+        stores.insert(&config.bucket_target, target);
+        for source in config.sources().unwrap_or_default() {
+            if stores.stores.contains_key(&source.bucket) {
+                continue;
+            }
+            let source_client = match &client {
+                Some(client) => Some(source.client(client).await),
+                None => None,
+            };
+            let storage = open(source_client.as_ref(), &source.bucket).await?;
+            stores.insert(&source.bucket, storage);
+        }
+        Ok(stores)
+    }
+
+    pub fn insert(&mut self, location: &str, storage: StorageRef) {
+        self.stores.insert(location.to_string(), storage);
+    }
+
+    pub fn get(&self, location: &str) -> Result<StorageRef> {
+        self.stores
+            .get(location)
+            .cloned()
+            .ok_or_else(|| anyhow!("no storage opened for: {location}"))
+    }
+
+    /// storage of a location outside the config, e.g. an inventory bucket
+    pub async fn open_location(&self, location: &str) -> Result<StorageRef> {
+        match self.stores.get(location) {
+            Some(storage) => Ok(storage.clone()),
+            None => open(self.client.as_ref(), location).await,
+        }
+    }
+
+    pub fn client(&self) -> Option<&Client> {
+        self.client.as_ref()
+    }
+}
+
+pub async fn read_file(storage: &dyn Storage, key: &str) -> Result<Bytes> {
+    storage
+        .get(key)
+        .await?
+        .ok_or_else(|| anyhow!("file not found: {}", object_url(storage.url(), key)))
+}
+
+pub async fn list_keys(storage: StorageRef, prefix: &str) -> Result<Vec<String>> {
+    let mut pages = ObjectPages::new(storage, prefix, None);
+    let mut keys = vec![];
+    while let Some(page) = pages.next_page().await? {
+        keys.extend(page.into_iter().map(|(key, _)| key));
+    }
+    Ok(keys)
+}
+
+pub async fn head_objects(storage: StorageRef, keys: Vec<String>) -> Result<HashMap<String, HeadInfo>> {
+    let sem = Arc::new(Semaphore::new(HEAD_OBJECT_WORKERS));
+    let mut tasks = JoinSet::new();
+    for key in keys {
+        let permit = sem.clone().acquire_owned().await?;
+        let storage = storage.clone();
+        tasks.spawn(async move {
+            let _permit = permit;
+            let res = storage.head(&key).await;
+            (key, res)
+        });
+    }
+
+    let mut res = HashMap::new();
+    while let Some(task) = tasks.join_next().await {
+        match task? {
+            (key, Ok(head)) => {
+                res.insert(key, head);
+            }
+            (key, Err(e)) => tracing::error!("failed to read head of file: {key}: {e:?}"),
+        }
+    }
+    Ok(res)
+}
+
+/// pages of a listing, fetched one at a time
+pub struct ObjectPages {
+    storage: StorageRef,
+    prefix: String,
+    start_after: Option<String>,
+    versions: bool,
+    next: Option<String>,
+    done: bool,
+}
+
+impl ObjectPages {
+    pub fn new(storage: StorageRef, prefix: &str, start_after: Option<&str>) -> Self {
+        Self {
+            storage,
+            prefix: prefix.to_string(),
+            start_after: start_after.map(|x| x.to_string()),
+            versions: false,
+            next: None,
+            done: false,
+        }
+    }
+
+    /// lists every version of the objects instead of the current ones
+    pub fn with_versions(mut self, versions: bool) -> Self {
+        self.versions = versions;
+        self
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    pub async fn next_page(&mut self) -> Result<Option<Vec<(String, ObjectInfo)>>> {
+        if self.done {
+            return Ok(None);
+        }
+        let page = if self.versions {
+            self.storage.list_versions_page(&self.prefix, self.next.take()).await?
+        } else {
+            self.storage
+                .list_page(&self.prefix, false, self.start_after.as_deref(), self.next.take())
+                .await?
+        };
+        self.next = page.next;
+        self.done = self.next.is_none();
+        Ok(Some(page.objects))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use dataplatform_multipart::constants::PART_SIZE;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("raw", "a/b.csv", "s3://raw/a/b.csv")]
+    #[case("s3://raw", "a/b.csv", "s3://raw/a/b.csv")]
+    #[case("file:///data/raw/", "a/b.csv", "file:///data/raw/a/b.csv")]
+    fn test_object_url(#[case] location: &str, #[case] key: &str, #[case] expected: &str) {
+        assert_eq!(object_url(location, key), expected);
+    }
+
+    #[tokio::test]
+    async fn test_memory_storage() -> Result<()> {
+        let storage = ObjectStoreStorage::memory("memory://raw");
+        for key in ["a/1.csv", "a/b/2.csv", "a/b/3.csv", "ab/4.csv", "c.csv"] {
+            storage.put(key, Bytes::from_static(b"0123456789")).await?;
+        }
+
+        let page = storage.list_page("a", false, None, None).await?;
+        let keys = page.objects.iter().map(|x| x.0.as_str()).collect::<Vec<_>>();
+        assert_eq!(keys, vec!["a/1.csv", "a/b/2.csv", "a/b/3.csv", "ab/4.csv"]);
+        assert_eq!(page.objects[0].1.size, Some(10));
+
+        let page = storage.list_page("a/", true, None, None).await?;
+        assert_eq!(page.objects.iter().map(|x| x.0.as_str()).collect::<Vec<_>>(), vec!["a/1.csv"]);
+        assert_eq!(page.prefixes, vec!["a/b/"]);
+
+        let page = storage.list_page("a/", false, Some("a/b/2.csv"), None).await?;
+        assert_eq!(page.objects.iter().map(|x| x.0.as_str()).collect::<Vec<_>>(), vec!["a/b/3.csv"]);
+
+        let page = storage.list_versions_page("a/b/", None).await?;
+        assert_eq!(page.objects.len(), 2);
+        assert!(page.objects.iter().all(|x| x.1.is_latest == Some(true) && x.1.version_id.is_none()));
+
+        assert_eq!(storage.get_range("c.csv", ByteRange::Head(3)).await?, Bytes::from_static(b"012"));
+        assert_eq!(storage.get_range("c.csv", ByteRange::Tail(2)).await?, Bytes::from_static(b"89"));
+        assert_eq!(storage.get_range("c.csv", ByteRange::Span(2, 4)).await?, Bytes::from_static(b"234"));
+
+        assert!(storage.put_if_absent("d.csv", Bytes::from_static(b"1")).await?);
+        assert!(!storage.put_if_absent("d.csv", Bytes::from_static(b"2")).await?);
+        assert_eq!(storage.get("d.csv").await?, Some(Bytes::from_static(b"1")));
+
+        storage.delete(vec!["c.csv".to_string(), "missing.csv".to_string()]).await?;
+        assert_eq!(storage.get("c.csv").await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_writer() -> Result<()> {
+        let storage = ObjectStoreStorage::memory("memory://index");
+        let mut writer = storage.writer("a.bin", PART_SIZE);
+        writer.write(Bytes::from_static(b"abc")).await?;
+        writer.write(Bytes::from_static(b"def")).await?;
+        writer.complete().await?;
+        assert_eq!(read_file(&storage, "a.bin").await?, Bytes::from_static(b"abcdef"));
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use super::{ListPage, Storage};
+use crate::extractors::ByteRange;
+use crate::utils::aws::{HeadInfo, ObjectInfo};
+use dataplatform_multipart::MultipartWriter;
+
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use aws_sdk_s3::{
+    operation::get_object::GetObjectError,
+    primitives::ByteStream,
+    types::{ChecksumMode, Delete, ObjectIdentifier},
+    Client,
+};
+use bytes::Bytes;
+use parquet::arrow::async_writer::AsyncFileWriter;
+
+/// s3 bucket, objects keep the storage class, owner and checksums of the listing
+pub struct S3Storage {
+    client: Client,
+    bucket: String,
+    url: String,
+}
+
+impl S3Storage {
+    pub fn new(client: Client, bucket: &str) -> Self {
+        Self {
+            client,
+            bucket: bucket.to_string(),
+            url: format!("s3://{bucket}"),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    async fn list_page(
+        &self,
+        prefix: &str,
+        delimiter: bool,
+        start_after: Option<&str>,
+        next: Option<String>,
+    ) -> Result<ListPage> {
+        let resp = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .fetch_owner(true)
+            .set_delimiter(delimiter.then(|| "/".to_string()))
+            .set_start_after(start_after.map(|x| x.to_string()))
+            .set_continuation_token(next)
+            .send()
+            .await?;
+
+        let objects = resp
+            .contents()
+            .iter()
+            .filter_map(|obj| {
+                obj.key()
+                    .filter(|key| !key.ends_with('/'))
+                    .map(|key| (key.to_string(), ObjectInfo::from(obj)))
+            })
+            .collect();
+        let prefixes = resp
+            .common_prefixes()
+            .iter()
+            .filter_map(|x| x.prefix().map(|x| x.to_string()))
+            .collect();
+        Ok(ListPage {
+            objects,
+            prefixes,
+            next: resp.next_continuation_token().map(|x| x.to_string()),
+        })
+    }
+
+    async fn list_versions_page(&self, prefix: &str, next: Option<String>) -> Result<ListPage> {
+        // the key and version id markers of the next page
+        let (key_marker, version_id_marker) = match next {
+            Some(next) => serde_json::from_str::<(Option<String>, Option<String>)>(&next)?,
+            None => (None, None),
+        };
+        let resp = self
+            .client
+            .list_object_versions()
+            .bucket(&self.bucket)
+            .prefix(prefix)
+            .set_key_marker(key_marker)
+            .set_version_id_marker(version_id_marker)
+            .send()
+            .await?;
+
+        let versions = resp
+            .versions()
+            .iter()
+            .filter_map(|x| x.key().map(|key| (key.to_string(), ObjectInfo::from(x))));
+        let markers = resp
+            .delete_markers()
+            .iter()
+            .filter_map(|x| x.key().map(|key| (key.to_string(), ObjectInfo::from(x))));
+        let mut objects = versions
+            .chain(markers)
+            .filter(|(key, _)| !key.ends_with('/'))
+            .collect::<Vec<_>>();
+        // versions and delete markers come in separate lists
+        objects.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| b.1.last_modified.cmp(&a.1.last_modified)));
+
+        let next = match resp.is_truncated() {
+            Some(true) => Some(serde_json::to_string(&(resp.next_key_marker(), resp.next_version_id_marker()))?),
+            _ => None,
+        };
+        Ok(ListPage {
+            objects,
+            prefixes: vec![],
+            next,
+        })
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>> {
+        let res = self.client.get_object().bucket(&self.bucket).key(key).send().await;
+        match res {
+            Ok(object) => Ok(Some(object.body.collect().await?.into_bytes())),
+            Err(sdk_err) => match sdk_err.into_service_error() {
+                GetObjectError::NoSuchKey(_) => Ok(None),
+                err => Err(err.into()),
+            },
+        }
+    }
+
+    async fn get_range(&self, key: &str, range: ByteRange) -> Result<Bytes> {
+        let resp = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .range(range.header())
+            .send()
+            .await?;
+        Ok(resp.body.collect().await?.into_bytes())
+    }
+
+    async fn head(&self, key: &str) -> Result<HeadInfo> {
+        let resp = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .checksum_mode(ChecksumMode::Enabled)
+            .send()
+            .await?;
+        Ok(HeadInfo::from(&resp))
+    }
+
+    async fn tags(&self, key: &str) -> Result<BTreeMap<String, String>> {
+        let resp = self
+            .client
+            .get_object_tagging()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await?;
+        Ok(resp
+            .tag_set()
+            .iter()
+            .map(|x| (x.key().to_string(), x.value().to_string()))
+            .collect())
+    }
+
+    async fn put(&self, key: &str, data: Bytes) -> Result<()> {
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .body(ByteStream::from(data))
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// conditional write with `If-None-Match: *`, s3 answers 412 if the key exists
+    async fn put_if_absent(&self, key: &str, data: Bytes) -> Result<bool> {
+        let res = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .if_none_match("*")
+            .body(ByteStream::from(data))
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) if e.raw_response().is_some_and(|x| x.status().as_u16() == 412) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// deletes the keys in batches of 1000, the DeleteObjects limit
+    async fn delete(&self, keys: Vec<String>) -> Result<()> {
+        for chunk in keys.chunks(1000) {
+            let objects = chunk
+                .iter()
+                .map(|key| ObjectIdentifier::builder().key(key).build())
+                .collect::<Result<Vec<_>, _>>()?;
+            let delete = Delete::builder().set_objects(Some(objects)).quiet(true).build()?;
+            let resp = self
+                .client
+                .delete_objects()
+                .bucket(&self.bucket)
+                .delete(delete)
+                .send()
+                .await?;
+            for err in resp.errors() {
+                tracing::error!("failed to delete file: {:?}: {:?}", err.key(), err.message());
+            }
+        }
+        Ok(())
+    }
+
+    fn writer(&self, key: &str, part_size: usize) -> Box<dyn AsyncFileWriter> {
+        Box::new(MultipartWriter::new(self.client.clone(), &self.bucket, key, part_size))
+    }
+}
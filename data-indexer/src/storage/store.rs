@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use super::{ListPage, Storage};
+use crate::extractors::ByteRange;
+use crate::utils::aws::{HeadInfo, ObjectInfo};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::TryStreamExt;
+use object_store::buffered::BufWriter;
+use object_store::local::LocalFileSystem;
+use object_store::memory::InMemory;
+use object_store::path::Path;
+use object_store::{Attribute, GetOptions, GetRange, ObjectMeta, ObjectStore, PutMode, PutOptions};
+use parquet::arrow::async_writer::AsyncFileWriter;
+
+/// local directory or in-memory store through the object_store crate,
+/// listings are returned as one page
+pub struct ObjectStoreStorage {
+    store: Arc<dyn ObjectStore>,
+    url: String,
+}
+
+impl ObjectStoreStorage {
+    pub fn new(store: Arc<dyn ObjectStore>, url: &str) -> Self {
+        Self {
+            store,
+            url: url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// the directory is created if it does not exist
+    pub fn local(path: &str) -> Result<Self> {
+        std::fs::create_dir_all(path)?;
+        let store = LocalFileSystem::new_with_prefix(path)?;
+        let path = std::fs::canonicalize(path)?;
+        Ok(Self::new(Arc::new(store), &format!("file://{}", path.display())))
+    }
+
+    pub fn memory(url: &str) -> Self {
+        Self::new(Arc::new(InMemory::new()), url)
+    }
+}
+
+/// object_store prefixes are whole path segments, s3 prefixes any part of the key
+fn parent(prefix: &str) -> Option<Path> {
+    prefix
+        .rsplit_once('/')
+        .map(|(dir, _)| dir)
+        .filter(|dir| !dir.is_empty())
+        .map(Path::from)
+}
+
+fn object_info(meta: &ObjectMeta) -> ObjectInfo {
+    ObjectInfo {
+        size: Some(meta.size as i64),
+        last_modified: Some(meta.last_modified.format("%Y-%m-%dT%H:%M:%SZ").to_string()),
+        etag: meta.e_tag.as_ref().map(|x| x.trim_matches('"').to_string()),
+        ..Default::default()
+    }
+}
+
+#[async_trait]
+impl Storage for ObjectStoreStorage {
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    async fn list_page(
+        &self,
+        prefix: &str,
+        delimiter: bool,
+        start_after: Option<&str>,
+        _next: Option<String>,
+    ) -> Result<ListPage> {
+        let dir = parent(prefix);
+        let (objects, prefixes) = if delimiter {
+            let res = match self.store.list_with_delimiter(dir.as_ref()).await {
+                Ok(res) => res,
+                Err(object_store::Error::NotFound { .. }) => return Ok(ListPage::default()),
+                Err(e) => return Err(e.into()),
+            };
+            let prefixes = res.common_prefixes.iter().map(|x| format!("{x}/")).collect::<Vec<_>>();
+            (res.objects, prefixes)
+        } else {
+            let objects = self.store.list(dir.as_ref()).try_collect::<Vec<_>>().await?;
+            (objects, vec![])
+        };
+
+        let mut objects = objects
+            .iter()
+            .map(|meta| (meta.location.to_string(), object_info(meta)))
+            .filter(|(key, _)| key.starts_with(prefix) && start_after.into_iter().all(|x| key.as_str() > x))
+            .collect::<Vec<_>>();
+        objects.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut prefixes = prefixes
+            .into_iter()
+            // a prefix is still listed if start_after is inside it
+            .filter(|x| {
+                x.starts_with(prefix)
+                    && !start_after.is_some_and(|after| x.as_str() <= after && !after.starts_with(x.as_str()))
+            })
+            .collect::<Vec<_>>();
+        prefixes.sort();
+        Ok(ListPage {
+            objects,
+            prefixes,
+            next: None,
+        })
+    }
+
+    /// object_store keeps no versions, each object is its own latest version
+    async fn list_versions_page(&self, prefix: &str, next: Option<String>) -> Result<ListPage> {
+        let mut page = self.list_page(prefix, false, None, next).await?;
+        for (_, info) in page.objects.iter_mut() {
+            info.is_latest = Some(true);
+            info.is_delete_marker = Some(false);
+        }
+        Ok(page)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Bytes>> {
+        match self.store.get(&Path::from(key)).await {
+            Ok(res) => Ok(Some(res.bytes().await?)),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_range(&self, key: &str, range: ByteRange) -> Result<Bytes> {
+        let range = match range {
+            ByteRange::Head(n) => GetRange::Bounded(0..n as usize),
+            ByteRange::Tail(n) => GetRange::Suffix(n as usize),
+            ByteRange::Span(start, end) => GetRange::Bounded(start as usize..end as usize + 1),
+        };
+        let options = GetOptions {
+            range: Some(range),
+            ..Default::default()
+        };
+        Ok(self.store.get_opts(&Path::from(key), options).await?.bytes().await?)
+    }
+
+    async fn head(&self, key: &str) -> Result<HeadInfo> {
+        let options = GetOptions {
+            head: true,
+            ..Default::default()
+        };
+        let res = self.store.get_opts(&Path::from(key), options).await?;
+        let attribute = |x: &Attribute| res.attributes.get(x).map(|x| x.to_string());
+        let user_metadata = res
+            .attributes
+            .iter()
+            .filter_map(|(k, v)| match k {
+                Attribute::Metadata(name) => Some((name.to_string(), v.to_string())),
+                _ => None,
+            })
+            .collect::<BTreeMap<_, _>>();
+        Ok(HeadInfo {
+            content_type: attribute(&Attribute::ContentType),
+            content_encoding: attribute(&Attribute::ContentEncoding),
+            user_metadata: Some(user_metadata),
+            ..Default::default()
+        })
+    }
+
+    /// tags are not kept by object_store
+    async fn tags(&self, _key: &str) -> Result<BTreeMap<String, String>> {
+        Ok(BTreeMap::new())
+    }
+
+    async fn put(&self, key: &str, data: Bytes) -> Result<()> {
+        self.store.put(&Path::from(key), data.into()).await?;
+        Ok(())
+    }
+
+    async fn put_if_absent(&self, key: &str, data: Bytes) -> Result<bool> {
+        let options = PutOptions {
+            mode: PutMode::Create,
+            ..Default::default()
+        };
+        match self.store.put_opts(&Path::from(key), data.into(), options).await {
+            Ok(_) => Ok(true),
+            Err(object_store::Error::AlreadyExists { .. }) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, keys: Vec<String>) -> Result<()> {
+        for key in keys {
+            match self.store.delete(&Path::from(key.as_str())).await {
+                Ok(()) | Err(object_store::Error::NotFound { .. }) => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    fn writer(&self, key: &str, part_size: usize) -> Box<dyn AsyncFileWriter> {
+        Box::new(BufWriter::with_capacity(self.store.clone(), Path::from(key), part_size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("", None)]
+    #[case("a", None)]
+    #[case("a/", Some("a"))]
+    #[case("a/b/c", Some("a/b"))]
+    fn test_parent(#[case] prefix: &str, #[case] expected: Option<&str>) {
+        assert_eq!(parent(prefix), expected.map(Path::from));
+    }
+
+    #[tokio::test]
+    async fn test_local() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("storage-{}", uuid::Uuid::new_v4()));
+        let storage = ObjectStoreStorage::local(&dir.to_string_lossy())?;
+        assert!(storage.url().starts_with("file:///"));
+        storage.put("a/b.csv", Bytes::from_static(b"x,y")).await?;
+        let page = storage.list_page("", false, None, None).await?;
+        assert_eq!(page.objects.len(), 1);
+        assert_eq!(page.objects[0].0, "a/b.csv");
+        assert_eq!(page.objects[0].1.size, Some(3));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
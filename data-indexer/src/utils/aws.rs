@@ -1,104 +1,133 @@
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
+use crate::inventory::InventoryInfo;
 use crate::utils::constants::*;
 
-use anyhow::Result;
 use aws_config::{retry::RetryConfig, BehaviorVersion, Region};
 use aws_sdk_s3::{
     config::Builder,
-    operation::get_object::{GetObjectError, GetObjectOutput},
+    operation::head_object::HeadObjectOutput,
+    types::{DeleteMarkerEntry, Object, ObjectVersion},
     Client,
 };
 
-pub async fn get_aws_client(region: &str) -> Client {
-    let region = Region::new(region.to_string());
-    let sdk_config = aws_config::defaults(BehaviorVersion::latest())
-        .region(region)
-        .load()
-        .await;
-    let config_builder = Builder::from(&sdk_config)
-        .retry_config(RetryConfig::standard().with_max_attempts(AWS_MAX_RETRIES));
-    let config = config_builder.build();
-    Client::from_conf(config)
+/// object metadata returned by ListObjectsV2 or ListObjectVersions
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ObjectInfo {
+    pub size: Option<i64>,
+    pub last_modified: Option<String>,
+    pub etag: Option<String>,
+    pub storage_class: Option<String>,
+    pub owner: Option<String>,
+    pub checksum_algorithm: Option<String>,
+    /// fields only available in s3 inventory reports
+    pub inventory: Option<InventoryInfo>,
+    /// only set when versions are listed
+    pub version_id: Option<String>,
+    pub is_latest: Option<bool>,
+    pub is_delete_marker: Option<bool>,
 }
 
-pub async fn read_file(client: Client, bucket: &str, key: &str) -> Result<Vec<u8>> {
-    let mut buf = Vec::new();
-    let mut object = get_file(client, bucket, key).await?;
-    while let Some(bytes) = object.body.try_next().await? {
-        buf.extend(bytes.to_vec());
+impl From<&Object> for ObjectInfo {
+    fn from(obj: &Object) -> Self {
+        Self {
+            size: obj.size(),
+            last_modified: obj.last_modified().map(|x| x.to_string()),
+            etag: obj.e_tag().map(trim_etag),
+            storage_class: obj.storage_class().map(|x| x.as_str().to_string()),
+            owner: obj.owner().and_then(|x| x.id()).map(|x| x.to_string()),
+            checksum_algorithm: obj.checksum_algorithm().first().map(|x| x.as_str().to_string()),
+            inventory: None,
+            version_id: None,
+            is_latest: None,
+            is_delete_marker: None,
+        }
     }
-    Ok(buf)
 }
 
-pub async fn try_get_file(
-    client: Client,
-    bucket: &str,
-    key: &str,
-) -> Result<Option<GetObjectOutput>> {
-    let resp = client.get_object().bucket(bucket).key(key);
-
-    let res = resp.send().await;
+impl From<&ObjectVersion> for ObjectInfo {
+    fn from(obj: &ObjectVersion) -> Self {
+        Self {
+            size: obj.size(),
+            last_modified: obj.last_modified().map(|x| x.to_string()),
+            etag: obj.e_tag().map(trim_etag),
+            storage_class: obj.storage_class().map(|x| x.as_str().to_string()),
+            owner: obj.owner().and_then(|x| x.id()).map(|x| x.to_string()),
+            checksum_algorithm: obj.checksum_algorithm().first().map(|x| x.as_str().to_string()),
+            inventory: None,
+            version_id: obj.version_id().map(|x| x.to_string()),
+            is_latest: obj.is_latest(),
+            is_delete_marker: Some(false),
+        }
+    }
+}
 
-    match res {
-        Ok(res) => Ok(Some(res)),
-        Err(sdk_err) => match sdk_err.into_service_error() {
-            GetObjectError::NoSuchKey(_) => Ok(None),
-            err => Err(err.into()),
-        },
+impl From<&DeleteMarkerEntry> for ObjectInfo {
+    fn from(marker: &DeleteMarkerEntry) -> Self {
+        Self {
+            last_modified: marker.last_modified().map(|x| x.to_string()),
+            owner: marker.owner().and_then(|x| x.id()).map(|x| x.to_string()),
+            version_id: marker.version_id().map(|x| x.to_string()),
+            is_latest: marker.is_latest(),
+            is_delete_marker: Some(true),
+            ..Default::default()
+        }
     }
 }
 
-pub async fn get_file(client: Client, bucket: &str, key: &str) -> Result<GetObjectOutput> {
-    let resp = client.get_object().bucket(bucket).key(key).send().await?;
-    Ok(resp)
+/// object metadata returned by HeadObject
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeadInfo {
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub checksum_algorithm: Option<String>,
+    pub checksum_value: Option<String>,
+    /// `x-amz-meta-*` headers without the prefix
+    pub user_metadata: Option<BTreeMap<String, String>>,
 }
 
-pub async fn list_keys(client: Client, bucket: &str, prefix: &str) -> Result<Vec<String>> {
-    let mut stream = client
-        .list_objects_v2()
-        .prefix(prefix)
-        .bucket(bucket)
-        .into_paginator()
-        .send();
+impl From<&HeadObjectOutput> for HeadInfo {
+    fn from(head: &HeadObjectOutput) -> Self {
+        let checksum = [
+            ("CRC64NVME", head.checksum_crc64_nvme()),
+            ("CRC32", head.checksum_crc32()),
+            ("CRC32C", head.checksum_crc32_c()),
+            ("SHA1", head.checksum_sha1()),
+            ("SHA256", head.checksum_sha256()),
+        ]
+        .into_iter()
+        .find_map(|(algorithm, value)| value.map(|v| (algorithm.to_string(), v.to_string())));
 
-    let mut files = Vec::new();
-    while let Some(objects) = stream.next().await.transpose()? {
-        for obj in objects.contents() {
-            if let Some(key) = obj.key() {
-                if !key.ends_with('/') {
-                    files.push(key.to_string());
-                }
-            }
+        Self {
+            content_type: head.content_type().map(|x| x.to_string()),
+            content_encoding: head.content_encoding().map(|x| x.to_string()),
+            checksum_algorithm: checksum.as_ref().map(|x| x.0.clone()),
+            checksum_value: checksum.map(|x| x.1),
+            user_metadata: head
+                .metadata()
+                .map(|x| x.iter().map(|(k, v)| (k.clone(), v.clone())).collect()),
         }
     }
-    Ok(files)
 }
 
-pub async fn list_keys_to_map(
-    client: Client,
-    bucket: &str,
-    prefix: &str,
-) -> Result<HashMap<String, (Option<i64>, Option<String>)>> {
-    let mut stream = client
-        .list_objects_v2()
-        .bucket(bucket)
-        .prefix(prefix)
-        .into_paginator()
-        .send();
+fn trim_etag(etag: &str) -> String {
+    etag.trim_matches('"').to_string()
+}
 
-    let mut files: HashMap<String, (Option<i64>, Option<String>)> = HashMap::new();
-    while let Some(objects) = stream.next().await.transpose()? {
-        for obj in objects.contents() {
-            if let Some(key) = obj.key() {
-                if !key.ends_with('/') {
-                    let file_name = key.to_string();
-                    let file_size = obj.size();
-                    let file_dt = obj.last_modified.map(|x| x.to_string());
-                    files.insert(file_name, (file_size, file_dt));
-                }
-            }
-        }
+pub async fn get_aws_client(region: &str) -> Client {
+    get_aws_client_with_profile(region, None).await
+}
+
+/// client with credentials of a named profile, e.g. for a source in another account
+pub async fn get_aws_client_with_profile(region: &str, profile: Option<&str>) -> Client {
+    let region = Region::new(region.to_string());
+    let mut loader = aws_config::defaults(BehaviorVersion::latest()).region(region);
+    if let Some(profile) = profile {
+        loader = loader.profile_name(profile);
     }
-    Ok(files)
+    let sdk_config = loader.load().await;
+    let config_builder = Builder::from(&sdk_config)
+        .retry_config(RetryConfig::standard().with_max_attempts(AWS_MAX_RETRIES));
+    let config = config_builder.build();
+    Client::from_conf(config)
 }
@@ -1,2 +1,38 @@
 pub const REGION: &str = "eu-central-1";
 pub const AWS_MAX_RETRIES: u32 = 10;
+pub const SOURCE_WORKERS: usize = 4; // max sources listed concurrently
+pub const LIST_WORKERS: usize = 16; // max concurrent ListObjectsV2 requests of the sharded lister
+pub const LIST_SPLIT_PAGES: usize = 10; // pages listed before a shard is split by key range
+pub const LIST_SHARD_DEPTH: usize = 3; // levels of common prefixes expanded to find shards
+pub const LIST_PAGE_SIZE: usize = 1000; // objects per page, as returned by ListObjectsV2
+pub const HEAD_OBJECT_WORKERS: usize = 50; // max concurrent HeadObject requests
+pub const WATERMARK_FILE: &str = "_watermark.json";
+pub const DELETIONS_DIR: &str = "_deletions/"; // deletion reports next to the index files
+pub const MANIFESTS_DIR: &str = "_manifests/"; // one manifest per index run next to the index files
+pub const MANIFEST_FILE: &str = "_manifest.json"; // manifest of the last run, read by the api for freshness
+pub const COMBINED_PREFIX: &str = "index/combined/"; // read by the api as object_store
+pub const GENERATION_FILE: &str = "_current.json"; // points to the current combined files
+pub const TARGET_FILE_SIZE: usize = 128 * 1024 * 1024; // 128 MiB
+pub const CATALOG_PREFIX: &str = "catalog/"; // read by the api as object_store_catalog
+pub const ROW_GROUP_SIZE: usize = 100_000; // rows buffered before a row group is written
+pub const MAX_OPEN_FILES: usize = 32; // partition files written at once, each buffers a row group and an upload part
+pub const BLOOM_FILTER_FPP: f64 = 0.01; // false positive probability of the bloom filters of a row group
+pub const SORT_RUN_ROWS: usize = 1_000_000; // rows sorted in memory before a run is spilled to a temp file
+pub const SORT_BATCH_ROWS: usize = 8192; // rows per batch read from and merged out of the sorted runs
+pub const METADATA_WORKERS: usize = 20; // max files read concurrently by metadata extractors
+pub const METADATA_MAX_READS: usize = 3; // ranged reads per file before giving up
+pub const ENRICH_WORKERS: usize = 50; // max files whose tags and head are read concurrently
+pub const ENRICH_MAX_ATTEMPTS: u32 = 3; // tries of a GetObjectTagging or HeadObject before the file is skipped
+pub const ENRICH_RETRY_DELAY_MS: u64 = 200; // first backoff, doubled on every retry
+pub const HASH_WORKERS: usize = 8; // max files hashed concurrently
+pub const HASH_CHUNK_SIZE: u64 = 8 * 1024 * 1024; // 8 MiB ranges read to hash large files
+pub const HASH_CHUNK_WORKERS: usize = 4; // max ranges of one file read ahead while hashing
+pub const ARCHIVE_WORKERS: usize = 8; // max archives whose members are read concurrently
+pub const ARCHIVE_CHUNK_SIZE: u64 = 1024 * 1024; // 1 MiB ranges read to find tar headers
+pub const ARCHIVE_CHUNK_WORKERS: usize = 4; // max ranges of a compressed tar read ahead
+pub const ARCHIVE_MAX_GZIP_SIZE: u64 = 1024 * 1024 * 1024; // compressed tars are read whole, bigger ones are skipped
+pub const SCHEMA_VERSION: u32 = 5; // bumped when index columns change, files without it are version 0
+pub const SCHEMA_VERSION_KEY: &str = "data_indexer.schema_version"; // parquet key-value metadata read by the api
+pub const DUPLICATES_PREFIX: &str = "duplicates/"; // files with the same content
+pub const DELTA_LOG_DIR: &str = "_delta_log/"; // commits of the delta table, read by the api
+pub const VACUUM_RETENTION_HOURS: u64 = 7 * 24; // removed delta files kept for readers of older versions
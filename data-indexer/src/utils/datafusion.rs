@@ -1,14 +1,16 @@
+use std::io::Cursor;
 use std::sync::Arc;
 
-use anyhow::Result;
-use aws_sdk_s3::primitives::ByteStream;
-use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
-use aws_sdk_s3::Client;
+use crate::storage::{read_file, Storage};
+use crate::writer_options::WriterOptions;
+
+use anyhow::{anyhow, Result};
+use dataplatform_multipart::constants::PART_SIZE;
 use datafusion::arrow::array::{ArrayRef, RecordBatch, StructArray};
-use datafusion::arrow::compute::concat;
+use datafusion::arrow::compute::{concat, concat_batches};
 use datafusion::arrow::datatypes::{DataType, Field, Schema};
 use datafusion::prelude::*;
-use parquet::arrow::AsyncArrowWriter;
+use parquet::arrow::{AsyncArrowWriter, ParquetRecordBatchStreamBuilder};
 use tokio_stream::StreamExt;
 
 pub fn select_all_exclude(df: DataFrame, to_exclude: &[&str]) -> Result<DataFrame> {
@@ -98,124 +100,55 @@ pub async fn df_cols_to_struct(
     Ok(res)
 }
 
-pub async fn write_batches_to_s3(
-    client: Client,
-    bucket: &str,
+pub async fn read_parquet(storage: &dyn Storage, key: &str) -> Result<Vec<RecordBatch>> {
+    let buf = read_file(storage, key).await?;
+    let mut stream = ParquetRecordBatchStreamBuilder::new(Cursor::new(buf))
+        .await?
+        .build()?;
+    let mut batches = vec![];
+    while let Some(batch) = stream.next().await.transpose()? {
+        batches.push(batch);
+    }
+    Ok(batches)
+}
+
+pub async fn write_batches(storage: &dyn Storage, key: &str, batches: Vec<RecordBatch>) -> Result<()> {
+    write_batches_with(storage, key, batches, &WriterOptions::default()).await
+}
+
+/// writes the batches as one file, sorted as a whole if the options sort by file_path
+pub async fn write_batches_with(
+    storage: &dyn Storage,
     key: &str,
     batches: Vec<RecordBatch>,
+    options: &WriterOptions,
 ) -> Result<()> {
-    let mut buf = vec![];
-    let schema = batches[0].schema();
-    let mut writer = AsyncArrowWriter::try_new(&mut buf, schema, None)?;
+    let schema = batches
+        .first()
+        .ok_or_else(|| anyhow!("no batches to write to: {key}"))?
+        .schema();
+    let batches = match options.sort_by_file_path {
+        true => vec![options.sort(concat_batches(&schema, &batches)?)?],
+        false => batches,
+    };
+    let sink = storage.writer(key, PART_SIZE);
+    let mut writer = AsyncArrowWriter::try_new(sink, schema.clone(), Some(options.properties(&schema)))?;
     for batch in batches {
         writer.write(&batch).await?;
     }
     writer.close().await?;
-
-    let multipart_upload_res = client
-        .create_multipart_upload()
-        .bucket(bucket)
-        .key(key)
-        .send()
-        .await?;
-
-    let upload_id = multipart_upload_res.upload_id().unwrap(); // todo
-    let mut upload_parts = Vec::new();
-    let mut stream = ByteStream::from(buf);
-    let mut part_number = 1;
-    while let Some(bytes) = stream.next().await {
-        let bytes = bytes?;
-        let upload_part_res = client
-            .upload_part()
-            .key(key)
-            .bucket(bucket)
-            .upload_id(upload_id)
-            .body(ByteStream::from(bytes))
-            .part_number(part_number)
-            .send()
-            .await?;
-
-        upload_parts.push(
-            CompletedPart::builder()
-                .e_tag(upload_part_res.e_tag.unwrap_or_default())
-                .part_number(part_number)
-                .build(),
-        );
-
-        part_number += 1;
-    }
-
-    let completed_multipart_upload = CompletedMultipartUpload::builder()
-        .set_parts(Some(upload_parts))
-        .build();
-
-    let _complete_multipart_upload_res = client
-        .complete_multipart_upload()
-        .bucket(bucket)
-        .key(key)
-        .multipart_upload(completed_multipart_upload)
-        .upload_id(upload_id)
-        .send()
-        .await?;
-
     Ok(())
 }
 
-pub async fn write_df_to_s3(client: Client, bucket: &str, key: &str, df: DataFrame) -> Result<()> {
-    let mut buf = vec![];
+pub async fn write_df(storage: &dyn Storage, key: &str, df: DataFrame) -> Result<()> {
     let schema = Schema::from(df.clone().schema());
     let mut stream = df.execute_stream().await?;
-    let mut writer = AsyncArrowWriter::try_new(&mut buf, schema.into(), None)?;
+    let sink = storage.writer(key, PART_SIZE);
+    let props = WriterOptions::default().properties(&schema);
+    let mut writer = AsyncArrowWriter::try_new(sink, schema.into(), Some(props))?;
     while let Some(batch) = stream.next().await.transpose()? {
         writer.write(&batch).await?;
     }
     writer.close().await?;
-
-    let multipart_upload_res = client
-        .create_multipart_upload()
-        .bucket(bucket)
-        .key(key)
-        .send()
-        .await?;
-
-    let upload_id = multipart_upload_res.upload_id().unwrap_or_default();
-    let mut upload_parts: Vec<CompletedPart> = Vec::new();
-    let mut stream = ByteStream::from(buf);
-    let mut part_number = 1;
-    while let Some(bytes) = stream.next().await {
-        let bytes = bytes?;
-        let upload_part_res = client
-            .upload_part()
-            .key(key)
-            .bucket(bucket)
-            .upload_id(upload_id)
-            .body(ByteStream::from(bytes))
-            .part_number(part_number)
-            .send()
-            .await?;
-
-        upload_parts.push(
-            CompletedPart::builder()
-                .e_tag(upload_part_res.e_tag.unwrap_or_default())
-                .part_number(part_number)
-                .build(),
-        );
-
-        part_number += 1;
-    }
-
-    let completed_multipart_upload: CompletedMultipartUpload = CompletedMultipartUpload::builder()
-        .set_parts(Some(upload_parts))
-        .build();
-
-    let _complete_multipart_upload_res = client
-        .complete_multipart_upload()
-        .bucket(bucket)
-        .key(key)
-        .multipart_upload(completed_multipart_upload)
-        .upload_id(upload_id)
-        .send()
-        .await?;
-
     Ok(())
 }
@@ -0,0 +1,88 @@
+use std::collections::BTreeMap;
+
+use crate::storage::Storage;
+use crate::utils::constants::WATERMARK_FILE;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// state of the last successful run, stored next to the index files
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Watermark {
+    pub last_run: String,
+    pub last_modified: Option<String>,
+    pub last_key: Option<String>,
+    pub snapshot: Vec<String>,
+    /// listing state per source url
+    #[serde(default)]
+    pub sources: BTreeMap<String, SourceWatermark>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SourceWatermark {
+    pub last_modified: Option<String>,
+    pub last_key: Option<String>,
+}
+
+impl Watermark {
+    pub fn key(prefix_target: &str) -> String {
+        format!("{prefix_target}{WATERMARK_FILE}")
+    }
+
+    /// state of the source, watermarks written before multiple sources
+    /// hold the state of their only source at the top level
+    pub fn source(&self, url: &str, single: bool) -> Option<SourceWatermark> {
+        match self.sources.get(url) {
+            Some(source) => Some(source.clone()),
+            None if single && self.sources.is_empty() => Some(SourceWatermark {
+                last_modified: self.last_modified.clone(),
+                last_key: self.last_key.clone(),
+            }),
+            None => None,
+        }
+    }
+
+    pub async fn load(storage: &dyn Storage, prefix_target: &str) -> Result<Option<Self>> {
+        let key = Self::key(prefix_target);
+        let Some(data) = storage.get(&key).await? else {
+            return Ok(None);
+        };
+        let watermark = serde_json::from_slice(&data)?;
+        Ok(Some(watermark))
+    }
+
+    pub async fn save(&self, storage: &dyn Storage, prefix_target: &str) -> Result<()> {
+        let key = Self::key(prefix_target);
+        let data = serde_json::to_vec_pretty(self)?;
+        storage.put(&key, data.into()).await?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Watermark {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "watermark: last_run: {} last_modified: {:?} last_key: {:?} snapshot files: {} sources: {}",
+        self.last_run,
+        self.last_modified,
+        self.last_key,
+        self.snapshot.len(),
+        self.sources.len(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_legacy() -> Result<()> {
+        let watermark: Watermark = serde_json::from_str(
+            r#"{"last_run": "2024-01-01T00:00:00Z", "last_modified": null, "last_key": "a/b.csv", "snapshot": []}"#,
+        )?;
+        let source = watermark.source("s3://bucket/a/", true).unwrap();
+        assert_eq!(source.last_key.as_deref(), Some("a/b.csv"));
+        assert_eq!(watermark.source("s3://bucket/a/", false), None);
+        Ok(())
+    }
+}
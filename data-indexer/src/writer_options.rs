@@ -0,0 +1,158 @@
+use std::str::FromStr;
+
+use crate::schema_version;
+use crate::utils::constants::*;
+
+use anyhow::{anyhow, Result};
+use datafusion::arrow::array::RecordBatch;
+use datafusion::arrow::compute::{sort_to_indices, take_record_batch};
+use datafusion::arrow::datatypes::Schema;
+use parquet::basic::Compression;
+use parquet::format::SortingColumn;
+use parquet::file::properties::{EnabledStatistics, WriterProperties};
+use parquet::schema::types::ColumnPath;
+
+/// column the rows of every row group are sorted by
+pub const SORT_COLUMN: &str = "file_path";
+
+/// parquet settings of the written index files
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriterOptions {
+    pub compression: Compression,
+    pub statistics: EnabledStatistics,
+    pub row_group_size: usize,
+    /// columns with a bloom filter per row group, for point lookups
+    pub bloom_filters: Vec<String>,
+    /// sorts the rows of each row group, compaction also sorts across row groups
+    pub sort_by_file_path: bool,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        Self {
+            compression: Compression::UNCOMPRESSED,
+            statistics: EnabledStatistics::Page,
+            row_group_size: ROW_GROUP_SIZE,
+            bloom_filters: vec![],
+            sort_by_file_path: false,
+        }
+    }
+}
+
+impl WriterOptions {
+    /// parses the codec and statistics level as written in the config, e.g. `zstd(3)` and `page`
+    pub fn try_new(
+        compression: Option<&str>,
+        statistics: Option<&str>,
+        row_group_size: Option<usize>,
+        bloom_filters: Vec<String>,
+        sort_by_file_path: bool,
+    ) -> Result<Self> {
+        let default = Self::default();
+        let compression = match compression {
+            Some(x) => Compression::from_str(x).map_err(|e| anyhow!("compression: {e}"))?,
+            None => default.compression,
+        };
+        let statistics = match statistics {
+            Some(x) => EnabledStatistics::from_str(x).map_err(|e| anyhow!("statistics: {e}"))?,
+            None => default.statistics,
+        };
+        let row_group_size = row_group_size.unwrap_or(default.row_group_size);
+        if row_group_size == 0 {
+            return Err(anyhow!("row_group_size must be greater than 0"));
+        }
+        Ok(Self {
+            compression,
+            statistics,
+            row_group_size,
+            bloom_filters,
+            sort_by_file_path,
+        })
+    }
+
+    /// properties of a file with the schema, bloom filters are sized for a full row group,
+    /// columns missing from the schema such as partition columns are skipped
+    pub fn properties(&self, schema: &Schema) -> WriterProperties {
+        let mut builder = WriterProperties::builder()
+            .set_compression(self.compression)
+            .set_statistics_enabled(self.statistics)
+            .set_max_row_group_size(self.row_group_size)
+            .set_key_value_metadata(Some(schema_version::metadata()));
+        for name in self.bloom_filters.iter().filter(|x| schema.index_of(x).is_ok()) {
+            let col = ColumnPath::from(name.as_str());
+            builder = builder
+                .set_column_bloom_filter_enabled(col.clone(), true)
+                .set_column_bloom_filter_fpp(col.clone(), BLOOM_FILTER_FPP)
+                .set_column_bloom_filter_ndv(col, self.row_group_size as u64);
+        }
+        if let Some(i) = self.sort_index(schema) {
+            builder = builder.set_sorting_columns(Some(vec![SortingColumn {
+                column_idx: i as i32,
+                descending: false,
+                nulls_first: true,
+            }]));
+        }
+        builder.build()
+    }
+
+    /// leaf index of the sort column, only top-level columns before it are supported
+    fn sort_index(&self, schema: &Schema) -> Option<usize> {
+        if !self.sort_by_file_path {
+            return None;
+        }
+        let i = schema.index_of(SORT_COLUMN).ok()?;
+        schema.fields()[..i]
+            .iter()
+            .all(|x| !x.data_type().is_nested())
+            .then_some(i)
+    }
+
+    /// sorts the rows by file_path if enabled, one sorted batch is written as whole row groups
+    pub fn sort(&self, batch: RecordBatch) -> Result<RecordBatch> {
+        if !self.sort_by_file_path {
+            return Ok(batch);
+        }
+        let Some(col) = batch.column_by_name(SORT_COLUMN) else {
+            return Ok(batch);
+        };
+        let indices = sort_to_indices(col, None, None)?;
+        Ok(take_record_batch(&batch, &indices)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::file_data::FileData;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(Some("zstd(3)"), Some("chunk"), true)]
+    #[case(Some("snappy"), None, true)]
+    #[case(Some("zstd(99)"), None, false)]
+    #[case(Some("foo"), None, false)]
+    #[case(None, Some("rows"), false)]
+    fn test_try_new(#[case] compression: Option<&str>, #[case] statistics: Option<&str>, #[case] valid: bool) {
+        let options = WriterOptions::try_new(compression, statistics, None, vec![], false);
+        assert_eq!(options.is_ok(), valid);
+    }
+
+    #[test]
+    fn test_properties() -> Result<()> {
+        let schema = FileData::schema();
+        let options = WriterOptions {
+            bloom_filters: vec!["file_name".to_string(), "file_path".to_string()],
+            sort_by_file_path: true,
+            ..Default::default()
+        };
+        let props = options.properties(&schema);
+        assert!(props.bloom_filter_properties(&ColumnPath::from("file_name")).is_some());
+        assert!(props.bloom_filter_properties(&ColumnPath::from("file_url")).is_none());
+        let sorting = props.sorting_columns().expect("sorting columns");
+        assert_eq!(sorting[0].column_idx as usize, schema.index_of(SORT_COLUMN)?);
+        assert!(WriterOptions::default().properties(&schema).sorting_columns().is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use data_indexer::compact::Generation;
+use data_indexer::config::{Config, Mode};
+use data_indexer::delta::DeltaTable;
+use data_indexer::events::index_events;
+use data_indexer::manifest::RunManifest;
+use data_indexer::run;
+use data_indexer::schema_version::read_versioned;
+use data_indexer::storage::{list_keys, read_file, ObjectStoreStorage, Storage, StorageRef, Stores};
+use data_indexer::utils::constants::SCHEMA_VERSION;
+use data_indexer::utils::datafusion::read_parquet;
+use data_indexer::watermark::Watermark;
+
+use anyhow::Result;
+use bytes::Bytes;
+use datafusion::arrow::array::{Array, AsArray, RecordBatch};
+
+const SOURCE: &str = "memory://raw";
+const TARGET: &str = "memory://index";
+
+fn config(target: &str, args: &str) -> Result<Config> {
+    Config::create("", target, "", "index/", "", args)
+}
+
+async fn put(storage: &dyn Storage, files: &[(&str, &str)]) -> Result<()> {
+    for (key, body) in files {
+        storage.put(key, Bytes::from(body.to_string())).await?;
+    }
+    Ok(())
+}
+
+/// values of a string column over all index files under the prefix
+async fn column(storage: &StorageRef, prefix: &str, name: &str) -> Result<Vec<String>> {
+    let mut values = vec![];
+    for key in list_keys(storage.clone(), prefix).await? {
+        if !key.ends_with(".parquet") {
+            continue;
+        }
+        for batch in read_parquet(storage.as_ref(), &key).await? {
+            values.extend(strings(&batch, name));
+        }
+    }
+    values.sort();
+    Ok(values)
+}
+
+fn strings(batch: &RecordBatch, name: &str) -> Vec<String> {
+    let col = batch.column_by_name(name).expect("column exists").as_string::<i32>();
+    (0..col.len())
+        .map(|i| if col.is_null(i) { String::new() } else { col.value(i).to_string() })
+        .collect()
+}
+
+fn memory_stores() -> (Stores, StorageRef, StorageRef) {
+    let source: StorageRef = Arc::new(ObjectStoreStorage::memory(SOURCE));
+    let target: StorageRef = Arc::new(ObjectStoreStorage::memory(TARGET));
+    let mut stores = Stores::new(None);
+    stores.insert(SOURCE, source.clone());
+    stores.insert(TARGET, target.clone());
+    (stores, source, target)
+}
+
+#[tokio::test]
+async fn test_index_compact_in_memory() -> Result<()> {
+    let (stores, source, target) = memory_stores();
+    put(
+        source.as_ref(),
+        &[("mri/a.csv", "id,name\n1,a\n"), ("mri/b/c.json", r#"{"id": 1}"#), ("other/d.csv", "x\n")],
+    )
+    .await?;
+    let args = r#"{
+        "incremental": true,
+        "track_deletions": true,
+        "extract_metadata": true,
+        "catalog": true,
+        "sources": [{"bucket": "memory://raw", "prefix": "mri/", "dataset": "mri"}]
+    }"#;
+
+    run(&stores, config(TARGET, args)?).await?;
+    assert_eq!(
+        column(&target, "index/", "file_url").await?,
+        vec!["memory://raw/mri/a.csv", "memory://raw/mri/b/c.json"]
+    );
+    let watermark = Watermark::load(target.as_ref(), "index/").await?.expect("watermark saved");
+    assert_eq!(watermark.snapshot.len(), 1);
+    assert_eq!(read_versioned(target.as_ref(), &watermark.snapshot[0]).await?.0, SCHEMA_VERSION);
+    assert_eq!(watermark.last_key.as_deref(), Some("mri/b/c.json"));
+    assert_eq!(column(&target, "catalog/", "file_type").await?, vec!["csv", "json"]);
+
+    let manifest: RunManifest = serde_json::from_slice(&read_file(target.as_ref(), "index/_manifest.json").await?)?;
+    assert!(manifest.success);
+    assert_eq!(manifest.sources, vec!["memory://raw/mri/"]);
+    assert_eq!((manifest.summary.listed, manifest.summary.new_or_changed), (2, 2));
+    assert_eq!(manifest.summary.file_types["csv"].files, 1);
+    assert_eq!(manifest.outputs, watermark.snapshot);
+
+    // the next run writes the new file and a tombstone for the deleted one
+    source.delete(vec!["mri/a.csv".to_string()]).await?;
+    put(source.as_ref(), &[("mri/e.csv", "id\n2\n")]).await?;
+    run(&stores, config(TARGET, args)?).await?;
+    let watermark = Watermark::load(target.as_ref(), "index/").await?.expect("watermark saved");
+    assert_eq!(watermark.snapshot.len(), 2);
+
+    let mut compact = config(TARGET, args)?;
+    compact.args.mode = Some(Mode::Compact);
+    run(&stores, compact).await?;
+    let generation = Generation::load(target.as_ref(), "index/combined/").await?.expect("generation saved");
+    assert_eq!(generation.rows, 3);
+    assert_eq!(
+        column(&target, "index/combined/", "file_url").await?,
+        vec!["memory://raw/mri/a.csv", "memory://raw/mri/b/c.json", "memory://raw/mri/e.csv"]
+    );
+    assert_eq!(column(&target, "index/combined/", "deleted_at").await?.iter().filter(|x| !x.is_empty()).count(), 1);
+
+    let mut verify = config(TARGET, args)?;
+    verify.args.mode = Some(Mode::Verify);
+    run(&stores, verify).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_index_delta_in_memory() -> Result<()> {
+    let (stores, source, target) = memory_stores();
+    put(source.as_ref(), &[("mri/a.csv", "1"), ("mri/b.csv", "2")]).await?;
+    let args = r#"{
+        "incremental": true,
+        "track_deletions": true,
+        "output": "delta",
+        "sources": [{"bucket": "memory://raw", "prefix": "mri/"}]
+    }"#;
+    run(&stores, config(TARGET, args)?).await?;
+
+    // the changed file is upserted and the deleted one replaced by its tombstone
+    source.delete(vec!["mri/a.csv".to_string()]).await?;
+    put(source.as_ref(), &[("mri/b.csv", "22")]).await?;
+    run(&stores, config(TARGET, args)?).await?;
+
+    let table = DeltaTable::new(target.clone(), "index/combined/");
+    let state = table.load().await?;
+    assert_eq!(state.version, Some(1));
+    let mut urls = vec![];
+    let mut live = vec![];
+    for path in state.files.keys() {
+        for batch in read_parquet(target.as_ref(), &table.key(path)).await? {
+            urls.extend(strings(&batch, "file_url"));
+            live.extend(strings(&batch, "deleted_at").into_iter().map(|x| x.is_empty()));
+        }
+    }
+    assert_eq!(urls.len(), 2);
+    assert_eq!(live.iter().filter(|x| !**x).count(), 1);
+    // no per-run files outside of the table
+    let keys = list_keys(target.clone(), "index/").await?;
+    assert!(keys.iter().filter(|x| x.ends_with(".parquet")).all(|x| x.starts_with("index/combined/")));
+
+    let watermark = Watermark::load(target.as_ref(), "index/").await?.expect("watermark saved");
+    assert_eq!(watermark.snapshot.len(), state.files.len());
+    let mut verify = config(TARGET, args)?;
+    verify.args.mode = Some(Mode::Verify);
+    run(&stores, verify).await?;
+
+    // the file rewritten by the merge is kept for the retention
+    let mut vacuum = config(TARGET, args)?;
+    vacuum.args.mode = Some(Mode::Vacuum);
+    run(&stores, vacuum).await?;
+    assert_eq!(table.stale_files(0).await?.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_index_events_in_memory() -> Result<()> {
+    let target: StorageRef = Arc::new(ObjectStoreStorage::memory(TARGET));
+    let mut stores = Stores::new(None);
+    stores.insert(TARGET, target.clone());
+    // event buckets are s3 bucket names
+    stores.insert("raw", Arc::new(ObjectStoreStorage::memory("memory://raw")));
+    let args = r#"{"sources": [{"bucket": "raw", "prefix": "mri/", "dataset": "mri"}]}"#;
+    let config = config(TARGET, args)?;
+
+    // only the newest event per object is kept, the removal of big.dcm replaces its upload
+    for (name, expected) in [("s3.json", (1, 1)), ("sqs.json", (0, 1))] {
+        let path = format!("{}/tests/fixtures/events/{name}", env!("CARGO_MANIFEST_DIR"));
+        let payload = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        let summary = index_events(&stores, &config, &payload).await?;
+        assert_eq!((summary.upserts, summary.tombstones), expected, "{name}");
+        assert_eq!(summary.outputs.len(), 1);
+    }
+
+    let mut compact = config.clone();
+    compact.args.mode = Some(Mode::Compact);
+    run(&stores, compact).await?;
+    assert_eq!(
+        column(&target, "index/combined/", "file_url").await?,
+        vec!["s3://raw/mri/big.dcm", "s3://raw/mri/old.csv", "s3://raw/mri/scan 1+2.csv"]
+    );
+    assert_eq!(column(&target, "index/combined/", "deleted_at").await?.iter().filter(|x| !x.is_empty()).count(), 2);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_index_local_directory() -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("data-indexer-{}", uuid::Uuid::new_v4()));
+    let source = ObjectStoreStorage::local(&dir.join("raw").to_string_lossy())?;
+    put(&source, &[("a.csv", "id\n1\n"), ("b/c.parquet", "not parquet")]).await?;
+    let target_url = format!("file://{}", dir.join("index").display());
+
+    // file:// locations are opened from the config without an aws client
+    let config = config(
+        &target_url,
+        &format!(r#"{{"sources": [{{"bucket": "{}", "prefix": ""}}]}}"#, source.url()),
+    )?;
+    let stores = Stores::open(None, &config).await?;
+    run(&stores, config).await?;
+
+    let target = stores.get(&target_url)?;
+    assert_eq!(
+        column(&target, "index/", "file_url").await?,
+        vec![format!("{}/a.csv", source.url()), format!("{}/b/c.parquet", source.url())]
+    );
+    assert!(dir.join("index/index/_watermark.json").is_file());
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
@@ -0,0 +1 @@
+pub const MAX_DIRECTORY_SIZE: u64 = 64 * 1024 * 1024; // central directories read in one request
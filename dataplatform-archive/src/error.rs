@@ -0,0 +1,28 @@
+use std::io::Error as IoError;
+
+use thiserror::Error;
+
+/// errors reading an archive, the readers of each crate convert them with `From`
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error("zip record is truncated at: {0}")]
+    Truncated(u64),
+
+    #[error("end of central directory not found")]
+    EndOfDirectoryNotFound,
+
+    #[error("invalid zip: {0}")]
+    InvalidZip(String),
+
+    #[error("member: {0} is encrypted")]
+    Encrypted(String),
+
+    #[error("member: {0} has the unsupported compression method: {1}")]
+    UnsupportedMethod(String, u16),
+
+    #[error("member: {0} does not match its size or crc-32")]
+    Checksum(String),
+
+    #[error("Io error")]
+    IoError(#[from] IoError),
+}
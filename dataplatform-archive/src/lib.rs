@@ -0,0 +1,6 @@
+pub mod constants;
+pub mod error;
+pub mod zip;
+
+pub use error::ArchiveError;
+pub use zip::{RangeRead, ZipEntry};
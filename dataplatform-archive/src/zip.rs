@@ -0,0 +1,414 @@
+use std::future::Future;
+use std::io::Read;
+
+use crate::constants::MAX_DIRECTORY_SIZE;
+use crate::ArchiveError;
+
+use bytes::Bytes;
+use flate2::read::DeflateDecoder;
+use flate2::Crc;
+
+pub const EOCD_SIGNATURE: u32 = 0x0605_4b50;
+const EOCD_SIZE: usize = 22;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const ZIP64_LOCATOR_SIZE: usize = 20;
+const ZIP64_EOCD_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_EOCD_SIZE: u64 = 56;
+pub const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const CENTRAL_HEADER_SIZE: usize = 46;
+pub const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const LOCAL_HEADER_SIZE: u64 = 30;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+pub const METHOD_STORED: u16 = 0;
+pub const METHOD_DEFLATED: u16 = 8;
+// end of central directory with the longest comment and the zip64 locator before it
+const ZIP_TAIL_SIZE: u64 = (EOCD_SIZE + ZIP64_LOCATOR_SIZE + u16::MAX as usize) as u64;
+
+type Result<T> = std::result::Result<T, ArchiveError>;
+
+/// ranged reads of an archive, errors of the zip records are converted into the error of the reader
+pub trait RangeRead {
+    type Error: From<ArchiveError>;
+
+    /// last `len` bytes of the file, all of it if it is smaller, and the size of the file
+    fn read_tail(&self, len: u64) -> impl Future<Output = std::result::Result<(Bytes, u64), Self::Error>> + Send;
+
+    /// bytes from start to end, inclusive
+    fn read_range(&self, start: u64, end: u64) -> impl Future<Output = std::result::Result<Bytes, Self::Error>> + Send;
+}
+
+/// archive read whole into memory
+impl RangeRead for Bytes {
+    type Error = ArchiveError;
+
+    async fn read_tail(&self, len: u64) -> Result<(Bytes, u64)> {
+        let start = self.len().saturating_sub(len as usize);
+        Ok((self.slice(start..), self.len() as u64))
+    }
+
+    async fn read_range(&self, start: u64, end: u64) -> Result<Bytes> {
+        if start > end || end >= self.len() as u64 {
+            return Err(ArchiveError::Truncated(start));
+        }
+        Ok(self.slice(start as usize..=end as usize))
+    }
+}
+
+/// file of the central directory, directories are not listed
+#[derive(Debug, Clone, PartialEq)]
+pub struct ZipEntry {
+    pub path: String,
+    pub size: u64,
+    pub compressed_size: u64,
+    pub method: u16,
+    pub flags: u16,
+    pub crc32: u32,
+    /// offset of the local header, the data follows it
+    pub header_offset: u64,
+}
+
+impl ZipEntry {
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & 1 != 0
+    }
+}
+
+fn bytes_at<const N: usize>(data: &[u8], at: usize) -> Result<[u8; N]> {
+    data.get(at..at + N)
+        .and_then(|x| x.try_into().ok())
+        .ok_or(ArchiveError::Truncated(at as u64))
+}
+
+fn u16_at(data: &[u8], at: usize) -> Result<u16> {
+    Ok(u16::from_le_bytes(bytes_at(data, at)?))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Result<u32> {
+    Ok(u32::from_le_bytes(bytes_at(data, at)?))
+}
+
+fn u64_at(data: &[u8], at: usize) -> Result<u64> {
+    Ok(u64::from_le_bytes(bytes_at(data, at)?))
+}
+
+/// position of the end of central directory record in the tail of the file
+pub fn find_eocd(tail: &[u8]) -> Option<usize> {
+    (0..=tail.len().checked_sub(EOCD_SIZE)?).rev().find(|&i| {
+        let comment_len = u16_at(tail, i + 20).unwrap_or(u16::MAX) as usize;
+        u32_at(tail, i).ok() == Some(EOCD_SIGNATURE) && i + EOCD_SIZE + comment_len <= tail.len()
+    })
+}
+
+/// offset and size of the central directory, from the zip64 record if the fields overflow
+fn zip_directory(tail: &[u8], eocd: usize, zip64: Option<&[u8]>) -> Result<(u64, u64)> {
+    let entries = u16_at(tail, eocd + 10)?;
+    let size = u32_at(tail, eocd + 12)?;
+    let offset = u32_at(tail, eocd + 16)?;
+    if entries != u16::MAX && size != u32::MAX && offset != u32::MAX {
+        return Ok((offset as u64, size as u64));
+    }
+    let record = zip64.ok_or_else(|| ArchiveError::InvalidZip("zip64 end of central directory not found".into()))?;
+    if u32_at(record, 0)? != ZIP64_EOCD_SIGNATURE {
+        return Err(ArchiveError::InvalidZip(
+            "invalid zip64 end of central directory".into(),
+        ));
+    }
+    Ok((u64_at(record, 48)?, u64_at(record, 40)?))
+}
+
+/// data of the zip64 extra field, if there is one
+fn zip64_extra(extra: &[u8]) -> Result<Option<&[u8]>> {
+    let mut at = 0;
+    while at + 4 <= extra.len() {
+        let (id, len) = (u16_at(extra, at)?, u16_at(extra, at + 2)? as usize);
+        if id == ZIP64_EXTRA_ID {
+            let data = extra
+                .get(at + 4..at + 4 + len)
+                .ok_or(ArchiveError::Truncated(at as u64))?;
+            return Ok(Some(data));
+        }
+        at += 4 + len;
+    }
+    Ok(None)
+}
+
+/// files of the central directory, values over 4 GiB are read from the zip64 extra field
+pub fn parse_central_directory(data: &[u8]) -> Result<Vec<ZipEntry>> {
+    let mut entries = vec![];
+    let mut pos = 0;
+    while pos + CENTRAL_HEADER_SIZE <= data.len() && u32_at(data, pos)? == CENTRAL_HEADER_SIGNATURE {
+        let mut compressed_size = u32_at(data, pos + 20)? as u64;
+        let mut size = u32_at(data, pos + 24)? as u64;
+        let mut header_offset = u32_at(data, pos + 42)? as u64;
+        let name_len = u16_at(data, pos + 28)? as usize;
+        let extra_len = u16_at(data, pos + 30)? as usize;
+        let comment_len = u16_at(data, pos + 32)? as usize;
+        let name_start = pos + CENTRAL_HEADER_SIZE;
+        let name = data
+            .get(name_start..name_start + name_len)
+            .ok_or(ArchiveError::Truncated(pos as u64))?;
+        let extra = data
+            .get(name_start + name_len..name_start + name_len + extra_len)
+            .unwrap_or_default();
+
+        // the zip64 field holds the overflowing values only, in this order
+        if let Some(mut zip64) = zip64_extra(extra)? {
+            for value in [&mut size, &mut compressed_size, &mut header_offset] {
+                if *value == u32::MAX as u64 {
+                    *value = u64_at(zip64, 0)?;
+                    zip64 = &zip64[8..];
+                }
+            }
+        }
+        let path = String::from_utf8_lossy(name).to_string();
+        if !path.ends_with('/') {
+            entries.push(ZipEntry {
+                path,
+                size,
+                compressed_size,
+                method: u16_at(data, pos + 10)?,
+                flags: u16_at(data, pos + 8)?,
+                crc32: u32_at(data, pos + 16)?,
+                header_offset,
+            });
+        }
+        pos = name_start + name_len + extra_len + comment_len;
+    }
+    Ok(entries)
+}
+
+/// reads the end of the file and then the central directory, two or three ranged requests
+pub async fn zip_entries<R: RangeRead + Sync>(reader: &R) -> std::result::Result<Vec<ZipEntry>, R::Error> {
+    let (tail, file_size) = reader.read_tail(ZIP_TAIL_SIZE).await?;
+    let tail_start = file_size.saturating_sub(tail.len() as u64);
+    let eocd = find_eocd(&tail).ok_or(ArchiveError::EndOfDirectoryNotFound)?;
+
+    let mut zip64 = None;
+    if let Some(locator) = eocd.checked_sub(ZIP64_LOCATOR_SIZE) {
+        if u32_at(&tail, locator)? == ZIP64_LOCATOR_SIGNATURE {
+            let offset = u64_at(&tail, locator + 8)?;
+            zip64 = Some(match offset.checked_sub(tail_start) {
+                Some(at) => {
+                    let at = at.min(tail.len() as u64);
+                    tail.slice(at as usize..(at + ZIP64_EOCD_SIZE).min(tail.len() as u64) as usize)
+                }
+                None => reader.read_range(offset, offset + ZIP64_EOCD_SIZE - 1).await?,
+            });
+        }
+    }
+    let (offset, size) = zip_directory(&tail, eocd, zip64.as_deref())?;
+    if size > MAX_DIRECTORY_SIZE || offset + size > file_size {
+        return Err(ArchiveError::InvalidZip(format!("central directory has an invalid size: {size}")).into());
+    }
+    if size == 0 {
+        return Ok(vec![]);
+    }
+    let directory = match offset.checked_sub(tail_start) {
+        Some(at) => tail.slice(at as usize..(at + size) as usize),
+        None => reader.read_range(offset, offset + size - 1).await?,
+    };
+    Ok(parse_central_directory(&directory)?)
+}
+
+/// data of the entry, the local header is read for the length of its extra field
+/// and then the compressed data, two ranged requests
+pub async fn read_zip_entry<R: RangeRead + Sync>(
+    reader: &R,
+    entry: &ZipEntry,
+) -> std::result::Result<Vec<u8>, R::Error> {
+    if entry.is_encrypted() {
+        return Err(ArchiveError::Encrypted(entry.path.clone()).into());
+    }
+    let offset = entry.header_offset;
+    let header = reader.read_range(offset, offset + LOCAL_HEADER_SIZE - 1).await?;
+    if u32_at(&header, 0)? != LOCAL_HEADER_SIGNATURE {
+        return Err(ArchiveError::InvalidZip(format!("no local header of: {} at: {offset}", entry.path)).into());
+    }
+    let start = offset + LOCAL_HEADER_SIZE + u16_at(&header, 26)? as u64 + u16_at(&header, 28)? as u64;
+    let data = match entry.compressed_size {
+        0 => Bytes::new(),
+        n => reader.read_range(start, start + n - 1).await?,
+    };
+    Ok(decompress(entry, &data)?)
+}
+
+/// stored or deflated data, checked against the size and crc-32 of the entry
+fn decompress(entry: &ZipEntry, data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    match entry.method {
+        METHOD_STORED => out.extend_from_slice(data),
+        // a bigger output than the size of the entry fails the checks below
+        METHOD_DEFLATED => {
+            DeflateDecoder::new(data).take(entry.size + 1).read_to_end(&mut out)?;
+        }
+        method => return Err(ArchiveError::UnsupportedMethod(entry.path.clone(), method)),
+    }
+    let mut crc = Crc::new();
+    crc.update(&out);
+    if out.len() as u64 != entry.size || crc.sum() != entry.crc32 {
+        return Err(ArchiveError::Checksum(entry.path.clone()));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Write;
+
+    use flate2::write::DeflateEncoder;
+    use rstest::rstest;
+
+    /// zip with the given files stored or deflated, and a comment after the end of central directory
+    fn zip_file(files: &[(&str, &[u8], u16)], comment: &[u8]) -> Vec<u8> {
+        let mut data = vec![];
+        let mut directory = vec![];
+        for (name, body, method) in files {
+            let compressed = match *method {
+                METHOD_DEFLATED => {
+                    let mut encoder = DeflateEncoder::new(vec![], flate2::Compression::default());
+                    encoder.write_all(body).expect("deflate in memory");
+                    encoder.finish().expect("deflate in memory")
+                }
+                _ => body.to_vec(),
+            };
+            let mut crc = Crc::new();
+            crc.update(body);
+            let offset = data.len() as u32;
+            let header = |signature: u32, central: bool| {
+                let mut out = signature.to_le_bytes().to_vec();
+                if central {
+                    out.extend(20u16.to_le_bytes());
+                }
+                out.extend([20, 0, 0, 0]);
+                out.extend(method.to_le_bytes());
+                out.extend([0; 4]);
+                out.extend(crc.sum().to_le_bytes());
+                out.extend((compressed.len() as u32).to_le_bytes());
+                out.extend((body.len() as u32).to_le_bytes());
+                out.extend((name.len() as u16).to_le_bytes());
+                out.extend(0u16.to_le_bytes());
+                if central {
+                    out.extend([0; 10]);
+                    out.extend(offset.to_le_bytes());
+                }
+                out.extend(name.as_bytes());
+                out
+            };
+            directory.extend(header(CENTRAL_HEADER_SIGNATURE, true));
+            data.extend(header(LOCAL_HEADER_SIGNATURE, false));
+            data.extend(compressed);
+        }
+        let offset = data.len() as u32;
+        data.extend(&directory);
+        data.extend(EOCD_SIGNATURE.to_le_bytes());
+        data.extend([0; 4]);
+        data.extend((files.len() as u16).to_le_bytes());
+        data.extend((files.len() as u16).to_le_bytes());
+        data.extend((directory.len() as u32).to_le_bytes());
+        data.extend(offset.to_le_bytes());
+        data.extend((comment.len() as u16).to_le_bytes());
+        data.extend(comment);
+        data
+    }
+
+    #[rstest]
+    #[case(b"")]
+    #[case(b"archived by the lab")]
+    #[tokio::test]
+    async fn test_read_zip_entries(#[case] comment: &[u8]) -> Result<()> {
+        let body = "id,name\n".repeat(100);
+        let files: &[(&str, &[u8], u16)] = &[
+            ("scans/a.dcm", b"dicom", METHOD_STORED),
+            ("scans/", b"", METHOD_STORED),
+            ("b.csv", body.as_bytes(), METHOD_DEFLATED),
+            ("empty.txt", b"", METHOD_DEFLATED),
+        ];
+        let data = Bytes::from(zip_file(files, comment));
+        let entries = zip_entries(&data).await?;
+        let paths = entries.iter().map(|x| (x.path.as_str(), x.size)).collect::<Vec<_>>();
+        assert_eq!(paths, vec![("scans/a.dcm", 5), ("b.csv", 800), ("empty.txt", 0)]);
+        assert!(entries[1].compressed_size < entries[1].size);
+        for (entry, expected) in entries.iter().zip([b"dicom".as_slice(), body.as_bytes(), b""]) {
+            assert_eq!(read_zip_entry(&data, entry).await?, expected, "{}", entry.path);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_zip_entry_errors() -> Result<()> {
+        let mut data = zip_file(&[("a.csv", b"id\n1\n", METHOD_STORED)], b"");
+        let entry = zip_entries(&Bytes::from(data.clone())).await?.remove(0);
+        data[LOCAL_HEADER_SIZE as usize + 5] = b'2';
+        let data = Bytes::from(data);
+        assert!(matches!(
+            read_zip_entry(&data, &entry).await,
+            Err(ArchiveError::Checksum(_))
+        ));
+
+        let encrypted = ZipEntry {
+            flags: 1,
+            ..entry.clone()
+        };
+        assert!(matches!(
+            read_zip_entry(&data, &encrypted).await,
+            Err(ArchiveError::Encrypted(_))
+        ));
+        let bzip2 = ZipEntry {
+            method: 12,
+            ..entry.clone()
+        };
+        assert!(matches!(
+            read_zip_entry(&data, &bzip2).await,
+            Err(ArchiveError::UnsupportedMethod(_, 12))
+        ));
+        let moved = ZipEntry {
+            header_offset: 1,
+            ..entry
+        };
+        assert!(matches!(
+            read_zip_entry(&data, &moved).await,
+            Err(ArchiveError::InvalidZip(_))
+        ));
+
+        assert!(matches!(
+            zip_entries(&Bytes::from_static(b"not a zip")).await,
+            Err(ArchiveError::EndOfDirectoryNotFound)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_zip64_extra() -> Result<()> {
+        let mut header = CENTRAL_HEADER_SIGNATURE.to_le_bytes().to_vec();
+        header.extend([20, 0, 20, 0, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        // compressed size and local header offset overflow, the size does not
+        header.extend(u32::MAX.to_le_bytes());
+        header.extend(7u32.to_le_bytes());
+        header.extend(5u16.to_le_bytes());
+        header.extend(20u16.to_le_bytes());
+        header.extend([0; 10]);
+        header.extend(u32::MAX.to_le_bytes());
+        header.extend(b"a.bin");
+        header.extend(ZIP64_EXTRA_ID.to_le_bytes());
+        header.extend(16u16.to_le_bytes());
+        header.extend((5u64 << 32).to_le_bytes());
+        header.extend((6u64 << 32).to_le_bytes());
+
+        let entries = parse_central_directory(&header)?;
+        assert_eq!(
+            entries,
+            vec![ZipEntry {
+                path: "a.bin".to_string(),
+                size: 7,
+                compressed_size: 5 << 32,
+                method: METHOD_DEFLATED,
+                flags: 0,
+                crc32: 0,
+                header_offset: 6 << 32,
+            }]
+        );
+        Ok(())
+    }
+}
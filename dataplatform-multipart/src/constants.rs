@@ -0,0 +1,3 @@
+pub const PART_SIZE: usize = 8 * 1024 * 1024; // 8 MiB
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024; // s3 minimum for all parts except the last
+pub const UPLOAD_PARTS_WORKERS: usize = 4; // max parts uploaded concurrently
@@ -0,0 +1,29 @@
+use aws_sdk_s3::error::SdkError;
+use aws_sdk_s3::operation::abort_multipart_upload::AbortMultipartUploadError;
+use aws_sdk_s3::operation::complete_multipart_upload::CompleteMultipartUploadError;
+use aws_sdk_s3::operation::create_multipart_upload::CreateMultipartUploadError;
+use aws_sdk_s3::operation::upload_part::UploadPartError;
+use thiserror::Error;
+use tokio::task::JoinError;
+
+/// errors of a multipart upload, the crates using the writer convert them with `From`
+#[derive(Debug, Error)]
+pub enum MultipartError {
+    #[error("AWS CreateMultipartUploadError error")]
+    CreateMultipartUploadError(#[from] SdkError<CreateMultipartUploadError>),
+
+    #[error("AWS UploadPartError error")]
+    UploadPartError(#[from] SdkError<UploadPartError>),
+
+    #[error("AWS CompleteMultipartUploadError error")]
+    CompleteMultipartUploadError(#[from] SdkError<CompleteMultipartUploadError>),
+
+    #[error("AWS AbortMultipartUploadError error")]
+    AbortMultipartUploadError(#[from] SdkError<AbortMultipartUploadError>),
+
+    #[error("Tokio error")]
+    TokioError(#[from] JoinError),
+
+    #[error("Unexpected error: {0}")]
+    UnexpectedError(String),
+}
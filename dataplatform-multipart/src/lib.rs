@@ -0,0 +1,6 @@
+pub mod constants;
+pub mod error;
+pub mod writer;
+
+pub use error::MultipartError;
+pub use writer::MultipartWriter;
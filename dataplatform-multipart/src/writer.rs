@@ -0,0 +1,356 @@
+use crate::constants::{MIN_PART_SIZE, UPLOAD_PARTS_WORKERS};
+use crate::MultipartError;
+
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
+use aws_sdk_s3::Client;
+use bytes::Bytes;
+use tokio::task::JoinSet;
+
+type Result<T> = std::result::Result<T, MultipartError>;
+
+/// streaming multipart upload to s3, keeps at most one part buffered
+/// and `UPLOAD_PARTS_WORKERS` parts uploading at the same time,
+/// the upload is aborted if any request fails
+pub struct MultipartWriter {
+    client: Client,
+    bucket: String,
+    key: String,
+    part_size: usize,
+    content_type: Option<String>,
+    content_disposition: Option<String>,
+    upload_id: Option<String>,
+    buffer: Vec<u8>,
+    part_number: i32,
+    in_flight: JoinSet<Result<CompletedPart>>,
+    parts: Vec<CompletedPart>,
+    finished: bool,
+}
+
+impl MultipartWriter {
+    pub fn new(client: Client, bucket: &str, key: &str, part_size: usize) -> Self {
+        let part_size = part_size.max(MIN_PART_SIZE);
+        Self {
+            client,
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            part_size,
+            content_type: None,
+            content_disposition: None,
+            upload_id: None,
+            buffer: Vec::new(),
+            part_number: 0,
+            in_flight: JoinSet::new(),
+            parts: Vec::new(),
+            finished: false,
+        }
+    }
+
+    pub fn with_content_type(mut self, content_type: &str) -> Self {
+        self.content_type = Some(content_type.to_string());
+        self
+    }
+
+    pub fn with_content_disposition(mut self, content_disposition: &str) -> Self {
+        self.content_disposition = Some(content_disposition.to_string());
+        self
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> Result<()> {
+        if self.finished {
+            return Err(MultipartError::UnexpectedError(format!(
+                "multipart upload for {} is already finished",
+                self.key
+            )));
+        }
+        let res = self.try_write(data).await;
+        if res.is_err() {
+            if let Err(e) = self.abort().await {
+                tracing::error!("failed to abort multipart upload: {e:?}");
+            }
+        }
+        res
+    }
+
+    pub async fn finish(&mut self) -> Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        let res = self.try_finish().await;
+        if res.is_err() {
+            if let Err(e) = self.abort().await {
+                tracing::error!("failed to abort multipart upload: {e:?}");
+            }
+        }
+        res
+    }
+
+    pub async fn abort(&mut self) -> Result<()> {
+        self.finished = true;
+        self.in_flight.shutdown().await;
+        self.buffer = Vec::new();
+        if let Some(upload_id) = self.upload_id.take() {
+            tracing::warn!("aborting multipart upload for: {}", self.key);
+            self.client
+                .abort_multipart_upload()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .upload_id(upload_id)
+                .send()
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn try_write(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let n = data.len().min(self.part_size - self.buffer.len());
+            self.buffer.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.buffer.len() == self.part_size {
+                self.upload_buffer().await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn try_finish(&mut self) -> Result<()> {
+        // s3 requires at least one part, the last part may be smaller than MIN_PART_SIZE
+        if !self.buffer.is_empty() || self.part_number == 0 {
+            self.upload_buffer().await?;
+        }
+        while let Some(part) = self.in_flight.join_next().await {
+            self.parts.push(part??);
+        }
+        self.parts.sort_by_key(|x| x.part_number());
+
+        let upload_id = self.upload_id.take().unwrap_or_default();
+        let completed_multipart_upload = CompletedMultipartUpload::builder()
+            .set_parts(Some(std::mem::take(&mut self.parts)))
+            .build();
+        let res = self
+            .client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .multipart_upload(completed_multipart_upload)
+            .upload_id(&upload_id)
+            .send()
+            .await;
+        if let Err(e) = res {
+            self.upload_id = Some(upload_id);
+            return Err(e.into());
+        }
+        self.finished = true;
+        Ok(())
+    }
+
+    async fn upload_id(&mut self) -> Result<String> {
+        if let Some(upload_id) = &self.upload_id {
+            return Ok(upload_id.clone());
+        }
+        let resp = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .set_content_type(self.content_type.clone())
+            .set_content_disposition(self.content_disposition.clone())
+            .send()
+            .await?;
+        let upload_id = resp
+            .upload_id()
+            .ok_or_else(|| MultipartError::UnexpectedError(format!("no upload id returned for: {}", self.key)))?
+            .to_string();
+        self.upload_id = Some(upload_id.clone());
+        Ok(upload_id)
+    }
+
+    async fn upload_buffer(&mut self) -> Result<()> {
+        let upload_id = self.upload_id().await?;
+        while self.in_flight.len() >= UPLOAD_PARTS_WORKERS {
+            if let Some(part) = self.in_flight.join_next().await {
+                self.parts.push(part??);
+            }
+        }
+
+        self.part_number += 1;
+        let part_number = self.part_number;
+        let body = Bytes::from(std::mem::take(&mut self.buffer));
+        let client = self.client.clone();
+        let bucket = self.bucket.clone();
+        let key = self.key.clone();
+        self.in_flight.spawn(async move {
+            let resp = client
+                .upload_part()
+                .bucket(bucket)
+                .key(key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .body(ByteStream::from(body))
+                .send()
+                .await?;
+            Ok::<_, MultipartError>(
+                CompletedPart::builder()
+                    .set_e_tag(resp.e_tag)
+                    .part_number(part_number)
+                    .build(),
+            )
+        });
+        Ok(())
+    }
+}
+
+/// parquet sink of the parquet version used by the crate, `AsyncArrowWriter` writes the row groups as parts
+#[cfg(any(feature = "parquet53", feature = "parquet54"))]
+macro_rules! impl_async_file_writer {
+    ($parquet:ident) => {
+        impl $parquet::arrow::async_writer::AsyncFileWriter for MultipartWriter {
+            fn write(&mut self, bs: Bytes) -> futures::future::BoxFuture<'_, $parquet::errors::Result<()>> {
+                Box::pin(async move {
+                    MultipartWriter::write(self, &bs)
+                        .await
+                        .map_err(|e| $parquet::errors::ParquetError::External(e.into()))
+                })
+            }
+
+            fn complete(&mut self) -> futures::future::BoxFuture<'_, $parquet::errors::Result<()>> {
+                Box::pin(async move {
+                    self.finish()
+                        .await
+                        .map_err(|e| $parquet::errors::ParquetError::External(e.into()))
+                })
+            }
+        }
+    };
+}
+
+#[cfg(feature = "parquet53")]
+impl_async_file_writer!(parquet53);
+#[cfg(feature = "parquet54")]
+impl_async_file_writer!(parquet54);
+
+impl Drop for MultipartWriter {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        let Some(upload_id) = self.upload_id.take() else {
+            return;
+        };
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        // writer dropped without finish, don't leave the parts billed in the bucket
+        let request = self
+            .client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .upload_id(upload_id);
+        handle.spawn(async move {
+            if let Err(e) = request.send().await {
+                tracing::error!("failed to abort multipart upload: {e:?}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use aws_sdk_s3::operation::abort_multipart_upload::AbortMultipartUploadOutput;
+    use aws_sdk_s3::operation::complete_multipart_upload::CompleteMultipartUploadOutput;
+    use aws_sdk_s3::operation::create_multipart_upload::CreateMultipartUploadOutput;
+    use aws_sdk_s3::operation::upload_part::{UploadPartError, UploadPartOutput};
+    use aws_smithy_mocks::{mock, mock_client, Rule, RuleMode};
+    use rstest::rstest;
+
+    /// rules of a multipart upload, sizes of the uploaded parts by part number
+    struct Mocks {
+        create: Rule,
+        upload: Rule,
+        complete: Rule,
+        abort: Rule,
+        parts: Arc<Mutex<Vec<(i32, usize)>>>,
+    }
+
+    impl Mocks {
+        fn new(fail_parts: bool) -> Self {
+            let parts = Arc::new(Mutex::new(vec![]));
+            let uploaded = parts.clone();
+            let upload = if fail_parts {
+                mock!(Client::upload_part).then_error(|| UploadPartError::unhandled("part failed"))
+            } else {
+                mock!(Client::upload_part).then_compute_output(move |req| {
+                    let size = req.body().bytes().map(|x| x.len()).unwrap_or_default();
+                    uploaded.lock().unwrap().push((req.part_number().unwrap_or_default(), size));
+                    UploadPartOutput::builder().e_tag("etag").build()
+                })
+            };
+            Self {
+                create: mock!(Client::create_multipart_upload)
+                    .then_output(|| CreateMultipartUploadOutput::builder().upload_id("upload").build()),
+                upload,
+                complete: mock!(Client::complete_multipart_upload)
+                    .then_output(|| CompleteMultipartUploadOutput::builder().build()),
+                abort: mock!(Client::abort_multipart_upload)
+                    .then_output(|| AbortMultipartUploadOutput::builder().build()),
+                parts,
+            }
+        }
+
+        fn client(&self) -> Client {
+            mock_client!(
+                aws_sdk_s3,
+                RuleMode::MatchAny,
+                [&self.create, &self.upload, &self.complete, &self.abort]
+            )
+        }
+
+        fn part_sizes(&self) -> Vec<usize> {
+            let mut parts = self.parts.lock().unwrap().clone();
+            parts.sort();
+            parts.into_iter().map(|(_, size)| size).collect()
+        }
+    }
+
+    #[rstest]
+    #[case(vec![MIN_PART_SIZE], vec![MIN_PART_SIZE])]
+    #[case(vec![MIN_PART_SIZE + 1], vec![MIN_PART_SIZE, 1])]
+    #[case(vec![MIN_PART_SIZE - 1, 2, MIN_PART_SIZE - 1], vec![MIN_PART_SIZE, MIN_PART_SIZE])]
+    #[case(vec![], vec![0])]
+    #[tokio::test]
+    async fn test_part_boundaries(#[case] writes: Vec<usize>, #[case] expected: Vec<usize>) -> Result<()> {
+        let mocks = Mocks::new(false);
+        // smaller part sizes are raised to the s3 minimum
+        let mut writer = MultipartWriter::new(mocks.client(), "bucket", "a.parquet", 1024);
+        for size in writes {
+            writer.write(&vec![0; size]).await?;
+        }
+        writer.finish().await?;
+        assert_eq!(mocks.part_sizes(), expected);
+        assert_eq!((mocks.create.num_calls(), mocks.complete.num_calls()), (1, 1));
+        assert_eq!(mocks.abort.num_calls(), 0);
+        assert!(writer.write(b"1").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_abort_on_error() {
+        let mocks = Mocks::new(true);
+        let mut writer = MultipartWriter::new(mocks.client(), "bucket", "a.parquet", MIN_PART_SIZE);
+        // parts are uploaded in the background, the failure is seen when they are joined
+        writer.write(&vec![0; 2 * MIN_PART_SIZE]).await.expect("parts uploading");
+        assert!(writer.finish().await.is_err());
+        assert_eq!(mocks.abort.num_calls(), 1);
+        assert_eq!(mocks.complete.num_calls(), 0);
+        // finished by the abort, nothing is left to abort on drop
+        assert!(writer.write(b"1").await.is_err());
+        drop(writer);
+        assert_eq!(mocks.abort.num_calls(), 1);
+    }
+}
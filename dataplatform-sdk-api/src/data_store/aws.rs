@@ -1,14 +1,26 @@
 use awscreds::Credentials;
-use datafusion::arrow::array::{Array, Int64Array, StringViewArray};
+use chrono::{DateTime, SecondsFormat};
+use datafusion::arrow::array::{Array, AsArray, BooleanArray, Int32Array, Int64Array, MapArray, StringViewArray, TimestampMicrosecondArray};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::{DataType, TimeUnit};
+use datafusion::logical_expr::cast as cast_expr;
+use datafusion::parquet::arrow::async_reader::{AsyncFileReader, ParquetObjectReader};
+use datafusion::parquet::format::KeyValue;
 use datafusion::prelude::*;
+use futures::TryStreamExt;
 use object_store::aws::AmazonS3Builder;
+use object_store::path::Path;
+use object_store::{ObjectMeta, ObjectStore};
 use serde::{Deserialize, Serialize};
 use tokio_stream::StreamExt;
 
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use url::Url;
 
+use super::delta::{delta_files, register_delta};
 use super::error::DataStoreError;
+use crate::utils::constants::{GENERATION_FILE, HIVE_DEFAULT_PARTITION, SCHEMA_VERSION, SCHEMA_VERSION_KEY};
 use crate::utils::datafusion::is_empty;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -19,6 +31,55 @@ pub struct Table {
     pub file_path: Option<String>,
     pub file_url: Option<String>,
     pub dt: Option<String>,
+    pub etag: Option<String>,
+    pub storage_class: Option<String>,
+    pub owner: Option<String>,
+    pub checksum_algorithm: Option<String>,
+    pub checksum_value: Option<String>,
+    pub content_sha256: Option<String>,
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+    pub source_bucket: Option<String>,
+    pub dataset: Option<String>,
+    pub deleted_at: Option<String>,
+    /// rfc 3339, dt is kept for existing queries
+    pub last_modified: Option<String>,
+    pub year: Option<i32>,
+    pub month: Option<i32>,
+    pub day: Option<i32>,
+    /// set when data-indexer runs with enrich
+    pub tags: Option<BTreeMap<String, String>>,
+    pub user_metadata: Option<BTreeMap<String, String>>,
+    /// set when data-indexer runs with versions, the worker downloads this version
+    pub version_id: Option<String>,
+    pub is_latest: Option<bool>,
+    pub is_delete_marker: Option<bool>,
+    /// set on rows of files inside archives, file_path is the archive the worker extracts them from
+    pub archive_path: Option<String>,
+    pub member_path: Option<String>,
+    pub member_size: Option<i64>,
+}
+
+/// microseconds since the epoch as rfc 3339 in utc, fractional seconds only if set
+fn rfc3339(micros: i64) -> Option<String> {
+    DateTime::from_timestamp_micros(micros).map(|x| x.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+}
+
+/// entries of a row of a string to string map column, none if the row is null
+fn map_value(col: &MapArray, i: usize) -> Option<BTreeMap<String, String>> {
+    if col.is_null(i) {
+        return None;
+    }
+    let entries = col.value(i);
+    let keys = cast(entries.column(0), &DataType::Utf8).ok()?;
+    let values = cast(entries.column(1), &DataType::Utf8).ok()?;
+    let (keys, values) = (keys.as_string::<i32>(), values.as_string::<i32>());
+    Some(
+        (0..keys.len())
+            .filter(|j| values.is_valid(*j))
+            .map(|j| (keys.value(j).to_string(), values.value(j).to_string()))
+            .collect(),
+    )
 }
 
 impl Table {
@@ -31,20 +92,51 @@ impl Table {
             let schema = batch.schema();
             let columns = schema.fields().iter().map(|f| f.name().clone()).collect::<Vec<_>>();
 
-            let get_string_col = |name: &str| -> Option<&StringViewArray> {
+            // string columns are read as Utf8 or Utf8View depending on the source
+            let get_string_col = |name: &str| -> Option<StringViewArray> {
                 columns
                     .iter()
                     .position(|n| n == name)
-                    .map(|i| batch.column(i).as_any().downcast_ref::<StringViewArray>())
-                    .flatten()
+                    .and_then(|i| cast(batch.column(i), &DataType::Utf8View).ok())
+                    .map(|col| col.as_string_view().clone())
             };
 
             let get_int_col = |name: &str| -> Option<&Int64Array> {
                 columns
                     .iter()
                     .position(|n| n == name)
-                    .map(|i| batch.column(i).as_any().downcast_ref::<Int64Array>())
-                    .flatten()
+                    .and_then(|i| batch.column(i).as_any().downcast_ref::<Int64Array>())
+            };
+
+            // year, month and day are Int32 both as file and as partition columns
+            let get_i32_col = |name: &str| -> Option<Int32Array> {
+                columns
+                    .iter()
+                    .position(|n| n == name)
+                    .and_then(|i| cast(batch.column(i), &DataType::Int32).ok())
+                    .map(|col| col.as_primitive().clone())
+            };
+
+            let get_timestamp_col = |name: &str| -> Option<TimestampMicrosecondArray> {
+                columns
+                    .iter()
+                    .position(|n| n == name)
+                    .and_then(|i| cast(batch.column(i), &DataType::Timestamp(TimeUnit::Microsecond, None)).ok())
+                    .map(|col| col.as_primitive().clone())
+            };
+
+            let get_bool_col = |name: &str| -> Option<&BooleanArray> {
+                columns
+                    .iter()
+                    .position(|n| n == name)
+                    .and_then(|i| batch.column(i).as_boolean_opt())
+            };
+
+            let get_map_col = |name: &str| -> Option<&MapArray> {
+                columns
+                    .iter()
+                    .position(|n| n == name)
+                    .and_then(|i| batch.column(i).as_map_opt())
             };
 
             let file_names = get_string_col("file_name");
@@ -53,15 +145,61 @@ impl Table {
             let file_paths = get_string_col("file_path");
             let file_urls = get_string_col("file_url");
             let dts = get_string_col("dt");
+            let etags = get_string_col("etag");
+            let storage_classes = get_string_col("storage_class");
+            let owners = get_string_col("owner");
+            let checksum_algorithms = get_string_col("checksum_algorithm");
+            let checksum_values = get_string_col("checksum_value");
+            let content_sha256s = get_string_col("content_sha256");
+            let content_types = get_string_col("content_type");
+            let content_encodings = get_string_col("content_encoding");
+            let source_buckets = get_string_col("source_bucket");
+            let datasets = get_string_col("dataset");
+            let deleted_ats = get_string_col("deleted_at");
+            let last_modifieds = get_timestamp_col("last_modified");
+            let years = get_i32_col("year");
+            let months = get_i32_col("month");
+            let days = get_i32_col("day");
+            let tags = get_map_col("tags");
+            let user_metadatas = get_map_col("user_metadata");
+            let version_ids = get_string_col("version_id");
+            let is_latests = get_bool_col("is_latest");
+            let is_delete_markers = get_bool_col("is_delete_marker");
+            let archive_paths = get_string_col("archive_path");
+            let member_paths = get_string_col("member_path");
+            let member_sizes = get_int_col("member_size");
 
             for i in 0..batch.num_rows() {
                 records.push(Self {
-                    file_name: file_names.and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
-                    file_type: file_types.and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    file_name: file_names.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    file_type: file_types.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
                     file_size: file_sizes.and_then(|col| if col.is_null(i) { None } else { Some(col.value(i)) }),
-                    file_path: file_paths.and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
-                    file_url: file_urls.and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
-                    dt: dts.and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    file_path: file_paths.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    file_url: file_urls.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    dt: dts.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    etag: etags.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    storage_class: storage_classes.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    owner: owners.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    checksum_algorithm: checksum_algorithms.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    checksum_value: checksum_values.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    content_sha256: content_sha256s.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    content_type: content_types.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    content_encoding: content_encodings.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    source_bucket: source_buckets.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    dataset: datasets.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    deleted_at: deleted_ats.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    last_modified: last_modifieds.as_ref().and_then(|col| if col.is_null(i) { None } else { rfc3339(col.value(i)) }),
+                    year: years.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i)) }),
+                    month: months.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i)) }),
+                    day: days.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i)) }),
+                    tags: tags.and_then(|col| map_value(col, i)),
+                    user_metadata: user_metadatas.and_then(|col| map_value(col, i)),
+                    version_id: version_ids.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    is_latest: is_latests.and_then(|col| if col.is_null(i) { None } else { Some(col.value(i)) }),
+                    is_delete_marker: is_delete_markers.and_then(|col| if col.is_null(i) { None } else { Some(col.value(i)) }),
+                    archive_path: archive_paths.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    member_path: member_paths.as_ref().and_then(|col| if col.is_null(i) { None } else { Some(col.value(i).to_string()) }),
+                    member_size: member_sizes.and_then(|col| if col.is_null(i) { None } else { Some(col.value(i)) }),
                 });
             }
         }
@@ -86,6 +224,14 @@ impl Table {
         }
         Ok(Some(df))
     }
+
+    /// drops rows of objects deleted from the source bucket
+    pub fn exclude_deleted(df: DataFrame) -> Result<DataFrame, DataStoreError> {
+        if df.schema().field_with_unqualified_name("deleted_at").is_err() {
+            return Ok(df);
+        }
+        Ok(df.filter(col("deleted_at").is_null())?)
+    }
 }
 
 pub async fn init_table_ctx(
@@ -94,6 +240,7 @@ pub async fn init_table_ctx(
     bucket: &str,
     key: &str,
     table_name: &str,
+    partition_cols: &[String],
 ) -> Result<(), DataStoreError> {
     let creds = Credentials::default()?;
     let aws_access_key_id = creds.access_key.unwrap_or_default();
@@ -108,13 +255,232 @@ pub async fn init_table_ctx(
         .with_token(aws_session_token)
         .build()
         .map_err(|e| DataStoreError::UnexpectedError(e.into()))?;
-
+    let s3: Arc<dyn ObjectStore> = Arc::new(s3);
     let path = format!("s3://{bucket}");
     let s3_url = Url::parse(&path)?;
     ctx.runtime_env()
-        .register_object_store(&s3_url, Arc::new(s3));
+        .register_object_store(&s3_url, s3.clone());
+
+    // written by data-indexer with output delta, not partitioned
+    if let Some(files) = delta_files(s3.as_ref(), key).await? {
+        check_schema_versions(s3.clone(), key, files.clone()).await?;
+        return register_delta(ctx, s3, bucket, table_name, &files).await;
+    }
+
+    let key = current_generation(s3.as_ref(), key).await?;
+    let files = parquet_files(s3.as_ref(), &key).await?;
+    check_schema_versions(s3.clone(), &key, files).await?;
     let path = format!("s3://{bucket}/{key}");
-    ctx.register_parquet(table_name, &path, ParquetReadOptions::default())
-        .await?;
+    register_partitioned(ctx, table_name, &path, partition_cols).await
+}
+
+/// type of a partition column, as the column of the index files it replaces
+fn partition_type(col: &str) -> DataType {
+    match col {
+        "year" | "month" | "day" => DataType::Int32,
+        _ => DataType::Utf8,
+    }
+}
+
+/// registers the parquet files under the path as a view of the table, hive partitions
+/// written by data-indexer, e.g. year=2021/month=03/file_type=csv/, are typed columns and
+/// `__HIVE_DEFAULT_PARTITION__` is read as null. Partition values are listed as Utf8,
+/// filters on the cast columns still prune the partitions
+pub async fn register_partitioned(
+    ctx: &SessionContext,
+    table_name: &str,
+    path: &str,
+    partition_cols: &[String],
+) -> Result<(), DataStoreError> {
+    let listed = partition_cols
+        .iter()
+        .map(|col| (col.to_string(), DataType::Utf8))
+        .collect::<Vec<_>>();
+    let options = ParquetReadOptions::default().table_partition_cols(listed);
+    let df = ctx.read_parquet(path, options).await?;
+    if partition_cols.is_empty() {
+        ctx.register_table(table_name, df.into_view())?;
+        return Ok(());
+    }
+    let exprs = df
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| {
+            let name = field.name();
+            if !partition_cols.contains(name) {
+                return col(name.as_str());
+            }
+            cast_expr(nullif(col(name.as_str()), lit(HIVE_DEFAULT_PARTITION)), partition_type(name)).alias(name.as_str())
+        })
+        .collect::<Vec<_>>();
+    ctx.register_table(table_name, df.select(exprs)?.into_view())?;
     Ok(())
 }
+
+#[derive(Debug, Deserialize)]
+struct Generation {
+    generation: String,
+}
+
+/// compacted index files are written by data-indexer into a new generation
+/// and switched by replacing `_current.json`, prefix without it is read as is
+async fn current_generation(store: &dyn ObjectStore, key: &str) -> Result<String, DataStoreError> {
+    let pointer = Path::from(format!("{key}{GENERATION_FILE}"));
+    let data = match store.get(&pointer).await {
+        Ok(res) => res.bytes().await,
+        Err(object_store::Error::NotFound { .. }) => return Ok(key.to_string()),
+        Err(e) => Err(e),
+    }
+    .map_err(|e| DataStoreError::UnexpectedError(e.into()))?;
+    let generation: Generation = serde_json::from_slice(&data)
+        .map_err(|e| DataStoreError::UnexpectedError(e.into()))?;
+    tracing::info!("reading generation: {} of: {}", generation.generation, key);
+    Ok(format!("{key}{}/", generation.generation))
+}
+
+/// files written before data-indexer stored the version are version 0,
+/// a value that can't be parsed is an unknown version
+fn schema_version(metadata: Option<&Vec<KeyValue>>) -> u32 {
+    match metadata.and_then(|kv| kv.iter().find(|x| x.key == SCHEMA_VERSION_KEY)) {
+        Some(kv) => kv.value.as_deref().and_then(|x| x.parse().ok()).unwrap_or(u32::MAX),
+        None => 0,
+    }
+}
+
+async fn parquet_files(store: &dyn ObjectStore, key: &str) -> Result<Vec<ObjectMeta>, DataStoreError> {
+    let prefix = Path::from(key);
+    store
+        .list(Some(&prefix))
+        .try_filter(|meta| futures::future::ready(meta.location.as_ref().ends_with(".parquet")))
+        .try_collect::<Vec<_>>()
+        .await
+        .map_err(|e| DataStoreError::UnexpectedError(e.into()))
+}
+
+/// refuses files written by a newer data-indexer, their columns may have changed meaning;
+/// older files are read with the columns added since as nulls
+async fn check_schema_versions(
+    store: Arc<dyn ObjectStore>,
+    key: &str,
+    files: Vec<ObjectMeta>,
+) -> Result<(), DataStoreError> {
+    let mut outdated = 0;
+    for meta in files {
+        let location = meta.location.to_string();
+        let mut reader = ParquetObjectReader::new(store.clone(), meta);
+        let metadata = reader
+            .get_metadata()
+            .await
+            .map_err(|e| DataStoreError::UnexpectedError(e.into()))?;
+        let version = schema_version(metadata.file_metadata().key_value_metadata());
+        if version > SCHEMA_VERSION {
+            return Err(DataStoreError::SchemaVersionError { key: location, version });
+        }
+        if version < SCHEMA_VERSION {
+            outdated += 1;
+        }
+    }
+    if outdated > 0 {
+        tracing::warn!("index files of an older schema version: {} in: {}, run data-indexer migrate", outdated, key);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(1_614_834_367_000_000, "2021-03-04T05:06:07Z")]
+    #[case(1_614_834_367_250_000, "2021-03-04T05:06:07.250Z")]
+    fn test_rfc3339(#[case] micros: i64, #[case] expected: &str) {
+        assert_eq!(rfc3339(micros).as_deref(), Some(expected));
+    }
+
+    #[test]
+    fn test_map_value() -> Result<(), datafusion::arrow::error::ArrowError> {
+        use datafusion::arrow::array::{MapBuilder, StringBuilder};
+
+        let mut builder = MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+        builder.keys().append_value("team");
+        builder.values().append_value("mri");
+        builder.append(true)?;
+        builder.append(false)?;
+        let col = builder.finish();
+        assert_eq!(map_value(&col, 0), Some(BTreeMap::from([("team".to_string(), "mri".to_string())])));
+        assert_eq!(map_value(&col, 1), None);
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(None, 0)]
+    #[case(Some(vec![KeyValue::new("ARROW:schema".to_string(), "x".to_string())]), 0)]
+    #[case(Some(vec![KeyValue::new(SCHEMA_VERSION_KEY.to_string(), "1".to_string())]), 1)]
+    #[case(Some(vec![KeyValue::new(SCHEMA_VERSION_KEY.to_string(), "v2".to_string())]), u32::MAX)]
+    fn test_schema_version(#[case] metadata: Option<Vec<KeyValue>>, #[case] expected: u32) {
+        assert_eq!(schema_version(metadata.as_ref()), expected);
+    }
+
+    #[tokio::test]
+    async fn test_register_partitioned() -> Result<(), Box<dyn std::error::Error>> {
+        use datafusion::arrow::array::{RecordBatch, StringArray};
+        use datafusion::arrow::datatypes::{Field, Schema};
+        use datafusion::arrow::util::pretty::pretty_format_batches;
+        use datafusion::parquet::arrow::ArrowWriter;
+
+        let dir = tempfile::tempdir()?;
+        let schema = Arc::new(Schema::new(vec![Field::new("file_path", DataType::Utf8, true)]));
+        for (partition, file_path) in [
+            ("year=2021/month=03/file_type=csv", "a.csv"),
+            ("year=__HIVE_DEFAULT_PARTITION__/month=__HIVE_DEFAULT_PARTITION__/file_type=__HIVE_DEFAULT_PARTITION__", "b"),
+        ] {
+            std::fs::create_dir_all(dir.path().join(partition))?;
+            let file = std::fs::File::create(dir.path().join(partition).join("part.parquet"))?;
+            let batch = RecordBatch::try_new(schema.clone(), vec![Arc::new(StringArray::from(vec![file_path]))])?;
+            let mut writer = ArrowWriter::try_new(file, schema.clone(), None)?;
+            writer.write(&batch)?;
+            writer.close()?;
+        }
+
+        let ctx = SessionContext::new();
+        let cols = ["year", "month", "file_type"].map(String::from);
+        register_partitioned(&ctx, "object_store", &format!("{}/", dir.path().display()), &cols).await?;
+        let df = ctx.table("object_store").await?;
+        let types = df.schema().fields().iter().map(|x| (x.name().clone(), x.data_type().clone())).collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            vec![
+                ("file_path".to_string(), DataType::Utf8View),
+                ("year".to_string(), DataType::Int32),
+                ("month".to_string(), DataType::Int32),
+                ("file_type".to_string(), DataType::Utf8),
+            ]
+        );
+
+        let rows = |sql: &'static str| {
+            let ctx = ctx.clone();
+            async move {
+                let batches = ctx.sql(sql).await?.collect().await?;
+                Ok::<_, Box<dyn std::error::Error>>(pretty_format_batches(&batches)?.to_string())
+            }
+        };
+        let expected = [
+            "+-----------+------+-----------+",
+            "| file_path | year | file_type |",
+            "+-----------+------+-----------+",
+            "| a.csv     | 2021 | csv       |",
+            "| b         |      |           |",
+            "+-----------+------+-----------+",
+        ];
+        let sql = "select file_path, year, file_type from object_store order by file_path";
+        assert_eq!(rows(sql).await?, expected.join("\n"));
+        let sql = "select file_path, year, file_type from object_store where year = 2021 and month = 3";
+        assert_eq!(rows(sql).await?, [&expected[..4], &expected[5..]].concat().join("\n"));
+        let sql = "select file_path, year, file_type from object_store where file_type is null";
+        assert_eq!(rows(sql).await?, [&expected[..3], &expected[4..]].concat().join("\n"));
+        Ok(())
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use datafusion::datasource::file_format::parquet::ParquetFormat;
+use datafusion::datasource::file_format::FileFormat;
+use datafusion::datasource::listing::{ListingOptions, ListingTable, ListingTableConfig, ListingTableUrl};
+use datafusion::prelude::*;
+use futures::TryStreamExt;
+use object_store::path::Path;
+use object_store::{ObjectMeta, ObjectStore};
+use serde::Deserialize;
+
+use super::error::DataStoreError;
+use crate::utils::constants::DELTA_LOG_DIR;
+
+#[derive(Debug, Deserialize)]
+struct DeltaFile {
+    path: String,
+}
+
+/// only adds and removes change the files of the table
+#[derive(Debug, Default, Deserialize)]
+struct Action {
+    add: Option<DeltaFile>,
+    remove: Option<DeltaFile>,
+}
+
+/// paths of the active files after the commits, in version order
+fn replay(commits: &[Bytes]) -> Result<Vec<String>, DataStoreError> {
+    let mut files = BTreeSet::new();
+    for commit in commits {
+        for line in commit.split(|x| *x == b'\n').filter(|x| !x.is_empty()) {
+            let action: Action = serde_json::from_slice(line)
+                .map_err(|e| DataStoreError::UnexpectedError(e.into()))?;
+            if let Some(remove) = action.remove {
+                files.remove(&remove.path);
+            }
+            if let Some(add) = action.add {
+                files.insert(add.path);
+            }
+        }
+    }
+    Ok(files.into_iter().collect())
+}
+
+/// files of the delta table at the prefix, none if it has no `_delta_log/`
+pub async fn delta_files(store: &dyn ObjectStore, key: &str) -> Result<Option<Vec<ObjectMeta>>, DataStoreError> {
+    let log = Path::from(format!("{key}{DELTA_LOG_DIR}"));
+    let mut commits = store
+        .list(Some(&log))
+        .try_collect::<Vec<_>>()
+        .await
+        .map_err(|e| DataStoreError::UnexpectedError(e.into()))?;
+    if commits.iter().any(|x| x.location.as_ref().contains(".checkpoint.")) {
+        return Err(DataStoreError::DeltaTableError(format!("checkpoints of: {key} are not supported")));
+    }
+    commits.retain(|x| x.location.as_ref().ends_with(".json"));
+    if commits.is_empty() {
+        return Ok(None);
+    }
+    // commit names are zero-padded versions
+    commits.sort_by(|a, b| a.location.cmp(&b.location));
+
+    let mut data = vec![];
+    for commit in &commits {
+        let bytes = match store.get(&commit.location).await {
+            Ok(res) => res.bytes().await,
+            Err(e) => Err(e),
+        }
+        .map_err(|e| DataStoreError::UnexpectedError(e.into()))?;
+        data.push(bytes);
+    }
+    tracing::info!("reading version: {} of delta table: {}", commits.len() - 1, key);
+
+    let mut files = vec![];
+    for path in replay(&data)? {
+        let location = Path::from_url_path(format!("{key}{path}"))
+            .map_err(|e| DataStoreError::UnexpectedError(e.into()))?;
+        let meta = store
+            .head(&location)
+            .await
+            .map_err(|e| DataStoreError::UnexpectedError(e.into()))?;
+        files.push(meta);
+    }
+    Ok(Some(files))
+}
+
+/// registers the files of the table instead of listing the prefix, so files
+/// removed from the table but not vacuumed yet are not read
+pub async fn register_delta(
+    ctx: &SessionContext,
+    store: Arc<dyn ObjectStore>,
+    bucket: &str,
+    table_name: &str,
+    files: &[ObjectMeta],
+) -> Result<(), DataStoreError> {
+    let format = Arc::new(ParquetFormat::default());
+    // files rewritten by older runs may miss columns, their schemas are merged
+    let schema = format.infer_schema(&ctx.state(), &store, files).await?;
+    let urls = files
+        .iter()
+        .map(|x| ListingTableUrl::parse(format!("s3://{bucket}/{}", x.location)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let options = ListingOptions::new(format).with_file_extension(".parquet");
+    let config = ListingTableConfig::new_with_multi_paths(urls)
+        .with_listing_options(options)
+        .with_schema(schema);
+    ctx.register_table(table_name, Arc::new(ListingTable::try_new(config)?))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay() -> Result<(), DataStoreError> {
+        let first = Bytes::from(
+            [
+                r#"{"commitInfo":{"operation":"MERGE"}}"#,
+                r#"{"protocol":{"minReaderVersion":1,"minWriterVersion":2}}"#,
+                r#"{"add":{"path":"a.parquet","size":1,"modificationTime":0,"dataChange":true}}"#,
+            ]
+            .join("\n"),
+        );
+        let second = Bytes::from(
+            [
+                r#"{"remove":{"path":"a.parquet","deletionTimestamp":1,"dataChange":true}}"#,
+                r#"{"add":{"path":"b.parquet","size":1,"modificationTime":1,"dataChange":true}}"#,
+                r#"{"add":{"path":"c.parquet","size":1,"modificationTime":1,"dataChange":true}}"#,
+            ]
+            .join("\n"),
+        );
+        assert_eq!(replay(std::slice::from_ref(&first))?, vec!["a.parquet"]);
+        assert_eq!(replay(&[first, second])?, vec!["b.parquet", "c.parquet"]);
+        Ok(())
+    }
+}
@@ -19,6 +19,12 @@ pub enum DataStoreError {
     #[error("Tokio error")]
     TokioError(#[from] JoinError),
 
+    #[error("Unsupported schema version: {version} of index file: {key}")]
+    SchemaVersionError { key: String, version: u32 },
+
+    #[error("Unsupported delta table: {0}")]
+    DeltaTableError(String),
+
     #[error("Unexpected error")]
     UnexpectedError(#[source] Report),
 }
@@ -1,3 +1,4 @@
 pub mod aws;
 pub mod catalog;
+pub mod delta;
 pub mod error;
@@ -13,7 +13,7 @@ pub mod routes;
 pub mod utils;
 
 use error::ApiError;
-use routes::{ping, post_download, post_select};
+use routes::{get_freshness, ping, post_download, post_select};
 use utils::queryparser::prepare_query;
 
 use crate::routes::{post_catalog, ApiRoute};
@@ -131,6 +131,8 @@ pub async fn handler(
     let response = match route {
         ApiRoute::AliveGet => ping().await?,
 
+        ApiRoute::FreshnessGet => get_freshness(&state.client).await?,
+
         ApiRoute::SelectPost => {
             handle_query(&body, QueryKind::SelectDownload, |query| async move {
                 post_select(&state.ctx, &query).await
@@ -1,10 +1,12 @@
+#![recursion_limit = "256"]
+
 use datafusion::prelude::SessionContext;
 use lambda_runtime::{run, service_fn, Error};
 
 use dataplatform_sdk_api::data_store::aws::init_table_ctx;
 use dataplatform_sdk_api::error::init_error_handler;
 use dataplatform_sdk_api::utils::aws::get_aws_client;
-use dataplatform_sdk_api::utils::constants::{prod::*, INDEX_BUCKET_SECRET};
+use dataplatform_sdk_api::utils::constants::{prod::*, INDEX_BUCKET_SECRET, INDEX_PARTITION_COLS};
 use dataplatform_sdk_api::utils::tracing::init_tracing;
 use dataplatform_sdk_api::{handler, AppState};
 
@@ -15,13 +17,13 @@ async fn main() -> Result<(), Error> {
 
     let client = get_aws_client(REGION.to_string()).await;
     let ctx = SessionContext::new();
-    init_table_ctx(&ctx, REGION, &INDEX_BUCKET_SECRET, INDEX_PREFIX, TABLE_NAME) // object_store table init
+    init_table_ctx(&ctx, REGION, &INDEX_BUCKET_SECRET, INDEX_PREFIX, TABLE_NAME, &INDEX_PARTITION_COLS) // object_store table init
         .await
         .map_err(|err| {
             tracing::error!(?err, "failed to init context");
             err
         })?;
-    init_table_ctx(&ctx, REGION, &INDEX_BUCKET_SECRET, CATALOG_PREFIX, CATALOG_NAME) // object_store table init
+    init_table_ctx(&ctx, REGION, &INDEX_BUCKET_SECRET, CATALOG_PREFIX, CATALOG_NAME, &[]) // object_store_catalog table init
         .await
         .map_err(|err| {
             tracing::error!(?err, "failed to init context");
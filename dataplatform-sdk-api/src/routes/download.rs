@@ -36,6 +36,8 @@ pub async fn post_download(
     let response = match df {
         None => ApiResponseKind::NotFound.try_into()?,
         Some(df) => {
+            // deleted objects can't be downloaded anymore
+            let df = Table::exclude_deleted(df).map_err(|e| ApiError::UnexpectedError(e.into()))?;
             // write parquet file to target s3, ecs then uses this file to get file names to process
             if is_empty(df.clone())
                 .await
@@ -0,0 +1,58 @@
+use aws_sdk_s3::Client;
+use color_eyre::eyre::Report;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::ApiError,
+    utils::{aws::get_aws_object, constants::prod::MANIFEST_KEY, constants::*, error::UtilsError},
+    ApiResponse, ApiResponseKind,
+};
+
+/// counts of the last data-indexer run, part of its `_manifest.json`
+#[derive(Deserialize, Serialize, Debug)]
+pub struct RunSummary {
+    pub listed: usize,
+    pub new_or_changed: usize,
+    pub bytes: i64,
+    pub deleted: usize,
+    pub errors: usize,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct IndexRun {
+    pub run: String,
+    pub started: String,
+    pub finished: Option<String>,
+    pub duration_secs: f64,
+    pub success: bool,
+    pub sources: Vec<String>,
+    pub summary: RunSummary,
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+pub struct FreshnessResponse {
+    pub result: IndexRun,
+}
+
+/// last index run written by data-indexer, 404 before the first one
+#[tracing::instrument(level = "info", name = "freshness", skip(client))]
+pub async fn get_freshness(client: &Client) -> Result<ApiResponse, ApiError> {
+    let object = match get_aws_object(client.clone(), &INDEX_BUCKET_SECRET, MANIFEST_KEY).await {
+        Ok(object) => object,
+        Err(UtilsError::SdkError(e)) if e.as_service_error().is_some_and(|x| x.is_no_such_key()) => {
+            return ApiResponseKind::NotFound.try_into();
+        }
+        Err(e) => return Err(ApiError::UnexpectedError(e.into())),
+    };
+    let data = object
+        .body
+        .collect()
+        .await
+        .map_err(|e| ApiError::UnexpectedError(Report::new(e)))?
+        .into_bytes();
+    let resp = FreshnessResponse {
+        result: serde_json::from_slice(&data)?,
+    };
+    let body = serde_json::to_string(&resp)?;
+    ApiResponseKind::Ok(Some(body)).try_into()
+}
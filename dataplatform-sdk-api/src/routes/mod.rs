@@ -1,11 +1,13 @@
 mod alive;
 mod catalog;
 mod download;
+mod freshness;
 mod route;
 mod select;
 
 pub use alive::*;
 pub use catalog::*;
 pub use download::*;
+pub use freshness::*;
 pub use route::*;
 pub use select::*;
@@ -4,6 +4,7 @@ pub enum ApiRoute {
     SelectPost,
     DownloadPost,
     CatalogPost,
+    FreshnessGet,
 }
 
 impl TryFrom<(&str, &str)> for ApiRoute {
@@ -15,6 +16,7 @@ impl TryFrom<(&str, &str)> for ApiRoute {
             ("POST", "/select") => Ok(ApiRoute::SelectPost),
             ("POST", "/download") => Ok(ApiRoute::DownloadPost),
             ("POST", "/catalog") => Ok(ApiRoute::CatalogPost),
+            ("GET", "/freshness") => Ok(ApiRoute::FreshnessGet),
             _ => Err(format!(
                 "unsupported resource method: {method}, path: {path}"
             )),
@@ -34,8 +36,9 @@ mod tests {
     #[case(("POST", "/select"), Ok(ApiRoute::SelectPost))]
     #[case(("POST", "/download"), Ok(ApiRoute::DownloadPost))]
     #[case(("POST", "/catalog"), Ok(ApiRoute::CatalogPost))]
-    #[case(("foo", "/foo"), Err(format!("unsupported resource method: foo, path: /foo")))]
-    #[case(("", "/"), Err(format!("unsupported resource method: , path: /")))]
+    #[case(("GET", "/freshness"), Ok(ApiRoute::FreshnessGet))]
+    #[case(("foo", "/foo"), Err("unsupported resource method: foo, path: /foo".to_string()))]
+    #[case(("", "/"), Err("unsupported resource method: , path: /".to_string()))]
     fn test_api_route(#[case] input: (&str, &str), #[case] expected: Result<ApiRoute, String>) {
         let res = input.try_into();
         assert_eq!(res, expected);
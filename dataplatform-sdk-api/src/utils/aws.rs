@@ -15,6 +15,8 @@ use datafusion::prelude::*;
 use tokio_stream::StreamExt;
 
 use super::error::UtilsError;
+use dataplatform_multipart::constants::PART_SIZE;
+use dataplatform_multipart::MultipartWriter;
 
 pub async fn get_aws_client(region: String) -> Client {
     let region = Region::new(region);
@@ -103,21 +105,13 @@ pub async fn write_df_to_s3(
     key: &str,
     df: DataFrame,
 ) -> Result<(), UtilsError> {
-    let mut buf = vec![];
     let schema = Schema::from(df.schema());
     let mut stream = df.execute_stream().await?;
-    let mut writer = AsyncArrowWriter::try_new(&mut buf, schema.into(), None)?;
+    let sink = MultipartWriter::new(client.clone(), bucket, key, PART_SIZE);
+    let mut writer = AsyncArrowWriter::try_new(sink, schema.into(), None)?;
     while let Some(batch) = stream.next().await.transpose()? {
         writer.write(&batch).await?;
     }
     writer.close().await?;
-
-    let _resp = client
-        .put_object()
-        .bucket(bucket)
-        .key(key)
-        .body(buf.into())
-        .send()
-        .await?;
     Ok(())
 }
@@ -12,6 +12,7 @@ pub mod prod {
     pub const TABLE_NAME: &str = "object_store";
     pub const CATALOG_NAME: &str = "object_store_catalog";
     pub const PRESIGNED_TIMEOUT: u64 = 3600;
+    pub const MANIFEST_KEY: &str = "index/_manifest.json"; // last run written by data-indexer
 }
 
 pub mod test {
@@ -24,14 +25,22 @@ pub mod test {
     pub const TABLE_NAME: &str = "object_store";
     pub const CATALOG_NAME: &str = "object_store_catalog";
     pub const PRESIGNED_TIMEOUT: u64 = 1800;
+    pub const MANIFEST_KEY: &str = "index/_manifest.json"; // last run written by data-indexer
 }
 
+pub const GENERATION_FILE: &str = "_current.json"; // written by data-indexer compaction
+pub const DELTA_LOG_DIR: &str = "_delta_log/"; // commits of the delta table written by data-indexer
+pub const SCHEMA_VERSION: u32 = 5; // newest index schema version the api can read
+pub const SCHEMA_VERSION_KEY: &str = "data_indexer.schema_version"; // parquet key-value metadata written by data-indexer
+pub const HIVE_DEFAULT_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__"; // partition value of rows without one
+
 pub mod env {
     pub const DATA_BUCKET_ENV_VAR: &str = "DATA_BUCKET";
     pub const INDEX_BUCKET_ENV_VAR: &str = "INDEX_BUCKET";   
     pub const ECS_CLUSTER_ENV_VAR: &str = "ECS_CLUSTER";   
     pub const SUBNETS_ENV_VAR: &str = "SUBNETS";  
     pub const SECURITY_GROUPS_ENV_VAR: &str = "SECURITY_GROUPS";    
+    pub const INDEX_PARTITION_COLS_ENV_VAR: &str = "INDEX_PARTITION_COLS";
 }
 
 pub static DATA_BUCKET_SECRET: LazyLock<String> = LazyLock::new(|| {
@@ -84,5 +93,16 @@ pub static SECURITY_GROUPS_SECRET: LazyLock<Vec<String>> = LazyLock::new(|| {
     secret.split('.').map(|x| x.trim().to_string()).collect()
 });
 
+// optional, must match partition_by of data-indexer, e.g. "year,month,file_type"
+pub static INDEX_PARTITION_COLS: LazyLock<Vec<String>> = LazyLock::new(|| {
+    dotenv().ok();
+    std_env::var(env::INDEX_PARTITION_COLS_ENV_VAR)
+        .unwrap_or_default()
+        .split(',')
+        .map(|x| x.trim().to_string())
+        .filter(|x| !x.is_empty())
+        .collect()
+});
+
 pub const CONTAINER_NAME: &str = "datalake-worker";
 pub const TASK_NAME: &str = "datalake-worker-run-dev";
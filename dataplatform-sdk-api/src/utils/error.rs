@@ -6,6 +6,7 @@ use aws_sdk_s3::operation::put_object::PutObjectError;
 use aws_smithy_types::byte_stream::error::Error as AWSSmithyError;
 use aws_smithy_types::error::operation::BuildError;
 use color_eyre::eyre::Report;
+use dataplatform_multipart::MultipartError;
 use datafusion::error::DataFusionError;
 use datafusion::parquet::errors::ParquetError;
 use std::io::Error as IoError;
@@ -40,6 +41,9 @@ pub enum UtilsError {
     #[error("AWS PutObjectError error")]
     PutObjectError(#[from] SdkError<PutObjectError>),
 
+    #[error("Multipart upload error")]
+    MultipartError(#[from] MultipartError),
+
     #[error("Unexpected error")]
     UnexpectedError(#[source] Report),
 }
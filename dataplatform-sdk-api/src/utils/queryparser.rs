@@ -55,7 +55,7 @@ fn prepare_query_select_download(ast: &mut [Statement]) -> Result<String, QueryP
         // check query contains object_store table
         let valid_table = match &*query.body {
             SetExpr::Select(select) => {
-                if let Some(from_table) = select.from.get(0) {
+                if let Some(from_table) = select.from.first() {
                     if let TableFactor::Table { name, .. } = &from_table.relation {
                         name.0
                             .last()
@@ -103,7 +103,7 @@ fn prepare_query_catalog(ast: &mut [Statement]) -> Result<String, QueryParserErr
         // check query contains object_store table
         let valid_table = match &*query.body {
             SetExpr::Select(select) => {
-                if let Some(from_table) = select.from.get(0) {
+                if let Some(from_table) = select.from.first() {
                     if let TableFactor::Table { name, .. } = &from_table.relation {
                         name.0
                             .last()
@@ -154,6 +154,7 @@ mod tests {
     #[case("select * from object_store limit 10", Ok("SELECT * FROM object_store LIMIT 10".to_string()))]
     #[case("select * from object_store where file_name = 'foo'", Ok("SELECT * FROM object_store WHERE file_name = 'foo' LIMIT 10".to_string()))]
     #[case("select * from object_store where file_name = 'foo' limit 10", Ok("SELECT * FROM object_store WHERE file_name = 'foo' LIMIT 10".to_string()))]
+    #[case("select * from object_store where tags['team'] = 'mri'", Ok("SELECT * FROM object_store WHERE tags['team'] = 'mri' LIMIT 10".to_string()))]
     #[case("select * from foo", Err(QueryParserError::InvalidTableName))]
     #[case(
         "delete from object_store",
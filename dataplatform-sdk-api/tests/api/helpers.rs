@@ -30,7 +30,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .get(&format!("{}/alive", &self.address))
+            .get(format!("{}/alive", &self.address))
             .json(body)
             .send()
             .await
@@ -42,7 +42,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/select", &self.address))
+            .post(format!("{}/select", &self.address))
             .json(body)
             .send()
             .await
@@ -54,7 +54,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/download", &self.address))
+            .post(format!("{}/download", &self.address))
             .json(body)
             .send()
             .await
@@ -66,7 +66,7 @@ impl TestApp {
         Body: serde::Serialize,
     {
         self.http_client
-            .post(&format!("{}/catalog", &self.address))
+            .post(format!("{}/catalog", &self.address))
             .json(body)
             .send()
             .await
@@ -74,6 +74,7 @@ impl TestApp {
     }
 }
 
+#[allow(dead_code)]
 pub async fn unzip_file(archive: File, out_dir: &Path) {
     let archive = BufReader::new(archive).compat();
     let mut reader = ZipFileReader::new(archive)
@@ -8,6 +8,8 @@ use color_eyre::eyre::Report;
 use datafusion::arrow::error::ArrowError;
 use datafusion::error::DataFusionError;
 use datafusion::parquet::errors::ParquetError;
+use dataplatform_archive::ArchiveError;
+use dataplatform_multipart::MultipartError;
 use std::io::Error as IoError;
 use thiserror::Error;
 
@@ -37,9 +39,15 @@ pub enum WorkerError {
     #[error("AWS UploadPartError error")]
     UploadPartError(#[from] SdkError<UploadPartError>),
 
+    #[error("Multipart upload error")]
+    MultipartError(#[from] MultipartError),
+
     #[error("Zip error")]
     ZipError(#[from] ZipError),
 
+    #[error("Archive error")]
+    ArchiveError(#[from] ArchiveError),
+
     #[error("Unexpected error")]
     UnexpectedError(#[source] Report),
 }
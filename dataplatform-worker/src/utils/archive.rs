@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::io::{self, Cursor, Read};
+
+use crate::utils::aws::S3Object;
+use crate::WorkerError;
+
+use bytes::Bytes;
+use color_eyre::eyre::eyre;
+use dataplatform_archive::zip::{read_zip_entry, zip_entries};
+use dataplatform_archive::RangeRead;
+use flate2::read::MultiGzDecoder;
+
+/// path of a member below the directory of its archive, `None` for absolute paths
+/// and paths leaving the directory with `..`
+pub fn member_path(path: &str) -> Option<String> {
+    let path = path.replace('\\', "/");
+    let mut parts = path.split('/').peekable();
+    let drive = parts
+        .peek()
+        .is_some_and(|x| x.len() == 2 && x.ends_with(':'));
+    if path.starts_with('/') || drive {
+        return None;
+    }
+    let mut normalized = vec![];
+    for part in parts {
+        match part {
+            "" | "." => continue,
+            ".." => return None,
+            part => normalized.push(part),
+        }
+    }
+    (!normalized.is_empty()).then(|| normalized.join("/"))
+}
+
+/// requested members of a zip or tar archive, by the extension of the key, with their paths
+/// below the directory of the archive. Zips are read with ranged requests for the members
+/// unless the archive is already downloaded, tars are streamed until all members are found.
+/// Members missing from the archive or with unsafe paths are logged and skipped
+pub async fn extract_members(
+    object: &S3Object,
+    data: Option<Bytes>,
+    members: &[String],
+) -> Result<Vec<(String, Bytes)>, WorkerError> {
+    let key = &object.key;
+    // archive paths by the paths of the members in the archive
+    let mut wanted = HashMap::new();
+    for member in members {
+        match member_path(member) {
+            Some(path) => {
+                wanted.insert(member.clone(), path);
+            }
+            None => tracing::error!("member: {member} of archive: {key} has an unsafe path"),
+        }
+    }
+    if wanted.is_empty() {
+        return Ok(vec![]);
+    }
+    let lower = key.to_lowercase();
+    let found = if lower.ends_with(".zip") {
+        match data {
+            Some(data) => extract_zip(&data, &wanted).await?,
+            None => extract_zip(object, &wanted).await?,
+        }
+    } else if lower.ends_with(".tar") || lower.ends_with(".tar.gz") || lower.ends_with(".tgz") {
+        let gzip = !lower.ends_with(".tar");
+        match data {
+            Some(data) => extract_tar(Cursor::new(data), gzip, &wanted)?,
+            None => {
+                let reader = object.reader().await?;
+                let wanted = wanted.clone();
+                tokio::task::spawn_blocking(move || extract_tar(reader, gzip, &wanted))
+                    .await
+                    .map_err(|e| WorkerError::UnexpectedError(e.into()))??
+            }
+        }
+    } else {
+        return Err(WorkerError::UnexpectedError(eyre!(
+            "file: {key} is not a zip or tar archive"
+        )));
+    };
+    for (member, path) in &wanted {
+        if !found.iter().any(|(x, _)| x == path) {
+            tracing::error!("member: {member} not found in archive: {key}");
+        }
+    }
+    Ok(found)
+}
+
+/// local headers and data of the wanted entries from the central directory
+async fn extract_zip<R: RangeRead + Sync>(
+    reader: &R,
+    wanted: &HashMap<String, String>,
+) -> Result<Vec<(String, Bytes)>, R::Error> {
+    let mut found = vec![];
+    for entry in zip_entries(reader).await? {
+        if let Some(path) = wanted.get(&entry.path) {
+            let data = read_zip_entry(reader, &entry).await?;
+            found.push((path.clone(), Bytes::from(data)));
+        }
+    }
+    Ok(found)
+}
+
+/// tars are read in order, the data of other members is skipped
+fn extract_tar(
+    reader: impl Read,
+    gzip: bool,
+    wanted: &HashMap<String, String>,
+) -> io::Result<Vec<(String, Bytes)>> {
+    let reader: Box<dyn Read> = match gzip {
+        true => Box::new(MultiGzDecoder::new(reader)),
+        false => Box::new(reader),
+    };
+    let mut archive = tar::Archive::new(reader);
+    let mut found = vec![];
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        let Some(path) = wanted.get(&path) else {
+            continue;
+        };
+        let mut buf = vec![];
+        entry.read_to_end(&mut buf)?;
+        found.push((path.clone(), Bytes::from(buf)));
+        if found.len() == wanted.len() {
+            break;
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use async_zip::base::write::ZipFileWriter;
+    use async_zip::{Compression, ZipEntryBuilder};
+    use aws_config::{BehaviorVersion, Region};
+    use aws_sdk_s3::operation::get_object::GetObjectOutput;
+    use aws_sdk_s3::primitives::ByteStream;
+    use aws_sdk_s3::Client;
+    use aws_smithy_mocks::{mock, mock_client, RuleMode};
+    use color_eyre::Result;
+    use flate2::write::GzEncoder;
+    use rstest::rstest;
+    use std::io::Write;
+
+    const FILES: [(&str, &[u8]); 3] = [
+        ("scans/a.dcm", b"dicom"),
+        ("b.csv", b"id\n1\n"),
+        ("../evil.sh", b"rm"),
+    ];
+
+    async fn zip_file() -> Result<Vec<u8>> {
+        let mut writer = ZipFileWriter::new(vec![]);
+        for (name, body) in FILES {
+            let builder = ZipEntryBuilder::new(name.into(), Compression::Deflate);
+            writer.write_entry_whole(builder, body).await?;
+        }
+        Ok(writer.close().await?)
+    }
+
+    fn tar_file() -> Result<Vec<u8>> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (name, body) in FILES {
+            let mut header = tar::Header::new_gnu();
+            // the builder refuses `..`, the name is set as a malicious archive would
+            header.as_gnu_mut().expect("gnu header").name[..name.len()]
+                .copy_from_slice(name.as_bytes());
+            header.set_size(body.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, body)?;
+        }
+        Ok(builder.into_inner()?)
+    }
+
+    /// object of a client without credentials, for archives already downloaded
+    fn object(key: &str) -> S3Object {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("eu-central-1"))
+            .build();
+        S3Object {
+            client: Arc::new(Client::from_conf(config)),
+            bucket: "raw".to_string(),
+            key: key.to_string(),
+            version_id: None,
+        }
+    }
+
+    #[rstest]
+    #[case("scans/a.dcm", Some("scans/a.dcm"))]
+    #[case("./scans//a.dcm", Some("scans/a.dcm"))]
+    #[case("scans\\a.dcm", Some("scans/a.dcm"))]
+    #[case("../evil.sh", None)]
+    #[case("scans/../../evil.sh", None)]
+    #[case("/etc/passwd", None)]
+    #[case("C:/evil.exe", None)]
+    #[case("./", None)]
+    fn test_member_path(#[case] path: &str, #[case] expected: Option<&str>) {
+        assert_eq!(member_path(path).as_deref(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_extract_members() -> Result<()> {
+        let mut encoder = GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&tar_file()?)?;
+        let archives = [
+            ("a.zip", zip_file().await?),
+            ("a.tar", tar_file()?),
+            ("a.tar.gz", encoder.finish()?),
+        ];
+        let members = ["scans/a.dcm", "missing.csv", "../evil.sh"].map(String::from);
+        for (key, data) in archives {
+            let found = extract_members(&object(key), Some(Bytes::from(data)), &members).await?;
+            assert_eq!(
+                found,
+                vec![("scans/a.dcm".to_string(), Bytes::from_static(b"dicom"))],
+                "{key}"
+            );
+        }
+        let data = Some(Bytes::new());
+        assert!(extract_members(&object("a.csv"), data, &members)
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_extract_zip_members_by_range() -> Result<()> {
+        let data = Bytes::from(zip_file().await?);
+        let ranges = Arc::new(Mutex::new(vec![]));
+        let rule = {
+            let (data, ranges) = (data.clone(), ranges.clone());
+            mock!(Client::get_object).then_compute_output(move |req| {
+                let range = req.range().expect("ranged read").to_string();
+                ranges.lock().expect("ranges").push(range.clone());
+                let len = data.len() as u64;
+                let bounds = range.strip_prefix("bytes=").and_then(|x| x.split_once('-'));
+                let (start, end) = match bounds.expect("byte range") {
+                    ("", n) => (len.saturating_sub(n.parse().expect("length")), len - 1),
+                    (start, end) => (start.parse().expect("start"), end.parse().expect("end")),
+                };
+                GetObjectOutput::builder()
+                    .body(ByteStream::from(data.slice(start as usize..=end as usize)))
+                    .content_range(format!("bytes {start}-{end}/{len}"))
+                    .build()
+            })
+        };
+        let client = mock_client!(aws_sdk_s3, RuleMode::MatchAny, [&rule]);
+        let object = S3Object {
+            client: Arc::new(client),
+            ..object("a.zip")
+        };
+        let found = extract_members(&object, None, &["b.csv".to_string()]).await?;
+        assert_eq!(
+            found,
+            vec![("b.csv".to_string(), Bytes::from_static(b"id\n1\n"))]
+        );
+        // the tail with the central directory, then the local header and the data of the member
+        assert_eq!(ranges.lock().expect("ranges").len(), 3);
+        Ok(())
+    }
+}
@@ -12,18 +12,26 @@ use aws_sdk_s3::config::Builder;
 use aws_sdk_s3::operation::get_object::GetObjectOutput;
 use aws_sdk_s3::Client;
 use bytes::Bytes;
+use color_eyre::eyre::eyre;
 use datafusion::parquet::arrow::ParquetRecordBatchStreamBuilder;
 use datafusion::prelude::*;
+use dataplatform_archive::RangeRead;
 use futures::TryStreamExt;
 use tokio::io::AsyncReadExt;
 use tokio::sync::Semaphore;
+use tokio_util::io::SyncIoBridge;
 
 pub async fn get_aws_object(
     client: Arc<Client>,
     bucket: &str,
     key: &str,
+    version_id: Option<&str>,
 ) -> Result<GetObjectOutput, WorkerError> {
-    let req = client.get_object().bucket(bucket).key(key);
+    let req = client
+        .get_object()
+        .bucket(bucket)
+        .key(key)
+        .set_version_id(version_id.map(|x| x.to_string()));
     let res = req.send().await?;
     Ok(res)
 }
@@ -46,17 +54,19 @@ pub async fn get_aws_client(region: String) -> Client {
     Client::from_conf(config)
 }
 
-/// Read file from aws s3
+/// Read file from aws s3, the given version or the current one
 pub async fn read_file(
     client: Arc<Client>,
     bucket: String,
     key: String,
+    version_id: Option<String>,
 ) -> Result<Vec<u8>, WorkerError> {
     let object = retry(|| {
         let client = client.clone();
         let bucket = bucket.clone();
         let key = key.clone();
-        async move { get_aws_object(client, &bucket, &key).await }
+        let version_id = version_id.clone();
+        async move { get_aws_object(client, &bucket, &key, version_id.as_deref()).await }
     })
     .await?;
     let size = object.content_length().unwrap_or(0) as u64;
@@ -71,8 +81,9 @@ pub async fn read_file(
             let client = client.clone();
             let bucket = bucket.clone();
             let key = key.clone();
+            let version_id = version_id.clone();
             async move {
-                let object = get_aws_object(client.clone(), &bucket, &key).await?;
+                let object = get_aws_object(client.clone(), &bucket, &key, version_id.as_deref()).await?;
                 let mut reader = object.body.into_async_read();
                 let mut buf = Vec::with_capacity(size as usize);
                 reader.read_to_end(&mut buf).await?;
@@ -86,8 +97,9 @@ pub async fn read_file(
             let client = client.clone();
             let bucket = bucket.clone();
             let key = key.clone();
+            let version_id = version_id.clone();
             async move {
-                let object = get_aws_object(client.clone(), &bucket, &key).await?;
+                let object = get_aws_object(client.clone(), &bucket, &key, version_id.as_deref()).await?;
                 let mut buf = Vec::with_capacity(size as usize);
                 let mut stream = object.body;
                 while let Some(chunk) = stream.try_next().await? {
@@ -113,6 +125,7 @@ pub async fn read_file(
             let client = client.clone();
             let bucket = bucket.clone();
             let key = key.clone();
+            let version_id = version_id.clone();
             let permit = semaphore
                 .clone()
                 .acquire_owned()
@@ -125,12 +138,14 @@ pub async fn read_file(
                     let client = client.clone();
                     let bucket = bucket.clone();
                     let key = key.clone();
+                    let version_id = version_id.clone();
                     async move {
                         let range = format!("bytes={}-{}", start, end);
                         let out = client
                             .get_object()
                             .bucket(&bucket)
                             .key(&key)
+                            .set_version_id(version_id)
                             .range(range)
                             .send()
                             .await?;
@@ -160,6 +175,70 @@ pub async fn read_file(
     }
 }
 
+/// object of a bucket, the given version or the current one, read by ranges for archive members
+#[derive(Debug, Clone)]
+pub struct S3Object {
+    pub client: Arc<Client>,
+    pub bucket: String,
+    pub key: String,
+    pub version_id: Option<String>,
+}
+
+impl S3Object {
+    /// body of the range with the content range of the response
+    async fn get_range(&self, range: String) -> Result<(Bytes, Option<String>), WorkerError> {
+        retry(|| {
+            let req = self
+                .client
+                .get_object()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .set_version_id(self.version_id.clone())
+                .range(range.clone());
+            async move {
+                let out = req.send().await?;
+                let content_range = out.content_range().map(|x| x.to_string());
+                Ok((out.body.collect().await?.into_bytes(), content_range))
+            }
+        })
+        .await
+    }
+
+    /// whole body as a blocking reader, for archives read in order
+    pub async fn reader(&self) -> Result<impl std::io::Read + Send + 'static, WorkerError> {
+        let object = get_aws_object(
+            self.client.clone(),
+            &self.bucket,
+            &self.key,
+            self.version_id.as_deref(),
+        )
+        .await?;
+        Ok(SyncIoBridge::new(Box::pin(object.body.into_async_read())))
+    }
+}
+
+/// size of the object from a content range of `bytes {start}-{end}/{size}`
+fn object_size(content_range: &str) -> Option<u64> {
+    content_range.rsplit_once('/')?.1.parse().ok()
+}
+
+impl RangeRead for S3Object {
+    type Error = WorkerError;
+
+    async fn read_tail(&self, len: u64) -> Result<(Bytes, u64), WorkerError> {
+        let (data, content_range) = self.get_range(format!("bytes=-{len}")).await?;
+        let size = content_range.as_deref().and_then(object_size).ok_or_else(|| {
+            WorkerError::UnexpectedError(eyre!("no content range of: {}", self.key))
+        })?;
+        Ok((data, size))
+    }
+
+    async fn read_range(&self, start: u64, end: u64) -> Result<Bytes, WorkerError> {
+        let (data, _) = self.get_range(format!("bytes={start}-{end}")).await?;
+        Ok(data)
+    }
+}
+
 async fn retry<F, Fut, T>(mut operation: F) -> Result<T, WorkerError>
 where
     F: FnMut() -> Fut,
@@ -186,7 +265,7 @@ pub async fn read_file_to_df(
     bucket: String,
     key: String,
 ) -> Result<DataFrame, WorkerError> {
-    let buf = read_file(client, bucket, key).await?;
+    let buf = read_file(client, bucket, key, None).await?;
     let stream = ParquetRecordBatchStreamBuilder::new(Cursor::new(buf))
         .await?
         .build()?;
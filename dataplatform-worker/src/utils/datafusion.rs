@@ -1,28 +1,78 @@
 use crate::WorkerError;
 
-use datafusion::{arrow::array::StringViewArray, prelude::*};
+use datafusion::arrow::array::{Array, AsArray};
+use datafusion::arrow::compute::cast;
+use datafusion::arrow::datatypes::DataType;
+use datafusion::prelude::*;
 use tokio_stream::StreamExt;
 
+/// object to download, indexes of several sources store the bucket per file,
+/// indexes of versioned buckets the version and rows of archive members their path
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileRef {
+    pub bucket: Option<String>,
+    pub key: String,
+    pub version_id: Option<String>,
+    pub member_path: Option<String>,
+}
+
 /// get file names from df table with links to s3 location to download
-pub async fn get_files_names(df: DataFrame) -> Result<Vec<String>, WorkerError> {
+pub async fn get_files_names(df: DataFrame) -> Result<Vec<FileRef>, WorkerError> {
     tracing::info!("selecting file names");
-    let df = df.select_columns(&["file_path"])?;
+    let mut columns = vec!["file_path"];
+    for name in [
+        "source_bucket",
+        "version_id",
+        "is_delete_marker",
+        "member_path",
+    ] {
+        if df.schema().field_with_unqualified_name(name).is_ok() {
+            columns.push(name);
+        }
+    }
+    let df = df.select_columns(&columns)?;
     let mut stream = df.execute_stream().await?;
-    let mut keys = vec![];
+    let mut files = vec![];
     while let Some(batch) = stream.next().await.transpose()? {
-        let file_pathes = batch
-            .column(0)
-            .as_any()
-            .downcast_ref::<StringViewArray>()
-            .expect("Expected a StringViewArray for file_pathes");
-        for name in file_pathes {
+        let get_col = |name: &str, data_type: &DataType| {
+            batch
+                .column_by_name(name)
+                .map(|x| cast(x, data_type))
+                .transpose()
+        };
+        let file_pathes = cast(batch.column(0), &DataType::Utf8View)?;
+        let file_pathes = file_pathes.as_string_view();
+        let buckets = get_col("source_bucket", &DataType::Utf8View)?;
+        let buckets = buckets.as_ref().map(|x| x.as_string_view());
+        let version_ids = get_col("version_id", &DataType::Utf8View)?;
+        let version_ids = version_ids.as_ref().map(|x| x.as_string_view());
+        let delete_markers = get_col("is_delete_marker", &DataType::Boolean)?;
+        let delete_markers = delete_markers.as_ref().map(|x| x.as_boolean());
+        let member_paths = get_col("member_path", &DataType::Utf8View)?;
+        let member_paths = member_paths.as_ref().map(|x| x.as_string_view());
+        for (i, name) in file_pathes.iter().enumerate() {
             match name {
-                Some(k) => keys.push(k.to_string()),
+                // delete markers have no body to download
+                Some(k) if delete_markers.is_some_and(|x| x.is_valid(i) && x.value(i)) => {
+                    tracing::warn!("skipping delete marker of: {k}");
+                }
+                Some(k) => files.push(FileRef {
+                    bucket: buckets
+                        .filter(|x| x.is_valid(i))
+                        .map(|x| x.value(i).to_string()),
+                    key: k.to_string(),
+                    version_id: version_ids
+                        .filter(|x| x.is_valid(i))
+                        .map(|x| x.value(i).to_string()),
+                    member_path: member_paths
+                        .filter(|x| x.is_valid(i))
+                        .map(|x| x.value(i).to_string()),
+                }),
                 None => tracing::error!("found none file path in batch"),
             };
         }
     }
-    Ok(keys)
+    Ok(files)
 }
 
 pub async fn df_to_json_bytes(df: DataFrame) -> Result<Vec<u8>, WorkerError> {
@@ -45,7 +95,7 @@ mod tests {
 
     use color_eyre::Result;
     use datafusion::arrow::{
-        array::{Int32Array, RecordBatch, StringArray, StringViewArray},
+        array::{BooleanArray, Int32Array, RecordBatch, StringArray, StringViewArray},
         datatypes::{DataType, Field, Schema},
     };
     use serde_json::Value;
@@ -96,7 +146,107 @@ mod tests {
         )?;
         let df = ctx.read_batch(batch)?;
         let res = get_files_names(df).await?;
-        assert_eq!(res, vec!["foo/path/", "bar/path/", "baz/path/"]);
+        let keys = res.iter().map(|x| x.key.as_str()).collect::<Vec<_>>();
+        assert_eq!(keys, vec!["foo/path/", "bar/path/", "baz/path/"]);
+        assert!(res.iter().all(|x| x.bucket.is_none()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_files_names_source_bucket() -> Result<()> {
+        let ctx = SessionContext::new();
+        let schema = Schema::new(vec![
+            Field::new("file_path", DataType::Utf8View, true),
+            Field::new("source_bucket", DataType::Utf8View, true),
+        ]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(StringViewArray::from(vec!["foo/a.csv", "bar/b.csv"])),
+                Arc::new(StringViewArray::from(vec![Some("raw"), None])),
+            ],
+        )?;
+        let df = ctx.read_batch(batch)?;
+        let res = get_files_names(df).await?;
+        assert_eq!(
+            res[0],
+            FileRef {
+                bucket: Some("raw".to_string()),
+                key: "foo/a.csv".to_string(),
+                version_id: None,
+                member_path: None
+            }
+        );
+        assert_eq!(
+            res[1],
+            FileRef {
+                bucket: None,
+                key: "bar/b.csv".to_string(),
+                version_id: None,
+                member_path: None
+            }
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_files_names_versions() -> Result<()> {
+        let ctx = SessionContext::new();
+        let schema = Schema::new(vec![
+            Field::new("file_path", DataType::Utf8View, true),
+            Field::new("version_id", DataType::Utf8View, true),
+            Field::new("is_delete_marker", DataType::Boolean, true),
+        ]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(StringViewArray::from(vec!["a.csv", "a.csv", "b.csv"])),
+                Arc::new(StringViewArray::from(vec![Some("v2"), Some("v1"), None])),
+                Arc::new(BooleanArray::from(vec![Some(true), Some(false), None])),
+            ],
+        )?;
+        let df = ctx.read_batch(batch)?;
+        let res = get_files_names(df).await?;
+        assert_eq!(
+            res[0],
+            FileRef {
+                bucket: None,
+                key: "a.csv".to_string(),
+                version_id: Some("v1".to_string()),
+                member_path: None
+            }
+        );
+        assert_eq!(
+            res[1],
+            FileRef {
+                bucket: None,
+                key: "b.csv".to_string(),
+                version_id: None,
+                member_path: None
+            }
+        );
+        assert_eq!(res.len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_files_names_members() -> Result<()> {
+        let ctx = SessionContext::new();
+        let schema = Schema::new(vec![
+            Field::new("file_path", DataType::Utf8View, true),
+            Field::new("member_path", DataType::Utf8View, true),
+        ]);
+        let batch = RecordBatch::try_new(
+            Arc::new(schema),
+            vec![
+                Arc::new(StringViewArray::from(vec!["a.zip", "a.zip"])),
+                Arc::new(StringViewArray::from(vec![Some("scans/a.dcm"), None])),
+            ],
+        )?;
+        let df = ctx.read_batch(batch)?;
+        let res = get_files_names(df).await?;
+        assert_eq!(res[0].member_path.as_deref(), Some("scans/a.dcm"));
+        assert_eq!(res[1].member_path, None);
         Ok(())
     }
 }
@@ -1,3 +1,4 @@
+pub mod archive;
 pub mod aws;
 pub mod constants;
 pub mod datafusion;
@@ -1,49 +1,137 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use crate::utils::aws::read_file;
+use crate::utils::archive::extract_members;
+use crate::utils::aws::{read_file, S3Object};
 use crate::utils::constants::*;
+use crate::utils::datafusion::FileRef;
 use crate::WorkerError;
 
 use async_zip::base::write::ZipFileWriter;
 use async_zip::{Compression, ZipEntryBuilder};
 use aws_sdk_s3::Client;
+use bytes::Bytes;
 use futures::AsyncWriteExt;
 use tokio::sync::{mpsc, Semaphore};
 use tokio::task::JoinSet;
 
+/// object read once, whole and with the members requested from it if it is an archive
+#[derive(Debug, PartialEq)]
+struct Download {
+    bucket: Option<String>,
+    key: String,
+    version_id: Option<String>,
+    whole: bool,
+    members: Vec<String>,
+}
+
+/// groups the rows of archive members by their archive, in order of the first row
+fn downloads(files: Vec<FileRef>) -> Vec<Download> {
+    let mut downloads: Vec<Download> = vec![];
+    let mut index = HashMap::new();
+    for FileRef {
+        bucket,
+        key,
+        version_id,
+        member_path,
+    } in files
+    {
+        let i = *index
+            .entry((bucket.clone(), key.clone(), version_id.clone()))
+            .or_insert_with(|| {
+                downloads.push(Download {
+                    bucket,
+                    key,
+                    version_id,
+                    whole: false,
+                    members: vec![],
+                });
+                downloads.len() - 1
+            });
+        match member_path {
+            Some(member) => downloads[i].members.push(member),
+            None => downloads[i].whole = true,
+        }
+    }
+    downloads
+}
+
 #[tracing::instrument(level = "info", name = "processor", skip(client, other))]
 pub async fn process(
     client: Arc<Client>,
     bucket: String,
-    keys: Vec<String>,
+    files: Vec<FileRef>,
     other: Vec<u8>,
     request_id: String,
 ) -> Result<Vec<u8>, WorkerError> {
     tracing::info!("start reading and zipping files");
     let mut zip_writer = ZipFileWriter::new(vec![]);
-    let (tx, mut rx) = mpsc::channel::<(String, Vec<u8>)>(MAX_ASYNC_WORKERS * 10);
+    let (tx, mut rx) = mpsc::channel::<(String, Bytes)>(MAX_ASYNC_WORKERS * 10);
     let sem = Arc::new(Semaphore::new(MAX_ASYNC_WORKERS));
     let mut tasks = JoinSet::new();
 
-    for key in keys {
+    for Download {
+        bucket: file_bucket,
+        key,
+        version_id,
+        whole,
+        members,
+    } in downloads(files)
+    {
         let permit = Arc::clone(&sem)
             .acquire_owned()
             .await
             .map_err(|e| WorkerError::UnexpectedError(e.into()))?;
         let tx = tx.clone();
         let client = client.clone();
-        let bucket = bucket.clone();
+        let bucket = file_bucket.unwrap_or_else(|| bucket.clone());
         let file_name = key.rsplit('/').next().unwrap_or_default().to_string();
+        // versions of the same file get their own directory
+        let file_name = match &version_id {
+            Some(version_id) => format!("{version_id}/{file_name}"),
+            None => file_name,
+        };
 
         tasks.spawn(async move {
             let _permit = permit;
-            match read_file(client, bucket, key.clone()).await {
-                Ok(bytes) => {
-                    if let Err(e) = tx.send((file_name.clone(), bytes)).await {
-                        tracing::error!("Failed to send file: {key} to zip task: {e:?}");
+            let object = S3Object {
+                client,
+                bucket,
+                key: key.clone(),
+                version_id,
+            };
+            // archives only read for their members are not downloaded whole
+            let data = if whole {
+                let (client, bucket) = (object.client.clone(), object.bucket.clone());
+                match read_file(client, bucket, key.clone(), object.version_id.clone()).await {
+                    Ok(bytes) => Some(Bytes::from(bytes)),
+                    Err(e) => {
+                        tracing::error!("Failed to read file: {key}: {e:?}");
+                        return;
                     }
                 }
-                Err(e) => tracing::error!("Failed to read file: {key}: {e:?}"),
+            } else {
+                None
+            };
+            // members are stored under a directory named after the archive
+            let mut entries = vec![];
+            if !members.is_empty() {
+                match extract_members(&object, data.clone(), &members).await {
+                    Ok(found) => entries.extend(
+                        found
+                            .into_iter()
+                            .map(|(path, data)| (format!("{file_name}/{path}"), data)),
+                    ),
+                    Err(e) => tracing::error!("Failed to extract members of: {key}: {e:?}"),
+                }
+            }
+            if let Some(data) = data {
+                entries.push((file_name, data));
+            }
+            for (name, data) in entries {
+                if let Err(e) = tx.send((name, data)).await {
+                    tracing::error!("Failed to send file: {key} to zip task: {e:?}");
+                }
             }
         });
     }
@@ -77,3 +165,32 @@ pub async fn process(
     let buffer = zip_writer.close().await?;
     Ok(buffer)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_ref(key: &str, member_path: Option<&str>) -> FileRef {
+        FileRef {
+            bucket: None,
+            key: key.to_string(),
+            version_id: None,
+            member_path: member_path.map(|x| x.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_downloads() {
+        let files = vec![
+            file_ref("a.zip", Some("x.csv")),
+            file_ref("b.csv", None),
+            file_ref("a.zip", Some("y.csv")),
+        ];
+        let res = downloads(files);
+        assert_eq!(res.len(), 2);
+        assert_eq!((res[0].key.as_str(), res[0].whole), ("a.zip", false));
+        assert_eq!(res[0].members, vec!["x.csv", "y.csv"]);
+        assert_eq!((res[1].key.as_str(), res[1].whole), ("b.csv", true));
+        assert!(res[1].members.is_empty());
+    }
+}
@@ -50,8 +50,8 @@ class ObjectStoreClient:
                 res = self._send_request(url)
                 if res is None:
                     return
-                columns = ["file_name", "file_type", "file_size", "file_path", "file_url", "dt"]
-                filtered = [{k: row[k] for k in columns} for row in res]
+                columns = ["file_name", "file_type", "file_size", "file_path", "file_url", "last_modified"]
+                filtered = [{k: row.get(k) for k in columns} for row in res]
                 print(tabulate(filtered, headers="keys", tablefmt="grid"))
             case "download":
                 url = f"{URL}/download"
@@ -9,6 +9,7 @@ struct ObjectStoreTable {
     file_path: Option<String>,
     file_url: Option<String>,
     dt: Option<String>,
+    last_modified: Option<String>,
 }
 
 #[component]
@@ -26,7 +27,7 @@ pub fn SelectResult(result: ReadSignal<Option<serde_json::Value>>) -> impl IntoV
                             ("file_size", Box::new(|r| r.file_size.map(|v| v.to_string()))),
                             ("file_path", Box::new(|r| r.file_path.clone())),
                             ("file_url", Box::new(|r| r.file_url.clone())),
-                            ("dt", Box::new(|r| r.dt.clone())),
+                            ("last_modified", Box::new(|r| r.last_modified.clone().or_else(|| r.dt.clone()))),
                         ];
 
                         let active_columns: Vec<_> = columns
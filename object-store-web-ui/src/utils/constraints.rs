@@ -9,15 +9,16 @@ pub const QUERY_EXAMPLES: &[(&str, &str)] = &[
     select * from object_store 
     where file_name = 'foo' 
     limit 10"#),
-    ("Files from 2021 (by prefix)", 
+    ("Files from 2021 (by year)", 
     r#"
     select * from object_store 
-    where dt like '2021%' 
+    where year = 2021 
     limit 10"#),
     ("Files from 2021 (by date range)", 
     r#"
     select * from object_store 
-    where cast(dt AS date) between '2021-01-01' and '2021-12-01' 
+    where last_modified >= '2021-01-01T00:00:00Z' 
+    and last_modified < '2021-12-01T00:00:00Z' 
     limit 10"#),
     ("Filter by file_type", 
     r#"
@@ -32,7 +33,7 @@ pub const QUERY_EXAMPLES: &[(&str, &str)] = &[
     ("Biggest foo files from 2022", 
     r#"
     select * from object_store 
-    where dt like '2022%' 
+    where year = 2022 
     and file_type = 'foo'
     order by file_size desc 
     limit 10"#),